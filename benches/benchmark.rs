@@ -1,4 +1,5 @@
 use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
+use rethink_about_amp::workload::{LatencySummary, Workload, WorkloadConfig};
 use rethink_about_amp::*;
 use std::time::Duration;
 
@@ -47,6 +48,8 @@ fn create_benchmark_data() -> Vec<OriginalAmp> {
                     i
                 ),
                 icon_id: format!("icon_{}", i % 50),
+                start: None,
+                end: None,
             });
         }
         synthetic_data
@@ -63,24 +66,35 @@ fn build_benchmark(c: &mut Criterion) {
 
     group.bench_function("hybrid", |b| {
         b.iter(|| {
-            let mut index = HybridAmpIndex::new();
-            index.build(black_box(&amp_data)).unwrap();
+            let index = HybridAmpIndex::build(black_box(&amp_data)).unwrap();
             black_box(index)
         })
     });
 
     group.bench_function("btree", |b| {
         b.iter(|| {
-            let mut index = BTreeAmpIndex::new();
-            index.build(black_box(&amp_data)).unwrap();
+            let index = BTreeAmpIndex::build(black_box(&amp_data)).unwrap();
             black_box(index)
         })
     });
 
     group.bench_function("art", |b| {
         b.iter(|| {
-            let mut index = BlartAmpIndex::new();
-            index.build(black_box(&amp_data)).unwrap();
+            let index = BlartAmpIndex::build(black_box(&amp_data)).unwrap();
+            black_box(index)
+        })
+    });
+
+    group.bench_function("sorted_array", |b| {
+        b.iter(|| {
+            let index = SortedArrayAmpIndex::build(black_box(&amp_data)).unwrap();
+            black_box(index)
+        })
+    });
+
+    group.bench_function("prefix_table", |b| {
+        b.iter(|| {
+            let index = PrefixTableAmpIndex::build(black_box(&amp_data)).unwrap();
             black_box(index)
         })
     });
@@ -93,14 +107,15 @@ fn query_benchmark(c: &mut Criterion) {
     let amp_data = create_benchmark_data();
 
     // Pre-build all indexes
-    let mut hybrid_index = HybridAmpIndex::new();
-    hybrid_index.build(&amp_data).unwrap();
+    let hybrid_index = HybridAmpIndex::build(&amp_data).unwrap();
 
-    let mut btree_index = BTreeAmpIndex::new();
-    btree_index.build(&amp_data).unwrap();
+    let btree_index = BTreeAmpIndex::build(&amp_data).unwrap();
 
-    let mut art_index = BlartAmpIndex::new();
-    art_index.build(&amp_data).unwrap();
+    let art_index = BlartAmpIndex::build(&amp_data).unwrap();
+
+    let sorted_array_index = SortedArrayAmpIndex::build(&amp_data).unwrap();
+
+    let prefix_table_index = PrefixTableAmpIndex::build(&amp_data).unwrap();
 
     // Test different query patterns
     let test_queries = vec![
@@ -110,9 +125,6 @@ fn query_benchmark(c: &mut Criterion) {
         ("medium_word", "amaz"),
         ("full_word", "amazon"),
         ("long_phrase", "amazon fresh"),
-        ("synthetic_short", "kw"),
-        ("synthetic_medium", "keyword"),
-        ("synthetic_long", "keyword_123"),
     ];
 
     for (query_type, query) in test_queries {
@@ -131,6 +143,14 @@ fn query_benchmark(c: &mut Criterion) {
             b.iter(|| black_box(art_index.query(black_box(q)).unwrap()))
         });
 
+        group.bench_with_input(BenchmarkId::new("sorted_array", query), query, |b, q| {
+            b.iter(|| black_box(sorted_array_index.query(black_box(q)).unwrap()))
+        });
+
+        group.bench_with_input(BenchmarkId::new("prefix_table", query), query, |b, q| {
+            b.iter(|| black_box(prefix_table_index.query(black_box(q)).unwrap()))
+        });
+
         group.finish();
     }
 }
@@ -142,19 +162,28 @@ fn memory_analysis_benchmark(c: &mut Criterion) {
     c.bench_function("memory_analysis", |b| {
         b.iter(|| {
             // Build each index and measure approximate memory usage
-            let mut hybrid_index = HybridAmpIndex::new();
-            hybrid_index.build(black_box(&amp_data)).unwrap();
+            let hybrid_index = HybridAmpIndex::build(black_box(&amp_data)).unwrap();
             let hybrid_stats = hybrid_index.stats();
 
-            let mut btree_index = BTreeAmpIndex::new();
-            btree_index.build(black_box(&amp_data)).unwrap();
+            let btree_index = BTreeAmpIndex::build(black_box(&amp_data)).unwrap();
             let btree_stats = btree_index.stats();
 
-            let mut art_index = BlartAmpIndex::new();
-            art_index.build(black_box(&amp_data)).unwrap();
+            let art_index = BlartAmpIndex::build(black_box(&amp_data)).unwrap();
             let art_stats = art_index.stats();
 
-            black_box((hybrid_stats, btree_stats, art_stats))
+            let sorted_array_index = SortedArrayAmpIndex::build(black_box(&amp_data)).unwrap();
+            let sorted_array_stats = sorted_array_index.stats();
+
+            let prefix_table_index = PrefixTableAmpIndex::build(black_box(&amp_data)).unwrap();
+            let prefix_table_stats = prefix_table_index.stats();
+
+            black_box((
+                hybrid_stats,
+                btree_stats,
+                art_stats,
+                sorted_array_stats,
+                prefix_table_stats,
+            ))
         })
     });
 }
@@ -163,16 +192,15 @@ fn memory_analysis_benchmark(c: &mut Criterion) {
 fn prefix_iteration_benchmark(c: &mut Criterion) {
     let amp_data = create_benchmark_data();
 
-    let mut hybrid_index = HybridAmpIndex::new();
-    hybrid_index.build(&amp_data).unwrap();
+    let hybrid_index = HybridAmpIndex::build(&amp_data).unwrap();
 
-    let mut btree_index = BTreeAmpIndex::new();
-    btree_index.build(&amp_data).unwrap();
+    let btree_index = BTreeAmpIndex::build(&amp_data).unwrap();
 
-    let mut art_index = BlartAmpIndex::new();
-    art_index.build(&amp_data).unwrap();
+    let art_index = BlartAmpIndex::build(&amp_data).unwrap();
 
-    let prefix_queries = vec!["a", "am", "k", "kw", "keyword"];
+    let sorted_array_index = SortedArrayAmpIndex::build(&amp_data).unwrap();
+
+    let prefix_queries = vec!["a", "am", "amazon", "wa", "wayfair"];
 
     for prefix in prefix_queries {
         let mut group = c.benchmark_group(format!("prefix_iter/{}", prefix));
@@ -198,15 +226,171 @@ fn prefix_iteration_benchmark(c: &mut Criterion) {
             })
         });
 
+        group.bench_with_input(BenchmarkId::new("sorted_array", prefix), prefix, |b, p| {
+            b.iter(|| {
+                let results = sorted_array_index.query(black_box(p)).unwrap();
+                black_box(results.len())
+            })
+        });
+
         group.finish();
     }
 }
 
+// Every typeable prefix of every keyword, answered or not, against every backend: the
+// point lookups of the answer table vs. the range scans of the trees
+fn all_prefixes_benchmark(c: &mut Criterion) {
+    let amp_data = create_benchmark_data();
+    let registry = BackendRegistry::with_defaults();
+
+    let keys = BTreeAmpIndex::build(&amp_data).unwrap();
+    let mut prefixes: Vec<String> = keys
+        .iter_prefix("")
+        .flat_map(|(key, _, _)| {
+            key.char_indices()
+                .skip(1)
+                .map(|(i, _)| key[..i].to_string())
+                .chain(std::iter::once(key.clone()))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    prefixes.sort();
+    prefixes.dedup();
+
+    let mut group = c.benchmark_group("all_prefixes");
+    group.measurement_time(Duration::from_secs(15));
+    group.sample_size(10);
+    group.throughput(criterion::Throughput::Elements(prefixes.len() as u64));
+
+    for name in registry.names() {
+        let index = registry
+            .build(name, &amp_data, &BuildOptions::default())
+            .unwrap();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut answered = 0;
+                for prefix in &prefixes {
+                    answered += index.query(black_box(prefix)).unwrap().len();
+                }
+                black_box(answered)
+            })
+        });
+    }
+
+    group.finish();
+}
+
+// Realistic typing sessions mixed with misses, against every backend. Criterion reports
+// the throughput; per-query p50/p99 latencies are printed before each backend is benched.
+fn workload_benchmark(c: &mut Criterion) {
+    let amp_data = create_benchmark_data();
+    let registry = BackendRegistry::with_defaults();
+    let workload = Workload::generate(&amp_data, &WorkloadConfig::default());
+
+    let mut group = c.benchmark_group("workload");
+    group.measurement_time(Duration::from_secs(15));
+    group.sample_size(10);
+    group.throughput(criterion::Throughput::Elements(workload.len() as u64));
+
+    for name in registry.names() {
+        let index = registry
+            .build(name, &amp_data, &BuildOptions::default())
+            .unwrap();
+
+        let latency = LatencySummary::measure(&workload.queries, |query| {
+            black_box(index.query(black_box(query)).unwrap());
+        });
+        println!(
+            "workload/{}: p50 {:?}, p99 {:?}, max {:?}, {:.0} queries/s ({} queries, {} misses)",
+            name,
+            latency.p50,
+            latency.p99,
+            latency.max,
+            latency.throughput,
+            workload.len(),
+            workload.misses
+        );
+
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut answered = 0;
+                for query in &workload.queries {
+                    answered += index.query(black_box(query)).unwrap().len();
+                }
+                black_box(answered)
+            })
+        });
+    }
+
+    group.finish();
+}
+
+// Cost of decoding suggestions out of the columnar table, as paid once per answered query
+// (`result`) and once per scanned key (`block_id`, `rejection`)
+fn suggestion_decode_benchmark(c: &mut Criterion) {
+    use rethink_about_amp::suggestions::SuggestionTableBuilder;
+
+    let amp_data = create_benchmark_data();
+    let mut pool = StringPool::new();
+    let mut builder = SuggestionTableBuilder::new();
+    for amp in &amp_data {
+        builder.push(amp, &mut pool);
+    }
+    let table = builder.finish();
+    let mut builder = SuggestionTableBuilder::new().compression(StringCompression::Fsst);
+    for amp in &amp_data {
+        builder.push(amp, &mut pool);
+    }
+    let compressed = builder.finish();
+    let params = UrlParams::default();
+    let ctx = QueryContext::new().block_id(-1).block_advertiser("Nobody");
+
+    let mut group = c.benchmark_group("suggestion_decode");
+    group.throughput(criterion::Throughput::Elements(table.len() as u64));
+
+    group.bench_function("result", |b| {
+        b.iter(|| {
+            for idx in 0..table.len() {
+                black_box(table.result(black_box(idx), String::new(), &params));
+            }
+        })
+    });
+
+    group.bench_function("result_fsst", |b| {
+        b.iter(|| {
+            for idx in 0..compressed.len() {
+                black_box(compressed.result(black_box(idx), String::new(), &params));
+            }
+        })
+    });
+
+    group.bench_function("block_id", |b| {
+        b.iter(|| {
+            for idx in 0..table.len() {
+                black_box(table.block_id(black_box(idx)));
+            }
+        })
+    });
+
+    group.bench_function("rejection", |b| {
+        b.iter(|| {
+            for idx in 0..table.len() {
+                black_box(table.rejection(black_box(idx), &ctx));
+            }
+        })
+    });
+
+    group.finish();
+}
+
 criterion_group!(
     benches,
     build_benchmark,
     query_benchmark,
     memory_analysis_benchmark,
-    prefix_iteration_benchmark
+    prefix_iteration_benchmark,
+    all_prefixes_benchmark,
+    workload_benchmark,
+    suggestion_decode_benchmark
 );
 criterion_main!(benches);
@@ -0,0 +1,53 @@
+//! Load arbitrary bytes as an AMP payload, both at once and streamed, and build and query
+//! every backend from whatever parses.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rethink_about_amp::stream::stream_amp_records;
+use rethink_about_amp::{AmpIndexer, BTreeAmpIndex, BlartAmpIndex, HybridAmpIndex, OriginalAmp};
+
+fn build_and_query<T: AmpIndexer>(amps: &[OriginalAmp]) {
+    let Ok(index) = T::build(amps) else {
+        return;
+    };
+    index.stats();
+    for amp in amps {
+        for keyword in &amp.keywords {
+            let _ = index.query(keyword);
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let loaded: Result<Vec<OriginalAmp>, _> = serde_json::from_slice(data);
+
+    let mut streamed = Vec::new();
+    let count = stream_amp_records(data, |amp| {
+        streamed.push(amp);
+        Ok(())
+    });
+
+    // Both loaders accept the same payloads, with the same records
+    match (&loaded, count) {
+        (Ok(amps), Ok(count)) => {
+            assert_eq!(amps.len(), count);
+            assert_eq!(
+                amps.iter().map(|a| a.block_id).collect::<Vec<_>>(),
+                streamed.iter().map(|a| a.block_id).collect::<Vec<_>>()
+            );
+        }
+        (Err(_), Err(_)) => {}
+        (loaded, count) => panic!(
+            "Loaders disagree: {:?} vs {:?}",
+            loaded.as_ref().map(Vec::len).map_err(|e| e.to_string()),
+            count.map_err(|e| e.to_string())
+        ),
+    }
+
+    if let Ok(amps) = loaded {
+        build_and_query::<BTreeAmpIndex>(&amps);
+        build_and_query::<BlartAmpIndex>(&amps);
+        build_and_query::<HybridAmpIndex>(&amps);
+    }
+});
@@ -0,0 +1,41 @@
+//! Query every registered backend, built from the US/desktop payload, with arbitrary input:
+//! none of them may fail or panic, and they must all agree.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rethink_about_amp::{AmpResult, BackendRegistry, BuildOptions, DynAmpIndex, OriginalAmp};
+use std::sync::LazyLock;
+
+/// `(backend name, index)`, for every backend of the default registry
+static INDEXES: LazyLock<Vec<(String, DynAmpIndex)>> = LazyLock::new(|| {
+    let data = include_str!("../../data/amp-us-desktop.json");
+    let amps: Vec<OriginalAmp> = serde_json::from_str(data).unwrap();
+    let registry = BackendRegistry::with_defaults();
+    registry
+        .names()
+        .into_iter()
+        .map(|name| {
+            let index = registry
+                .build(name, &amps, &BuildOptions::default())
+                .unwrap();
+            (name.to_string(), index)
+        })
+        .collect()
+});
+
+fuzz_target!(|query: &str| {
+    let answer = |results: Vec<AmpResult>| {
+        results
+            .into_iter()
+            .map(|r| (r.block_id, r.full_keyword))
+            .collect::<Vec<_>>()
+    };
+
+    let (reference_name, reference) = &INDEXES[0];
+    let expected = answer(reference.query(query).unwrap());
+    for (name, index) in &INDEXES[1..] {
+        let actual = answer(index.query(query).unwrap());
+        assert_eq!(actual, expected, "{} and {} on {:?}", name, reference_name, query);
+    }
+});
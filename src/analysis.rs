@@ -0,0 +1,444 @@
+//! Redundancy report of an AMP payload: how many bytes each field takes and how much each
+//! compression idea of the README would save on it.
+//!
+//! Sizes are estimates of the encoded data only, not of the structures that would hold it:
+//! dictionary ids and run lengths are counted at the bit width they need, strings at their
+//! UTF-8 length.
+
+use crate::common::{FullKeyword, OriginalAmp, collapse_intervals, collapse_keywords_ex};
+use crate::fsst::SymbolTable;
+use crate::suggestions::split_url;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+/// Number of advertisers listed in a report
+pub const TOP_ADVERTISERS: usize = 10;
+
+/// Bytes a field would take with one encoding
+#[derive(Clone, Debug, PartialEq)]
+pub struct Estimate {
+    pub encoding: &'static str,
+    pub bytes: usize,
+}
+
+/// How one field of the suggestions could be stored
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldReport {
+    pub field: &'static str,
+    /// Number of values, e.g. one per keyword for the keywords
+    pub values: usize,
+    pub raw_bytes: usize,
+    pub distinct: usize,
+    /// Estimates for every encoding that applies, the plain values first
+    pub estimates: Vec<Estimate>,
+}
+
+impl FieldReport {
+    /// The encoding taking the fewest bytes
+    pub fn best(&self) -> &Estimate {
+        self.estimates
+            .iter()
+            .min_by_key(|estimate| estimate.bytes)
+            .expect("the plain encoding always applies")
+    }
+}
+
+/// Collapsed keyword chains, as built by `collapse_keywords_ex`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChainStats {
+    pub keywords: usize,
+    pub chains: usize,
+    /// Chains standing for a single keyword
+    pub singletons: usize,
+    pub longest: usize,
+    /// Collapsed keys that are their own full keyword
+    pub implicit_full_keywords: usize,
+    /// Intervals and exclusions of the same keywords as collapsed by `collapse_intervals`
+    pub intervals: usize,
+    pub exclusions: usize,
+}
+
+impl ChainStats {
+    pub fn mean_length(&self) -> f64 {
+        self.keywords as f64 / self.chains.max(1) as f64
+    }
+}
+
+/// How often a URL shares its template with an earlier one
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemplateStats {
+    pub field: &'static str,
+    pub urls: usize,
+    pub templates: usize,
+}
+
+impl TemplateStats {
+    /// Share of the URLs whose template was already in the dictionary
+    pub fn hit_rate(&self) -> f64 {
+        self.urls.saturating_sub(self.templates) as f64 / self.urls.max(1) as f64
+    }
+}
+
+/// Keyword count of an advertiser
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdvertiserKeywords {
+    pub advertiser: String,
+    pub suggestions: usize,
+    pub keywords: usize,
+}
+
+/// Everything `analyze` finds in a payload
+#[derive(Clone, Debug, PartialEq)]
+pub struct RedundancyReport {
+    pub suggestions: usize,
+    pub fields: Vec<FieldReport>,
+    pub chains: ChainStats,
+    pub templates: Vec<TemplateStats>,
+    /// Advertisers with the most keywords, most first
+    pub top_advertisers: Vec<AdvertiserKeywords>,
+}
+
+/// Analyze the redundancy of every field of `amps`
+pub fn analyze(amps: &[OriginalAmp]) -> RedundancyReport {
+    // Suggestions grouped by advertiser, as the "Group Suggestions by Advertisers" idea
+    // would store them
+    let mut grouped: Vec<&OriginalAmp> = amps.iter().collect();
+    grouped.sort_by(|a, b| a.advertiser.cmp(&b.advertiser));
+
+    let mut fields = vec![keywords_field(amps), full_keywords_field(amps)];
+    for (field, get) in [
+        ("title", (|amp| &amp.title) as fn(&OriginalAmp) -> &String),
+        ("advertiser", |amp| &amp.advertiser),
+        ("iab_category", |amp| &amp.iab_category),
+        ("icon", |amp| &amp.icon_id),
+    ] {
+        let values: Vec<&str> = amps.iter().map(|amp| get(amp).as_str()).collect();
+        let in_groups: Vec<&str> = grouped.iter().map(|amp| get(amp).as_str()).collect();
+        let mut report = string_field(field, &values);
+        report.estimates.push(Estimate {
+            encoding: "grouped by advertiser, run-length",
+            bytes: run_length_bytes(&in_groups),
+        });
+        fields.push(report);
+    }
+    let mut templates = Vec::new();
+    for (field, get) in [
+        ("url", (|amp| &amp.url) as fn(&OriginalAmp) -> &String),
+        ("click_url", |amp| &amp.click_url),
+        ("impression_url", |amp| &amp.impression_url),
+    ] {
+        let values: Vec<&str> = amps.iter().map(|amp| get(amp).as_str()).collect();
+        let (template_bytes, template_stats) = url_template_bytes(field, &values);
+        let mut report = string_field(field, &values);
+        report.estimates.push(Estimate {
+            encoding: "URL templates",
+            bytes: template_bytes,
+        });
+        fields.push(report);
+        templates.push(template_stats);
+    }
+
+    RedundancyReport {
+        suggestions: amps.len(),
+        fields,
+        chains: chain_stats(amps),
+        templates,
+        top_advertisers: top_advertisers(amps),
+    }
+}
+
+/// Bytes taken by `n` ids into a dictionary of `distinct` values
+fn id_bytes(n: usize, distinct: usize) -> usize {
+    let width = usize::BITS - distinct.saturating_sub(1).leading_zeros();
+    (n * width as usize).div_ceil(8)
+}
+
+fn distinct_bytes(values: &[&str]) -> (usize, usize) {
+    let distinct: HashSet<&str> = values.iter().copied().collect();
+    (
+        distinct.len(),
+        distinct.iter().map(|value| value.len()).sum(),
+    )
+}
+
+/// Raw, dictionary and FSST estimates of a string field
+fn string_field(field: &'static str, values: &[&str]) -> FieldReport {
+    let raw_bytes = values.iter().map(|value| value.len()).sum();
+    let (distinct, dictionary_bytes) = distinct_bytes(values);
+    FieldReport {
+        field,
+        values: values.len(),
+        raw_bytes,
+        distinct,
+        estimates: vec![
+            Estimate {
+                encoding: "plain",
+                bytes: raw_bytes,
+            },
+            Estimate {
+                encoding: "dictionary",
+                bytes: dictionary_bytes + id_bytes(values.len(), distinct),
+            },
+            Estimate {
+                encoding: "FSST",
+                bytes: fsst_bytes(values),
+            },
+        ],
+    }
+}
+
+/// Bytes of the values compressed one by one with a symbol table trained on them
+fn fsst_bytes(values: &[&str]) -> usize {
+    let symbols = SymbolTable::train(values);
+    let mut buffer = Vec::new();
+    for value in values {
+        symbols.compress(value, &mut buffer);
+    }
+    symbols.size_bytes() + buffer.len()
+}
+
+/// Dictionary values, then a dictionary id and a run length per run of equal values
+fn run_length_bytes(values: &[&str]) -> usize {
+    let (distinct, dictionary_bytes) = distinct_bytes(values);
+    let runs = values.chunk_by(|a, b| a == b).count();
+    let longest = values
+        .chunk_by(|a, b| a == b)
+        .map(<[_]>::len)
+        .max()
+        .unwrap_or(0);
+    dictionary_bytes + id_bytes(runs, distinct) + id_bytes(runs, longest + 1)
+}
+
+/// Bytes of the URLs as a dictionary of templates, a template id and a suffix each
+fn url_template_bytes(field: &'static str, urls: &[&str]) -> (usize, TemplateStats) {
+    let templates: Vec<&str> = urls.iter().map(|url| split_url(url).0).collect();
+    let (distinct, dictionary_bytes) = distinct_bytes(&templates);
+    let suffix_bytes: usize = urls.iter().map(|url| split_url(url).1.len()).sum();
+    let stats = TemplateStats {
+        field,
+        urls: urls.len(),
+        templates: distinct,
+    };
+    (
+        dictionary_bytes + suffix_bytes + id_bytes(urls.len(), distinct),
+        stats,
+    )
+}
+
+/// Keywords stored one by one, as collapsed chains and as collapsed intervals
+fn keywords_field(amps: &[OriginalAmp]) -> FieldReport {
+    let keywords: Vec<&str> = amps
+        .iter()
+        .flat_map(|amp| amp.keywords.iter().map(String::as_str))
+        .collect();
+    let raw_bytes = keywords.iter().map(|kw| kw.len()).sum();
+    let (distinct, _) = distinct_bytes(&keywords);
+
+    // A collapsed key takes its bytes and a byte for its min prefix length, an exclusion a
+    // byte for its length
+    let (mut chain_bytes, mut interval_bytes) = (0, 0);
+    for amp in amps {
+        chain_bytes += collapse_keywords_ex(&amp.keywords, &amp.full_keywords)
+            .iter()
+            .map(|(key, _, _)| key.len() + 1)
+            .sum::<usize>();
+        interval_bytes += collapse_intervals(&amp.keywords, &amp.full_keywords)
+            .intervals
+            .iter()
+            .map(|interval| interval.key.len() + 1 + interval.excluded.len())
+            .sum::<usize>();
+    }
+
+    FieldReport {
+        field: "keywords",
+        values: keywords.len(),
+        raw_bytes,
+        distinct,
+        estimates: vec![
+            Estimate {
+                encoding: "plain",
+                bytes: raw_bytes,
+            },
+            Estimate {
+                encoding: "collapsed chains",
+                bytes: chain_bytes,
+            },
+            Estimate {
+                encoding: "collapsed intervals",
+                bytes: interval_bytes,
+            },
+        ],
+    }
+}
+
+/// Full keywords stored once per keyword, run-length encoded as in the payload, and only
+/// where they differ from the collapsed key
+fn full_keywords_field(amps: &[OriginalAmp]) -> FieldReport {
+    let pointwise: Vec<&str> = amps
+        .iter()
+        .flat_map(|amp| {
+            amp.full_keywords
+                .iter()
+                .flat_map(|(fk, count)| std::iter::repeat_n(fk.as_str(), *count))
+        })
+        .collect();
+    let raw_bytes = pointwise.iter().map(|fk| fk.len()).sum();
+    let (distinct, _) = distinct_bytes(&pointwise);
+
+    let runs: Vec<&(String, usize)> = amps.iter().flat_map(|amp| &amp.full_keywords).collect();
+    let longest = runs.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    let rle_bytes =
+        runs.iter().map(|(fk, _)| fk.len()).sum::<usize>() + id_bytes(runs.len(), longest + 1);
+
+    // Distinct stored strings, and a reference per collapsed key: 0 when the key is its
+    // own full keyword
+    let mut stored: HashSet<String> = HashSet::new();
+    let mut keys = 0;
+    for amp in amps {
+        for (_, _, full_keyword) in collapse_keywords_ex(&amp.keywords, &amp.full_keywords) {
+            keys += 1;
+            if let FullKeyword::Different(fk) = full_keyword {
+                stored.insert(fk);
+            }
+        }
+    }
+    let dedup_bytes =
+        stored.iter().map(String::len).sum::<usize>() + id_bytes(keys, stored.len() + 1);
+
+    FieldReport {
+        field: "full_keywords",
+        values: pointwise.len(),
+        raw_bytes,
+        distinct,
+        estimates: vec![
+            Estimate {
+                encoding: "plain",
+                bytes: raw_bytes,
+            },
+            Estimate {
+                encoding: "run-length",
+                bytes: rle_bytes,
+            },
+            Estimate {
+                encoding: "deduplicated against collapsed keys",
+                bytes: dedup_bytes,
+            },
+        ],
+    }
+}
+
+fn chain_stats(amps: &[OriginalAmp]) -> ChainStats {
+    let mut stats = ChainStats::default();
+    for amp in amps {
+        for (key, min_pref, full_keyword) in collapse_keywords_ex(&amp.keywords, &amp.full_keywords)
+        {
+            let length = key.chars().count() + 1 - min_pref;
+            stats.keywords += length;
+            stats.chains += 1;
+            stats.singletons += usize::from(length == 1);
+            stats.longest = stats.longest.max(length);
+            stats.implicit_full_keywords += usize::from(matches!(full_keyword, FullKeyword::Same));
+        }
+        let intervals = collapse_intervals(&amp.keywords, &amp.full_keywords);
+        stats.intervals += intervals.intervals.len();
+        stats.exclusions += intervals.exclusions();
+    }
+    stats
+}
+
+fn top_advertisers(amps: &[OriginalAmp]) -> Vec<AdvertiserKeywords> {
+    let mut by_advertiser: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for amp in amps {
+        let (suggestions, keywords) = by_advertiser.entry(&amp.advertiser).or_default();
+        *suggestions += 1;
+        *keywords += amp.keywords.len();
+    }
+    let mut advertisers: Vec<AdvertiserKeywords> = by_advertiser
+        .into_iter()
+        .map(|(advertiser, (suggestions, keywords))| AdvertiserKeywords {
+            advertiser: advertiser.to_string(),
+            suggestions,
+            keywords,
+        })
+        .collect();
+    // Stable, so ties stay in alphabetical order
+    advertisers.sort_by_key(|a| std::cmp::Reverse(a.keywords));
+    advertisers.truncate(TOP_ADVERTISERS);
+    advertisers
+}
+
+impl fmt::Display for RedundancyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Suggestions: {}", self.suggestions)?;
+
+        writeln!(f, "\nFields")?;
+        for field in &self.fields {
+            let best = field.best();
+            writeln!(
+                f,
+                "  {}: {} values, {} distinct, {} bytes raw; best: {} ({} bytes, {:.1}%)",
+                field.field,
+                field.values,
+                field.distinct,
+                field.raw_bytes,
+                best.encoding,
+                best.bytes,
+                100.0 * best.bytes as f64 / field.raw_bytes.max(1) as f64
+            )?;
+            for estimate in &field.estimates {
+                writeln!(
+                    f,
+                    "    {:<36} {:>8} bytes",
+                    estimate.encoding, estimate.bytes
+                )?;
+            }
+        }
+
+        let chains = &self.chains;
+        writeln!(f, "\nKeyword chains")?;
+        writeln!(
+            f,
+            "  {} keywords in {} chains: {:.2} keywords per chain, {} singletons, longest {}",
+            chains.keywords,
+            chains.chains,
+            chains.mean_length(),
+            chains.singletons,
+            chains.longest
+        )?;
+        writeln!(
+            f,
+            "  {} collapsed keys are their own full keyword ({:.1}%)",
+            chains.implicit_full_keywords,
+            100.0 * chains.implicit_full_keywords as f64 / chains.chains.max(1) as f64
+        )?;
+        writeln!(
+            f,
+            "  As intervals: {} intervals + {} exclusions, {:.2}x fewer entries than keywords",
+            chains.intervals,
+            chains.exclusions,
+            chains.keywords as f64 / (chains.intervals + chains.exclusions).max(1) as f64
+        )?;
+
+        writeln!(f, "\nURL templates")?;
+        for stats in &self.templates {
+            writeln!(
+                f,
+                "  {}: {} templates for {} URLs, {:.1}% hit rate",
+                stats.field,
+                stats.templates,
+                stats.urls,
+                100.0 * stats.hit_rate()
+            )?;
+        }
+
+        writeln!(f, "\nAdvertisers with the most keywords")?;
+        for advertiser in &self.top_advertisers {
+            writeln!(
+                f,
+                "  {}: {} keywords in {} suggestions",
+                advertiser.advertiser, advertiser.keywords, advertiser.suggestions
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+//! Compare two AMP payloads, e.g. the deployed one and a new candidate.
+//!
+//! Usage: amp_diff [--json] [--sample N] [--prefix P]... <old.json> <new.json>
+
+use rethink_about_amp::diff::{DiffOptions, diff_payloads};
+use rethink_about_amp::load_amp_data;
+use std::process::exit;
+
+fn usage() -> ! {
+    eprintln!("Usage: amp_diff [--json] [--sample N] [--prefix P]... <old.json> <new.json>");
+    exit(2);
+}
+
+fn main() {
+    let mut json = false;
+    let mut options = DiffOptions::default();
+    let mut prefixes = Vec::new();
+    let mut paths = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--sample" => {
+                options.sample_size = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage());
+            }
+            "--prefix" => prefixes.push(args.next().unwrap_or_else(|| usage())),
+            "-h" | "--help" => usage(),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() != 2 {
+        usage();
+    }
+    if !prefixes.is_empty() {
+        options.prefixes = Some(prefixes);
+    }
+
+    let load = |path: &str| {
+        load_amp_data(path).unwrap_or_else(|e| {
+            eprintln!("Failed to load {}: {}", path, e);
+            exit(1);
+        })
+    };
+    let old = load(&paths[0]);
+    let new = load(&paths[1]);
+    let diff = diff_payloads(&old, &new, &options).unwrap_or_else(|e| {
+        eprintln!("Failed to diff the payloads: {}", e);
+        exit(1);
+    });
+
+    if json {
+        let json = diff.to_json().unwrap_or_else(|e| {
+            eprintln!("Failed to serialize the diff: {}", e);
+            exit(1);
+        });
+        println!("{}", json);
+    } else {
+        print!("{}", diff);
+    }
+}
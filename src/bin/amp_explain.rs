@@ -0,0 +1,51 @@
+//! Explain how an index answers queries: the collapsed keys it scanned, why they were
+//! rejected, the structure that served the query and how the full keyword was resolved.
+//!
+//! Usage: amp_explain [--data PATH] [--backend NAME] [--json] <query>...
+
+use rethink_about_amp::registry::DEFAULT_BACKEND;
+use rethink_about_amp::{AmpQuery, BackendRegistry, BuildOptions, load_amp_data};
+use std::process::exit;
+
+fn usage() -> ! {
+    eprintln!("Usage: amp_explain [--data PATH] [--backend NAME] [--json] <query>...");
+    exit(2);
+}
+
+fn main() {
+    let mut data = "data/amp-us-desktop.json".to_string();
+    let mut backend = DEFAULT_BACKEND.to_string();
+    let mut json = false;
+    let mut queries = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--data" => data = args.next().unwrap_or_else(|| usage()),
+            "--backend" => backend = args.next().unwrap_or_else(|| usage()),
+            "--json" => json = true,
+            "-h" | "--help" => usage(),
+            _ => queries.push(arg),
+        }
+    }
+    if queries.is_empty() {
+        usage();
+    }
+
+    let amps = load_amp_data(&data).unwrap();
+    let index = BackendRegistry::with_defaults()
+        .build(&backend, &amps, &BuildOptions::default())
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            exit(2);
+        });
+
+    for query in &queries {
+        let explanation = index.explain(query).unwrap();
+        if json {
+            println!("{}", serde_json::to_string(&explanation).unwrap());
+        } else {
+            println!("{}", explanation);
+        }
+    }
+}
@@ -0,0 +1,361 @@
+//! Serve AMP suggestions over HTTP, with responses shaped like Merino's.
+//!
+//! Usage: amp-serve [--data PATH] [--backend NAME] [--addr HOST:PORT] [--threads N]
+//!                  [--now TIMESTAMP]
+//!
+//! Endpoints:
+//!
+//! - `GET /api/v1/suggest?q=...`: sponsored suggestions matching the query. The
+//!   `request_id`, `region` and `form_factor` parameters fill the URL placeholders.
+//! - `GET /api/v1/explain?q=...`: how the index answers the query
+//! - `GET /api/v1/schedule`: upcoming starts and ends of campaigns
+//! - `GET /__heartbeat__`, `GET /__lbheartbeat__`: health checks
+//! - `GET /stats`: statistics of the loaded index
+//! - `GET /metrics`: query metrics in the Prometheus text format, reset on reload
+//!
+//! Suggestions outside their time window are not served. `--now` pins the clock to a Unix
+//! timestamp, e.g. to preview a schedule.
+//!
+//! Sending SIGHUP reloads the index from the data file. The running index keeps
+//! serving until the new one is built; if the reload fails, it stays in place.
+
+use rethink_about_amp::registry::DEFAULT_BACKEND;
+use rethink_about_amp::schedule::ManualClock;
+use rethink_about_amp::{
+    AmpQuery, AmpResult, BackendRegistry, BuildOptions, Clock, DynAmpIndex, InstrumentedIndex,
+    QueryContext, Schedule, SystemClock, UrlParam, UrlParams, load_amp_data,
+};
+use serde_json::{Value, json};
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+use std::error::Error;
+use std::io::Write;
+use std::process::exit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Merino reports AMP suggestions under this provider name
+const PROVIDER: &str = "adm";
+
+struct Config {
+    data: String,
+    backend: String,
+    addr: String,
+    threads: usize,
+    now: Option<i64>,
+}
+
+/// The index being served along with the schedule of its data
+struct Served {
+    index: InstrumentedIndex<DynAmpIndex>,
+    schedule: Schedule,
+}
+
+/// The index being served, swapped as a whole on reload
+struct State {
+    config: Config,
+    registry: BackendRegistry,
+    clock: Box<dyn Clock>,
+    served: RwLock<Arc<Served>>,
+    reloads: AtomicUsize,
+}
+
+fn load_index(config: &Config, registry: &BackendRegistry) -> Result<Served, Box<dyn Error>> {
+    let amps = load_amp_data(&config.data)?;
+    let index = registry.build(&config.backend, &amps, &BuildOptions::default())?;
+    Ok(Served {
+        index: InstrumentedIndex::for_amps(index, &amps),
+        schedule: Schedule::new(&amps),
+    })
+}
+
+impl State {
+    fn served(&self) -> Arc<Served> {
+        self.served.read().unwrap().clone()
+    }
+
+    /// Filter of the current request, hiding the suggestions out of their time window
+    fn context(&self) -> QueryContext {
+        QueryContext::new().at(self.clock.now())
+    }
+
+    fn reload(&self) -> Result<(), Box<dyn Error>> {
+        let served = load_index(&self.config, &self.registry)?;
+        *self.served.write().unwrap() = Arc::new(served);
+        self.reloads.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: amp-serve [--data PATH] [--backend NAME] [--addr HOST:PORT] [--threads N] \
+         [--now TIMESTAMP]"
+    );
+    exit(2);
+}
+
+fn parse_args() -> Config {
+    let mut config = Config {
+        data: "data/amp-us-desktop.json".to_string(),
+        backend: DEFAULT_BACKEND.to_string(),
+        addr: "127.0.0.1:8000".to_string(),
+        threads: 4,
+        now: None,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--data" => config.data = value(),
+            "--backend" => config.backend = value(),
+            "--addr" => config.addr = value(),
+            "--threads" => {
+                config.threads = value()
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage());
+            }
+            "--now" => config.now = Some(value().parse().unwrap_or_else(|_| usage())),
+            _ => usage(),
+        }
+    }
+    config
+}
+
+/// Decode a `application/x-www-form-urlencoded` value. Every `%` must be followed by two
+/// hex digits.
+fn decode_component(s: &str) -> Result<String, String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                    .ok_or_else(|| format!("Invalid percent-encoding in '{}'", s))?;
+                // Two hex digits always fit a byte
+                let hex = std::str::from_utf8(hex).unwrap();
+                decoded.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 2;
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    Ok(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+/// Value of the query parameter `name` in a request URL
+fn query_param(url: &str, name: &str) -> Result<Option<String>, String> {
+    let Some((_, query)) = url.split_once('?') else {
+        return Ok(None);
+    };
+    for pair in query.split('&') {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        if decode_component(key)? == name {
+            return decode_component(value).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// The required query parameter `q` of a request URL
+fn query(url: &str) -> Result<String, String> {
+    query_param(url, "q")?.ok_or_else(|| "Missing query parameter 'q'".to_string())
+}
+
+/// Values of the URL placeholders passed as query parameters of the same name
+fn url_params(url: &str) -> Result<UrlParams, String> {
+    let mut params = UrlParams::default();
+    for param in UrlParam::ALL {
+        if let Some(value) = query_param(url, param.name())? {
+            params.set(param.name(), &value)?;
+        }
+    }
+    Ok(params)
+}
+
+fn suggestion(result: &AmpResult) -> Value {
+    json!({
+        "title": result.title,
+        "url": result.url,
+        "click_url": result.click_url,
+        "impression_url": result.impression_url,
+        "advertiser": result.advertiser,
+        "block_id": result.block_id,
+        "iab_category": result.iab_category,
+        "icon": result.icon,
+        "full_keyword": result.full_keyword,
+        "is_sponsored": true,
+        "provider": PROVIDER,
+    })
+}
+
+fn json_response(status: u16, body: &Value) -> Response<std::io::Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+fn error_response(status: u16, message: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    json_response(status, &json!({ "error": message }))
+}
+
+fn handle(state: &State, request: &Request) -> Response<std::io::Cursor<Vec<u8>>> {
+    if request.method() != &Method::Get {
+        return error_response(405, "Method not allowed");
+    }
+    let url = request.url();
+    let path = url.split_once('?').map_or(url, |(path, _)| path);
+
+    match path {
+        "/api/v1/suggest" => {
+            let q = match query(url) {
+                Ok(q) => q,
+                Err(e) => return error_response(400, &e),
+            };
+            let url_params = match url_params(url) {
+                Ok(params) => params,
+                Err(e) => return error_response(400, &e),
+            };
+            let request_id = url_params.request_id.clone();
+            let ctx = state.context().with_url_params(url_params);
+            match state.served().index.query_with_context(&q, &ctx) {
+                Ok(results) => json_response(
+                    200,
+                    &json!({
+                        "suggestions": results.iter().map(suggestion).collect::<Vec<_>>(),
+                        "client_variants": [],
+                        "server_variants": [],
+                        "request_id": request_id,
+                    }),
+                ),
+                Err(e) => error_response(500, &e.to_string()),
+            }
+        }
+        "/api/v1/explain" => {
+            let q = match query(url) {
+                Ok(q) => q,
+                Err(e) => return error_response(400, &e),
+            };
+            match state
+                .served()
+                .index
+                .explain_with_context(&q, &state.context())
+            {
+                Ok(explanation) => json_response(200, &json!(explanation)),
+                Err(e) => error_response(500, &e.to_string()),
+            }
+        }
+        "/api/v1/schedule" => {
+            let now = state.clock.now();
+            json_response(
+                200,
+                &json!({
+                    "now": now,
+                    "upcoming": state.served().schedule.upcoming(now),
+                }),
+            )
+        }
+        "/__heartbeat__" | "/__lbheartbeat__" => json_response(200, &json!({ "status": "ok" })),
+        "/stats" => {
+            let mut stats = serde_json::Map::new();
+            for (name, value) in state.served().index.stats() {
+                stats.insert(name, value.into());
+            }
+            json_response(
+                200,
+                &json!({
+                    "backend": state.config.backend,
+                    "reloads": state.reloads.load(Ordering::Relaxed),
+                    "index": stats,
+                }),
+            )
+        }
+        "/metrics" => {
+            let content_type =
+                Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+            Response::from_string(state.served().index.snapshot().to_prometheus("amp"))
+                .with_header(content_type)
+        }
+        _ => error_response(404, "Not found"),
+    }
+}
+
+fn serve(state: &State, server: &Server) {
+    while let Ok(request) = server.recv() {
+        let response = handle(state, &request);
+        if let Err(e) = request.respond(response) {
+            eprintln!("Failed to respond: {}", e);
+        }
+    }
+}
+
+fn main() {
+    let config = parse_args();
+    let registry = BackendRegistry::with_defaults();
+    if !registry.contains(&config.backend) {
+        eprintln!(
+            "Unknown backend '{}', expected one of: {}",
+            config.backend,
+            registry.names().join(", ")
+        );
+        exit(2);
+    }
+
+    let served = load_index(&config, &registry).unwrap_or_else(|e| {
+        eprintln!("Failed to load {}: {}", config.data, e);
+        exit(1);
+    });
+    let server = Server::http(&config.addr).unwrap_or_else(|e| {
+        eprintln!("Failed to listen on {}: {}", config.addr, e);
+        exit(1);
+    });
+    let threads = config.threads;
+    let clock: Box<dyn Clock> = match config.now {
+        Some(now) => Box::new(ManualClock::new(now)),
+        None => Box::new(SystemClock),
+    };
+    let state = Arc::new(State {
+        config,
+        registry,
+        clock,
+        served: RwLock::new(Arc::new(served)),
+        reloads: AtomicUsize::new(0),
+    });
+
+    let mut signals = Signals::new([SIGHUP]).expect("Failed to register SIGHUP handler");
+    let reloader = Arc::clone(&state);
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            match reloader.reload() {
+                Ok(()) => println!("Reloaded {}", reloader.config.data),
+                Err(e) => eprintln!("Failed to reload {}: {}", reloader.config.data, e),
+            }
+        }
+    });
+
+    // Tests read the bound address from this line when listening on port 0
+    println!("Listening on http://{}", server.server_addr());
+    std::io::stdout().flush().unwrap();
+
+    let server = Arc::new(server);
+    let workers: Vec<_> = (0..threads)
+        .map(|_| {
+            let state = Arc::clone(&state);
+            let server = Arc::clone(&server);
+            std::thread::spawn(move || serve(&state, &server))
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
@@ -1,100 +1,226 @@
-use jemalloc_ctl::{epoch, stats};
+//! Measure the memory, build time and query time of the backends over AMP datasets, as
+//! JSON or CSV. With a baseline report, exit non-zero when a backend's memory grew by more
+//! than the threshold.
+//!
+//! Usage: memory_comparison [--format json|csv] [--backend NAME]... [--fsst]
+//!                          [--baseline REPORT.json] [--threshold PCT] [DATASET]...
+
 use jemallocator::Jemalloc;
+use rethink_about_amp::memory_report::{Measurement, MemoryReport, split_stats};
 use rethink_about_amp::*;
-use std::time::Instant;
+use std::alloc::{GlobalAlloc, Layout};
+use std::process::exit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Jemalloc wrapper that tracks the high-water mark of live allocated bytes
+struct PeakAlloc {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl PeakAlloc {
+    /// Reset the peak to the current allocation and return it
+    fn reset_peak(&self) -> usize {
+        let current = self.current.load(Ordering::SeqCst);
+        self.peak.store(current, Ordering::SeqCst);
+        current
+    }
+
+    fn peak(&self) -> usize {
+        self.peak.load(Ordering::SeqCst)
+    }
+
+    fn grow(&self, size: usize) {
+        let current = self.current.fetch_add(size, Ordering::SeqCst) + size;
+        self.peak.fetch_max(current, Ordering::SeqCst);
+    }
+}
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { Jemalloc.alloc(layout) };
+        if !ptr.is_null() {
+            self.grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { Jemalloc.dealloc(ptr, layout) };
+        self.current.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { Jemalloc.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            self.current.fetch_sub(layout.size(), Ordering::SeqCst);
+            self.grow(new_size);
+        }
+        new_ptr
+    }
+}
 
 #[global_allocator]
-static GLOBAL: Jemalloc = Jemalloc;
-
-fn measure_memory<F, T>(name: &str, build_fn: F) -> T
-where
-    F: FnOnce() -> T,
-{
-    // Force garbage collection by dropping a large allocation
-    drop(Vec::<u8>::with_capacity(1024 * 1024));
-
-    // Update stats
-    epoch::advance().unwrap();
-    let start_allocated = stats::allocated::read().unwrap();
+static GLOBAL: PeakAlloc = PeakAlloc {
+    current: AtomicUsize::new(0),
+    peak: AtomicUsize::new(0),
+};
+
+const DEFAULT_DATASET: &str = "data/amp-us-desktop.json";
+
+/// Growth in percent tolerated over the baseline
+const DEFAULT_THRESHOLD_PCT: f64 = 5.0;
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: memory_comparison [--format json|csv] [--backend NAME]... [--fsst]\n\
+         \x20                        [--baseline REPORT.json] [--threshold PCT] [DATASET]..."
+    );
+    exit(2);
+}
+
+/// Run `build_fn` and return its result, the bytes it left allocated, the peak bytes it
+/// allocated and how long it took. Bytes are the requested sizes, without the size-class
+/// rounding and thread caches that make jemalloc's own count jitter between runs.
+fn measure_memory<T>(build_fn: impl FnOnce() -> T) -> (T, usize, usize, Duration) {
+    let start_live = GLOBAL.reset_peak();
     let start_time = Instant::now();
 
-    // Run the build function
     let result = build_fn();
 
-    // Measure after
     let build_time = start_time.elapsed();
-    epoch::advance().unwrap();
-    let end_allocated = stats::allocated::read().unwrap();
-
-    println!(
-        "{} memory: {} bytes (built in: {:?})",
-        name,
-        end_allocated - start_allocated,
-        build_time
-    );
+    let peak = GLOBAL.peak() - start_live;
+    let live = GLOBAL
+        .current
+        .load(Ordering::SeqCst)
+        .saturating_sub(start_live);
 
     // Return the result so the structure stays alive until we're done measuring
-    result
+    (result, live, peak, build_time)
 }
 
-fn main() {
-    // Load data once
-    println!("Loading AMP data...");
-    let amps = load_amp_data("data/amp-us-desktop.json").unwrap();
-    println!("Loaded {} AMP suggestions", amps.len());
-
-    // Measure all structures one by one
-    // This prevents interference between measurements
-
-    // 1. BTreeMap
-    {
-        let index = measure_memory("BTreeMap", || {
-            let mut index = BTreeAmpIndex::new();
-            index.build(&amps).unwrap();
-            index
-        });
-
-        // Print stats and test a query
-        println!("BTree stats: {:?}", index.stats());
-        let results = index.query("amaz").unwrap();
-        println!("BTree query 'amaz' returned {} results", results.len());
+/// Build `backend` over `amps` and measure it
+fn measure(
+    registry: &BackendRegistry,
+    dataset: &str,
+    backend: &str,
+    amps: &[OriginalAmp],
+    queries: &[&str],
+    options: &BuildOptions,
+) -> Measurement {
+    let (index, allocated_bytes, peak_bytes, build_time) =
+        measure_memory(|| registry.build(backend, amps, options).unwrap());
+
+    let start = Instant::now();
+    for query in queries {
+        std::hint::black_box(index.query(query).unwrap());
     }
-
-    // Add a pause between measurements
-    std::thread::sleep(std::time::Duration::from_secs(1));
-    println!("\n---------------------------------------\n");
-
-    // 2. Blart
-    {
-        let index = measure_memory("Blart", || {
-            let mut index = BlartAmpIndex::new();
-            index.build(&amps).unwrap();
-            index
-        });
-
-        println!("Blart stats: {:?}", index.stats());
-        let results = index.query("amaz").unwrap();
-        println!("Blart query 'amaz' returned {} results", results.len());
+    let ns_per_query = start.elapsed().as_nanos() as f64 / queries.len().max(1) as f64;
+    let (components, counts) = split_stats(index.stats());
+
+    Measurement {
+        dataset: dataset.to_string(),
+        backend: backend.to_string(),
+        string_compression: options.string_compression,
+        allocated_bytes,
+        peak_bytes,
+        build_ms: build_time.as_secs_f64() * 1000.0,
+        ns_per_query,
+        key_count: index.iter_prefix("").count(),
+        components,
+        counts,
     }
+}
 
-    std::thread::sleep(std::time::Duration::from_secs(1));
-    println!("\n---------------------------------------\n");
+fn main() {
+    let registry = BackendRegistry::with_defaults();
+    let mut csv = false;
+    let mut backends = Vec::new();
+    let mut compressions = vec![StringCompression::None];
+    let mut baseline = None;
+    let mut threshold = DEFAULT_THRESHOLD_PCT;
+    let mut datasets = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => match args.next().as_deref() {
+                Some("json") => csv = false,
+                Some("csv") => csv = true,
+                _ => usage(),
+            },
+            "--backend" => backends.push(args.next().unwrap_or_else(|| usage())),
+            "--fsst" => compressions.push(StringCompression::Fsst),
+            "--baseline" => baseline = Some(args.next().unwrap_or_else(|| usage())),
+            "--threshold" => {
+                threshold = args
+                    .next()
+                    .and_then(|pct| pct.parse().ok())
+                    .unwrap_or_else(|| usage());
+            }
+            "-h" | "--help" => usage(),
+            _ => datasets.push(arg),
+        }
+    }
+    if datasets.is_empty() {
+        datasets.push(DEFAULT_DATASET.to_string());
+    }
+    if backends.is_empty() {
+        backends = registry.names().into_iter().map(String::from).collect();
+    }
+    for backend in &backends {
+        if !registry.names().contains(&backend.as_str()) {
+            eprintln!(
+                "Unknown backend '{}', expected one of: {}",
+                backend,
+                registry.names().join(", ")
+            );
+            exit(2);
+        }
+    }
 
-    // 3. Hybrid
-    {
-        let index = measure_memory("Hybrid", || {
-            let mut index = HybridAmpIndex::new();
-            index.build(&amps).unwrap();
-            index
-        });
+    let mut report = MemoryReport::default();
+    for dataset in &datasets {
+        let amps = load_amp_data(dataset).unwrap();
+        let mut queries: Vec<&str> = amps
+            .iter()
+            .flat_map(|amp| amp.keywords.iter().map(String::as_str))
+            .collect();
+        queries.sort_unstable();
+        queries.dedup();
+
+        for backend in &backends {
+            for &string_compression in &compressions {
+                let options = BuildOptions {
+                    string_compression,
+                    ..Default::default()
+                };
+                eprintln!(
+                    "Measuring {} ({:?}) on {}",
+                    backend, string_compression, dataset
+                );
+                report.measurements.push(measure(
+                    &registry, dataset, backend, &amps, &queries, &options,
+                ));
+            }
+        }
+    }
 
-        println!("Hybrid stats: {:?}", index.stats());
-        let results = index.query("amaz").unwrap();
-        println!("Hybrid query 'amaz' returned {} results", results.len());
+    if csv {
+        print!("{}", report.to_csv());
+    } else {
+        println!("{}", report.to_json().unwrap());
     }
 
-    // Print a summary at the end
-    println!("\n=========== Memory Usage Summary ===========");
-    println!("Note: These measurements include all data structures,");
-    println!("      not just the keyword index part.");
+    if let Some(path) = baseline {
+        let baseline = MemoryReport::from_json(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let regressions = report.regressions(&baseline, threshold);
+        for regression in &regressions {
+            eprintln!("Memory regression: {}", regression);
+        }
+        if !regressions.is_empty() {
+            exit(1);
+        }
+    }
 }
@@ -0,0 +1,176 @@
+//! Succinct building blocks: a bit vector with rank/select support and an array of
+//! bit-packed integers.
+
+/// An append-only bit vector. Call `finish` once all bits are pushed to build the rank
+/// directory used by `rank1`, `select0` and `select1`.
+#[derive(Clone, Debug, Default)]
+pub struct BitVec {
+    words: Vec<u64>,
+    len: usize,
+    /// Number of ones before each word
+    ranks: Vec<u32>,
+}
+
+impl BitVec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, bit: bool) {
+        if self.len.is_multiple_of(64) {
+            self.words.push(0);
+        }
+        if bit {
+            self.words[self.len / 64] |= 1 << (self.len % 64);
+        }
+        self.len += 1;
+    }
+
+    /// Build the rank directory; bits can't be pushed afterwards
+    pub fn finish(&mut self) {
+        self.words.shrink_to_fit();
+        let mut ones = 0;
+        self.ranks = self
+            .words
+            .iter()
+            .map(|word| {
+                let before = ones;
+                ones += word.count_ones();
+                before
+            })
+            .collect();
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    /// Number of ones in `[0, i)`
+    pub fn rank1(&self, i: usize) -> usize {
+        let (word, bit) = (i / 64, i % 64);
+        if word == self.words.len() {
+            return self.count_ones();
+        }
+        let below = self.words[word] & ((1 << bit) - 1);
+        self.ranks[word] as usize + below.count_ones() as usize
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.ranks.last().map_or(0, |&r| r as usize)
+            + self.words.last().map_or(0, |w| w.count_ones() as usize)
+    }
+
+    /// Position of the `k`-th one, 0-based
+    pub fn select1(&self, k: usize) -> Option<usize> {
+        self.select(k, |word| self.ranks[word] as usize, |w| w)
+    }
+
+    /// Position of the `k`-th zero, 0-based
+    pub fn select0(&self, k: usize) -> Option<usize> {
+        self.select(k, |word| word * 64 - self.ranks[word] as usize, |w| !w)
+    }
+
+    fn select(
+        &self,
+        k: usize,
+        before: impl Fn(usize) -> usize,
+        bits: impl Fn(u64) -> u64,
+    ) -> Option<usize> {
+        // Binary search the last word with at most `k` matching bits before it
+        let (mut lo, mut hi) = (0, self.words.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if before(mid) <= k {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        let word = lo.checked_sub(1)?;
+        let mut x = bits(self.words[word]);
+        let skip = k - before(word);
+        if skip >= x.count_ones() as usize {
+            return None;
+        }
+        for _ in 0..skip {
+            x &= x - 1;
+        }
+        let pos = word * 64 + x.trailing_zeros() as usize;
+        (pos < self.len).then_some(pos)
+    }
+
+    /// Heap bytes held
+    pub fn size_bytes(&self) -> usize {
+        self.words.capacity() * 8 + self.ranks.capacity() * 4
+    }
+}
+
+/// Unsigned integers packed with the width of the largest one
+#[derive(Clone, Debug, Default)]
+pub struct PackedInts {
+    words: Vec<u64>,
+    width: u32,
+    len: usize,
+}
+
+impl PackedInts {
+    pub fn from_values(values: &[u64]) -> Self {
+        let max = values.iter().copied().max().unwrap_or(0);
+        let width = u64::BITS - max.leading_zeros();
+        let mut words = vec![0u64; (values.len() * width as usize).div_ceil(64)];
+        // All zeros take no space
+        for (i, &value) in values.iter().enumerate().filter(|_| width > 0) {
+            let bit = i * width as usize;
+            let (word, offset) = (bit / 64, bit % 64);
+            words[word] |= value << offset;
+            if offset + width as usize > 64 {
+                words[word + 1] |= value >> (64 - offset);
+            }
+        }
+        PackedInts {
+            words,
+            width,
+            len: values.len(),
+        }
+    }
+
+    pub fn get(&self, i: usize) -> u64 {
+        assert!(i < self.len, "index {} out of bounds ({})", i, self.len);
+        if self.width == 0 {
+            return 0;
+        }
+        let bit = i * self.width as usize;
+        let (word, offset) = (bit / 64, bit % 64);
+        let mut value = self.words[word] >> offset;
+        if offset + self.width as usize > 64 {
+            value |= self.words[word + 1] << (64 - offset);
+        }
+        value & (u64::MAX >> (64 - self.width))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Bits per value
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Heap bytes held
+    pub fn size_bytes(&self) -> usize {
+        self.words.capacity() * 8
+    }
+}
@@ -1,136 +1,81 @@
 use crate::common::{
-    AmpIndexer, AmpResult, FullKeyword, OriginalAmp, collapse_keywords_ex, extract_template,
+    AmpIndexBuilder, AmpIndexer, AmpQuery, AmpResult, BuildOptions, IntervalSink, KeywordEntry,
+    KeywordInterval, OriginalAmp, PoolRef, QueryContext, QueryPath, StringPool, add_intervals,
+    expand_keyword,
 };
+use crate::explain::{Explanation, FullKeywordResolution, Verdict};
+use crate::full_keywords::{FullKeywordRef, FullKeywords, FullKeywordsBuilder};
+use crate::infix::InfixIndex;
+use crate::suggestions::{SuggestionTable, SuggestionTableBuilder};
+use crate::url_params::UrlParams;
 use blart::TreeMap;
 use std::collections::HashMap;
+use std::ffi::CString;
 
 /// Stores the metadata for each collapsed keyword
 #[derive(Clone)]
 struct KeywordMetadata {
     suggestion_idx: usize,
     min_prefix_len: usize,
-    full_keyword: FullKeyword,
+    /// Char lengths of the prefixes the key's interval excludes, ascending
+    excluded: Box<[usize]>,
+    full_keyword: FullKeywordRef,
     collapsed_keyword: String,
 }
 
-/// Same compact suggestion structure as our other implementations
-#[derive(Clone)]
-struct CompactSuggestion {
-    title_id: u32,
-    url_tid: u32,
-    url_suffix: String,
-    click_tid: u32,
-    click_suffix: String,
-    imp_tid: u32,
-    imp_suffix: String,
-    advertiser_id: u32,
-    block_id: i32,
-    iab_id: u32,
-    icon_id: u32,
+impl KeywordMetadata {
+    /// Whether the interval of the key excludes its prefix of `len` chars
+    fn excludes(&self, len: usize) -> bool {
+        self.excluded.binary_search(&len).is_ok()
+    }
 }
 
 /// AMP Index using BLART (Adaptive Radix Tree)
 pub struct BlartAmpIndex {
-    /// BLART handles all the complex tree operations for us
-    keyword_tree: TreeMap<Box<[u8]>, KeywordMetadata>,
+    /// BLART handles all the complex tree operations for us.
+    ///
+    /// Keys are NUL-terminated so that no key is a prefix of another, which ART requires
+    /// (e.g. "canon " and "canon dslr cameras" are both collapsed keys).
+    keyword_tree: TreeMap<CString, KeywordMetadata>,
+
+    full_keywords: FullKeywords,
 
     /// Storage for suggestions
-    suggestions: Vec<CompactSuggestion>,
-
-    /// Dictionary structures - identical to other implementations
-    advertisers: HashMap<u32, String>,
-    titles: HashMap<u32, String>,
-    url_templates: HashMap<u32, String>,
-    click_templates: HashMap<u32, String>,
-    imp_templates: HashMap<u32, String>,
-    iab_categories: HashMap<u32, String>,
-    icons: HashMap<u32, String>,
+    suggestions: SuggestionTable,
+
+    /// Word-boundary suffixes of the full keywords, when enabled
+    infix: Option<InfixIndex>,
 }
 
 impl AmpIndexer for BlartAmpIndex {
-    fn new() -> Self {
-        BlartAmpIndex {
-            keyword_tree: TreeMap::new(),
-            suggestions: Vec::new(),
-            advertisers: HashMap::new(),
-            titles: HashMap::new(),
-            url_templates: HashMap::new(),
-            click_templates: HashMap::new(),
-            imp_templates: HashMap::new(),
-            iab_categories: HashMap::new(),
-            icons: HashMap::new(),
-        }
-    }
-
-    fn build(&mut self, amps: &[OriginalAmp]) -> Result<(), Box<dyn std::error::Error>> {
-        // Dictionary lookups - same pattern as other implementations
-        let mut adv_lookup = HashMap::new();
-        let mut title_lookup = HashMap::new();
-        let mut url_lookup = HashMap::new();
-        let mut click_lookup = HashMap::new();
-        let mut imp_lookup = HashMap::new();
-        let mut iab_lookup = HashMap::new();
-        let mut icon_lookup = HashMap::new();
-
-        for amp in amps {
-            // Dictionary encode all fields
-            let advertiser_id =
-                Self::intern(&amp.advertiser, &mut adv_lookup, &mut self.advertisers);
-            let title_id = Self::intern(&amp.title, &mut title_lookup, &mut self.titles);
-            let iab_id = Self::intern(&amp.iab_category, &mut iab_lookup, &mut self.iab_categories);
-            let icon_id = Self::intern(&amp.icon_id, &mut icon_lookup, &mut self.icons);
-
-            // Extract URL templates
-            let (url_tid, url_suf) =
-                extract_template(&amp.url, &mut url_lookup, &mut self.url_templates);
-            let (click_tid, clk_suf) =
-                extract_template(&amp.click_url, &mut click_lookup, &mut self.click_templates);
-            let (imp_tid, imp_suf) = extract_template(
-                &amp.impression_url,
-                &mut imp_lookup,
-                &mut self.imp_templates,
-            );
-
-            // Store suggestion
-            let sidx = self.suggestions.len();
-            self.suggestions.push(CompactSuggestion {
-                title_id,
-                url_tid,
-                url_suffix: url_suf,
-                click_tid,
-                click_suffix: clk_suf,
-                imp_tid,
-                imp_suffix: imp_suf,
-                advertiser_id,
-                block_id: amp.block_id,
-                iab_id,
-                icon_id,
-            });
+    type Builder<'p> = BlartIndexBuilder<'p>;
 
-            // Process and insert collapsed keywords
-            for (kw, min_pref, full_kw) in collapse_keywords_ex(&amp.keywords, &amp.full_keywords) {
-                let metadata = KeywordMetadata {
-                    suggestion_idx: sidx,
-                    min_prefix_len: min_pref,
-                    full_keyword: full_kw,
-                    collapsed_keyword: kw.clone(),
-                };
-
-                // BLART requires Box<[u8]> for keys
-                let key = kw.into_bytes().into_boxed_slice();
-
-                // BLART returns the old value if key already exists
-                // We'll keep the first occurrence (like other implementations)
-                let _ = self.keyword_tree.try_insert(key, metadata);
-            }
-        }
+    fn builder(options: &BuildOptions) -> Self::Builder<'static> {
+        BlartIndexBuilder::new(PoolRef::Owned(StringPool::new()), options)
+    }
 
-        self.suggestions.shrink_to_fit();
+    fn builder_with_pool<'p>(
+        pool: &'p mut StringPool,
+        options: &BuildOptions,
+    ) -> Self::Builder<'p> {
+        BlartIndexBuilder::new(PoolRef::Borrowed(pool), options)
+    }
+}
 
-        Ok(())
+impl AmpQuery for BlartAmpIndex {
+    fn query_with_context(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<Vec<AmpResult>, Box<dyn std::error::Error>> {
+        Ok(self.query_with_path(query, ctx)?.0)
     }
 
-    fn query(&self, query: &str) -> Result<Vec<AmpResult>, Box<dyn std::error::Error>> {
+    fn query_with_path(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<(Vec<AmpResult>, QueryPath), Box<dyn std::error::Error>> {
         let mut results = Vec::new();
         let query_len = query.chars().count();
         let query_bytes = query.as_bytes();
@@ -138,126 +83,311 @@ impl AmpIndexer for BlartAmpIndex {
         // Find the shortest valid match using BLART's range iterator
         let mut best_match: Option<(&[u8], &KeywordMetadata)> = None;
 
-        // Convert query to Box<[u8]> for the range bound
-        let range_start = query_bytes.to_vec().into_boxed_slice();
+        // Keys can't contain NUL, so no key starts with a query containing one
+        let Ok(range_start) = CString::new(query) else {
+            return Ok((results, QueryPath::Tree));
+        };
 
-        // Use the boxed slice as the range start
+        // Use the C string as the range start
         for (key, metadata) in self.keyword_tree.range(range_start..) {
             // Check if this key actually starts with our query
-            if !key.starts_with(query_bytes) {
+            if !key.as_bytes().starts_with(query_bytes) {
                 break; // No more matches possible
             }
 
-            // Check minimum prefix length requirement
-            if query_len >= metadata.min_prefix_len {
+            // Check minimum prefix length requirement and the interval's exclusions, and
+            // skip filtered out suggestions
+            if query_len >= metadata.min_prefix_len
+                && !metadata.excludes(query_len)
+                && self.rejection(metadata.suggestion_idx, ctx).is_none()
+            {
                 // Take the first valid match (shortest due to tree ordering)
-                best_match = Some((key, metadata));
+                best_match = Some((key.as_bytes(), metadata));
                 break;
             }
         }
 
         // Build result if we found a match
         if let Some((_, metadata)) = best_match {
-            self.build_result(metadata, &mut results)?;
+            let full_keyword = self
+                .full_keywords
+                .resolve(metadata.full_keyword, &metadata.collapsed_keyword);
+            self.build_result(
+                metadata.suggestion_idx,
+                full_keyword,
+                &ctx.url_params,
+                &mut results,
+            )?;
+            return Ok((results, QueryPath::Tree));
         }
 
-        Ok(results)
+        // Fall back to the suffixes of full keywords
+        if let Some(infix) = &self.infix {
+            if let Some(m) = infix.find(query, |idx| self.rejection(idx, ctx)) {
+                self.build_result(
+                    m.suggestion_idx,
+                    m.full_keyword.to_string(),
+                    &ctx.url_params,
+                    &mut results,
+                )?;
+            }
+            return Ok((results, QueryPath::Infix));
+        }
+
+        Ok((results, QueryPath::Tree))
     }
 
     fn stats(&self) -> HashMap<String, usize> {
         let mut stats = HashMap::new();
 
         stats.insert("keyword_count".into(), self.keyword_tree.len());
-        stats.insert("suggestions_count".into(), self.suggestions.len());
-        stats.insert("advertisers_count".into(), self.advertisers.len());
-        stats.insert("titles_count".into(), self.titles.len());
-        stats.insert("url_templates_count".into(), self.url_templates.len());
-        stats.insert("iab_categories_count".into(), self.iab_categories.len());
-        stats.insert("icons_count".into(), self.icons.len());
+        stats.insert(
+            "keyword_exclusions".into(),
+            self.keyword_tree
+                .values()
+                .map(|metadata| metadata.excluded.len())
+                .sum(),
+        );
+        stats.insert("keyword_structure_bytes".into(), self.keyword_bytes());
+        let refs = self
+            .keyword_tree
+            .values()
+            .map(|metadata| metadata.full_keyword);
+        self.full_keywords.add_stats(refs, &mut stats);
+        self.suggestions.add_stats(&mut stats);
+        stats.insert(
+            "infix_entries".into(),
+            self.infix.as_ref().map_or(0, |infix| infix.len()),
+        );
+        stats.insert(
+            "infix_bytes".into(),
+            self.infix.as_ref().map_or(0, |infix| infix.size_bytes()),
+        );
 
         stats
     }
+
+    fn explain_with_context(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<Explanation, Box<dyn std::error::Error>> {
+        let mut explanation = Explanation::new(query, QueryPath::Tree);
+        let query_len = explanation.query_len;
+        let Ok(range_start) = CString::new(query) else {
+            return Ok(explanation);
+        };
+
+        // Same scan as `query_with_context`
+        for (key, metadata) in self.keyword_tree.range(range_start..) {
+            if !key.as_bytes().starts_with(query.as_bytes()) {
+                break;
+            }
+            let keyword = &metadata.collapsed_keyword;
+            let min_prefix_len = metadata.min_prefix_len;
+            let block_id = self.suggestions.block_id(metadata.suggestion_idx);
+            if query_len < min_prefix_len {
+                explanation.scanned(
+                    keyword,
+                    min_prefix_len,
+                    block_id,
+                    QueryPath::Tree,
+                    Verdict::TooShort,
+                );
+                continue;
+            }
+            if metadata.excludes(query_len) {
+                explanation.scanned(
+                    keyword,
+                    min_prefix_len,
+                    block_id,
+                    QueryPath::Tree,
+                    Verdict::Excluded,
+                );
+                continue;
+            }
+            if let Some(verdict) = self.rejection(metadata.suggestion_idx, ctx) {
+                explanation.scanned(keyword, min_prefix_len, block_id, QueryPath::Tree, verdict);
+                continue;
+            }
+            explanation.scanned(
+                keyword,
+                min_prefix_len,
+                block_id,
+                QueryPath::Tree,
+                Verdict::Selected,
+            );
+            explanation.full_keyword = Some(FullKeywordResolution::from_full_keyword(
+                &self.full_keywords.get(metadata.full_keyword),
+                keyword,
+            ));
+            return Ok(explanation);
+        }
+
+        if let Some(infix) = &self.infix {
+            explanation.path = QueryPath::Infix;
+            infix.explain(
+                &mut explanation,
+                query,
+                |idx| self.rejection(idx, ctx),
+                |idx| self.suggestions.block_id(idx),
+            );
+        }
+        Ok(explanation)
+    }
+
+    fn iter_prefix<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = KeywordEntry> + 'a> {
+        // Keys can't contain NUL, so neither can their prefixes
+        let Ok(range_start) = CString::new(prefix) else {
+            return Box::new(std::iter::empty());
+        };
+        let prefix = prefix.as_bytes().to_vec();
+        let entries = self
+            .keyword_tree
+            .range(range_start..)
+            .take_while(move |(key, _)| key.as_bytes().starts_with(&prefix))
+            .map(|(_, metadata)| {
+                (
+                    metadata.collapsed_keyword.clone(),
+                    metadata.min_prefix_len,
+                    self.suggestions.block_id(metadata.suggestion_idx),
+                )
+            });
+        Box::new(entries)
+    }
+
+    fn keywords_for(&self, block_id: i32) -> Vec<String> {
+        let mut keywords: Vec<String> = self
+            .keyword_tree
+            .values()
+            .filter(|metadata| self.suggestions.block_id(metadata.suggestion_idx) == block_id)
+            .flat_map(|metadata| {
+                expand_keyword(&metadata.collapsed_keyword, metadata.min_prefix_len)
+                    .into_iter()
+                    .zip(metadata.min_prefix_len..)
+                    .filter(|(_, len)| !metadata.excludes(*len))
+                    .map(|(keyword, _)| keyword)
+            })
+            .collect();
+        keywords.sort();
+        keywords
+    }
 }
 
-impl BlartAmpIndex {
-    /// Helper for string interning
-    fn intern(
-        value: &str,
-        lookup: &mut HashMap<String, u32>,
-        dict: &mut HashMap<u32, String>,
-    ) -> u32 {
-        if let Some(&id) = lookup.get(value) {
-            id
-        } else {
-            let id = lookup.len() as u32;
-            lookup.insert(value.to_string(), id);
-            dict.insert(id, value.to_string());
-            id
+/// Builds a `BlartAmpIndex` one record at a time
+pub struct BlartIndexBuilder<'p> {
+    index: BlartAmpIndex,
+    pool: PoolRef<'p>,
+    full_keywords: FullKeywordsBuilder,
+    suggestions: SuggestionTableBuilder,
+}
+
+impl<'p> BlartIndexBuilder<'p> {
+    fn new(pool: PoolRef<'p>, options: &BuildOptions) -> Self {
+        BlartIndexBuilder {
+            index: BlartAmpIndex::empty(options.infix_min_chars.map(InfixIndex::new)),
+            pool,
+            full_keywords: FullKeywordsBuilder::new(),
+            suggestions: SuggestionTableBuilder::new().compression(options.string_compression),
         }
     }
+}
 
-    /// Build result from metadata and dictionaries
-    fn build_result(
-        &self,
-        metadata: &KeywordMetadata,
-        results: &mut Vec<AmpResult>,
+impl AmpIndexBuilder for BlartIndexBuilder<'_> {
+    type Index = BlartAmpIndex;
+
+    fn add(&mut self, amp: &OriginalAmp) -> Result<(), Box<dyn std::error::Error>> {
+        let index = &mut self.index;
+        let pool = &mut *self.pool;
+
+        let sidx = self.suggestions.push(amp, pool);
+
+        add_intervals(index, &mut self.full_keywords, pool, sidx, amp)?;
+
+        if let Some(infix) = &mut index.infix {
+            infix.add_suggestion(sidx, amp, pool);
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<BlartAmpIndex, Box<dyn std::error::Error>> {
+        self.index.full_keywords = self.full_keywords.finish();
+        self.index.suggestions = self.suggestions.finish();
+        if let Some(infix) = &mut self.index.infix {
+            infix.finish();
+        }
+        Ok(self.index)
+    }
+}
+
+impl IntervalSink for BlartAmpIndex {
+    fn contains_key(&self, key: &str) -> bool {
+        CString::new(key).is_ok_and(|key| self.keyword_tree.contains_key(&key))
+    }
+
+    fn insert(
+        &mut self,
+        suggestion_idx: usize,
+        interval: KeywordInterval,
+        full_keyword: FullKeywordRef,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let sug = &self.suggestions[metadata.suggestion_idx];
-
-        // Reconstruct all fields from dictionaries
-        let title = self.titles.get(&sug.title_id).cloned().unwrap_or_default();
-        let advertiser = self
-            .advertisers
-            .get(&sug.advertiser_id)
-            .cloned()
-            .unwrap_or_default();
-        let iab_category = self
-            .iab_categories
-            .get(&sug.iab_id)
-            .cloned()
-            .unwrap_or_default();
-        let icon = self.icons.get(&sug.icon_id).cloned().unwrap_or_default();
-
-        // Handle full keyword
-        let full_keyword = match &metadata.full_keyword {
-            FullKeyword::Same => {
-                // Use the stored collapsed keyword directly
-                metadata.collapsed_keyword.clone()
-            }
-            FullKeyword::Different(kw) => kw.clone(),
+        // BLART requires prefix-free keys, the NUL terminator guarantees that
+        let key = CString::new(interval.key.as_str())?;
+        let metadata = KeywordMetadata {
+            suggestion_idx,
+            min_prefix_len: interval.min_len,
+            excluded: interval.excluded.into_boxed_slice(),
+            full_keyword,
+            collapsed_keyword: interval.key,
         };
+        self.keyword_tree.insert(key, metadata);
+        Ok(())
+    }
+}
 
-        // Reconstruct URLs
-        let url = self.reconstruct_url(sug.url_tid, &sug.url_suffix, &self.url_templates);
-        let click_url =
-            self.reconstruct_url(sug.click_tid, &sug.click_suffix, &self.click_templates);
-        let impression_url =
-            self.reconstruct_url(sug.imp_tid, &sug.imp_suffix, &self.imp_templates);
-
-        results.push(AmpResult {
-            title,
-            url,
-            click_url,
-            impression_url,
-            advertiser,
-            block_id: sug.block_id,
-            iab_category,
-            icon,
-            full_keyword,
-        });
+impl BlartAmpIndex {
+    /// An empty index, only ever handed to a builder
+    fn empty(infix: Option<InfixIndex>) -> Self {
+        BlartAmpIndex {
+            keyword_tree: TreeMap::new(),
+            full_keywords: FullKeywords::default(),
+            suggestions: SuggestionTable::default(),
+            infix,
+        }
+    }
 
-        Ok(())
+    /// Heap bytes of the keyword structure: the keys and their metadata, without the
+    /// tree's inner nodes
+    pub fn keyword_bytes(&self) -> usize {
+        self.keyword_tree
+            .iter()
+            .map(|(key, metadata)| {
+                std::mem::size_of::<(CString, KeywordMetadata)>()
+                    + key.as_bytes_with_nul().len()
+                    + metadata.collapsed_keyword.capacity()
+                    + std::mem::size_of_val(&*metadata.excluded)
+            })
+            .sum()
+    }
+
+    /// Why `ctx` skips the suggestion at `suggestion_idx`, if it does
+    fn rejection(&self, suggestion_idx: usize, ctx: &QueryContext) -> Option<Verdict> {
+        self.suggestions.rejection(suggestion_idx, ctx)
     }
 
-    fn reconstruct_url(
+    /// Build result from a suggestion and dictionaries
+    fn build_result(
         &self,
-        template_id: u32,
-        suffix: &str,
-        templates: &HashMap<u32, String>,
-    ) -> String {
-        templates
-            .get(&template_id)
-            .map(|t| format!("{}{}", t, suffix))
-            .unwrap_or_else(|| suffix.to_string())
+        suggestion_idx: usize,
+        full_keyword: String,
+        params: &UrlParams,
+        results: &mut Vec<AmpResult>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        results.push(
+            self.suggestions
+                .result(suggestion_idx, full_keyword, params),
+        );
+        Ok(())
     }
 }
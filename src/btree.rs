@@ -1,121 +1,69 @@
 use crate::common::{
-    AmpIndexer, AmpResult, FullKeyword, OriginalAmp, collapse_keywords_ex, extract_template,
+    AmpIndexBuilder, AmpIndexer, AmpQuery, AmpResult, BuildOptions, IntervalSink, KeywordEntry,
+    KeywordInterval, OriginalAmp, PoolRef, QueryContext, QueryPath, StringPool, add_intervals,
+    expand_keyword,
 };
+use crate::explain::{Explanation, FullKeywordResolution, Verdict};
+use crate::full_keywords::{FullKeywordRef, FullKeywords, FullKeywordsBuilder};
+use crate::infix::InfixIndex;
+use crate::suggestions::{SuggestionTable, SuggestionTableBuilder};
+use crate::url_params::UrlParams;
 use std::collections::{BTreeMap, HashMap};
 use std::ops::Bound::{Included, Unbounded};
 
-/// Optimized AMP suggestion for storage
-#[derive(Clone)]
-struct AmpSuggestion {
-    title: String,
-    url_tid: u32,
-    url_suf: String,
-    click_tid: u32,
-    click_suf: String,
-    imp_tid: u32,
-    imp_suf: String,
-    advertiser_id: u32,
-    block_id: i32,
-    iab: String,
-    icon_id: String,
-}
-
 pub struct BTreeAmpIndex {
-    /// collapsed prefix → (suggestion_idx, unused_min_pref, full_keyword)
-    pub keyword_index: BTreeMap<String, (usize, usize, FullKeyword)>,
-    suggestions: Vec<AmpSuggestion>,
-    advertisers: HashMap<u32, String>,
-    url_templates: HashMap<u32, String>,
-    click_templates: HashMap<u32, String>,
-    imp_templates: HashMap<u32, String>,
-    icons: HashMap<String, String>,
+    /// interval key → (suggestion_idx, min_pref, full_keyword)
+    pub keyword_index: BTreeMap<String, (usize, usize, FullKeywordRef)>,
+    /// interval key → char lengths of the prefixes its interval excludes, for the keys
+    /// that exclude any
+    excluded: HashMap<String, Box<[usize]>>,
+    full_keywords: FullKeywords,
+    suggestions: SuggestionTable,
+    /// Word-boundary suffixes of the full keywords, when enabled
+    infix: Option<InfixIndex>,
 }
 
 impl AmpIndexer for BTreeAmpIndex {
-    fn new() -> Self {
-        BTreeAmpIndex {
-            keyword_index: BTreeMap::new(),
-            suggestions: Vec::new(),
-            advertisers: HashMap::new(),
-            url_templates: HashMap::new(),
-            click_templates: HashMap::new(),
-            imp_templates: HashMap::new(),
-            icons: HashMap::new(),
-        }
-    }
-
-    fn build(&mut self, amps: &[OriginalAmp]) -> Result<(), Box<dyn std::error::Error>> {
-        let mut adv_lookup = HashMap::new();
-        let mut url_lookup = HashMap::new();
-        let mut click_lookup = HashMap::new();
-        let mut imp_lookup = HashMap::new();
-
-        for amp in amps {
-            // Internal advertiser
-            let adv_id = if let Some(&id) = adv_lookup.get(&amp.advertiser) {
-                id
-            } else {
-                let id = adv_lookup.len() as u32;
-                adv_lookup.insert(amp.advertiser.clone(), id);
-                self.advertisers.insert(id, amp.advertiser.clone());
-                id
-            };
-
-            // Templatize URLs
-            let (url_tid, url_suf) =
-                extract_template(&amp.url, &mut url_lookup, &mut self.url_templates);
-            let (click_tid, click_suf) =
-                extract_template(&amp.click_url, &mut click_lookup, &mut self.click_templates);
-            let (imp_tid, imp_suf) = extract_template(
-                &amp.impression_url,
-                &mut imp_lookup,
-                &mut self.imp_templates,
-            );
-
-            // Store suggestion
-            let idx = self.suggestions.len();
-            self.suggestions.push(AmpSuggestion {
-                title: amp.title.clone(),
-                url_tid,
-                url_suf,
-                click_tid,
-                click_suf,
-                imp_tid,
-                imp_suf,
-                advertiser_id: adv_id,
-                block_id: amp.block_id,
-                iab: amp.iab_category.clone(),
-                icon_id: amp.icon_id.clone(),
-            });
+    type Builder<'p> = BTreeIndexBuilder<'p>;
 
-            // Internal icon
-            self.icons
-                .entry(amp.icon_id.clone())
-                .or_insert_with(|| format!("icon://{}", amp.icon_id));
-
-            // Collapse each chain on keyword partials
-            for (kw, min_pref, fw) in
-                collapse_keywords_ex(&amp.keywords, &amp.full_keywords).into_iter()
-            {
-                self.keyword_index.insert(kw, (idx, min_pref, fw));
-            }
-        }
+    fn builder(options: &BuildOptions) -> Self::Builder<'static> {
+        BTreeIndexBuilder::new(PoolRef::Owned(StringPool::new()), options)
+    }
 
-        self.suggestions.shrink_to_fit();
+    fn builder_with_pool<'p>(
+        pool: &'p mut StringPool,
+        options: &BuildOptions,
+    ) -> Self::Builder<'p> {
+        BTreeIndexBuilder::new(PoolRef::Borrowed(pool), options)
+    }
+}
 
-        Ok(())
+impl AmpQuery for BTreeAmpIndex {
+    fn query_with_context(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<Vec<AmpResult>, Box<dyn std::error::Error>> {
+        Ok(self.query_with_path(query, ctx)?.0)
     }
 
-    fn query(&self, query: &str) -> Result<Vec<AmpResult>, Box<dyn std::error::Error>> {
+    fn query_with_path(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<(Vec<AmpResult>, QueryPath), Box<dyn std::error::Error>> {
         let qlen = query.chars().count();
         let range = (Included(query), Unbounded);
-        let mut best: Option<(&String, &(usize, usize, FullKeyword))> = None;
+        let mut best: Option<(&String, &(usize, usize, FullKeywordRef))> = None;
 
-        // scan collapsed keys in order, picking the shortest key that meets min_pref
+        // scan collapsed keys in order, picking the shortest key that meets min_pref and
+        // whose suggestion isn't filtered out
         for (key, val) in self.keyword_index.range::<str, _>(range) {
             match (key, val) {
                 (key, _) if !key.starts_with(query) => break,
                 (_, &(_, min_pref, _)) if qlen < min_pref => continue,
+                (key, _) if self.excludes(key, qlen) => continue,
+                (_, &(sidx, _, _)) if self.rejection(sidx, ctx).is_some() => continue,
                 (_, _) => {
                     best = Some((key, val));
                     break;
@@ -124,68 +72,260 @@ impl AmpIndexer for BTreeAmpIndex {
         }
 
         // if we found a match, build and return it
-        if let Some((key, &(sidx, _, ref fk))) = best {
-            let mut out = Vec::new();
-            self.build_result(key, sidx, fk, &mut out)?;
-            return Ok(out);
+        let mut out = Vec::new();
+        if let Some((key, &(sidx, _, fk))) = best {
+            self.build_result(key, sidx, fk, &ctx.url_params, &mut out)?;
+            return Ok((out, QueryPath::Tree));
+        }
+
+        // otherwise fall back to the suffixes of full keywords
+        if let Some(infix) = &self.infix {
+            if let Some(m) = infix.find(query, |sidx| self.rejection(sidx, ctx)) {
+                self.build_result(
+                    m.full_keyword,
+                    m.suggestion_idx,
+                    FullKeywordRef::SAME,
+                    &ctx.url_params,
+                    &mut out,
+                )?;
+            }
+            return Ok((out, QueryPath::Infix));
         }
-        Ok(Vec::new())
+        Ok((out, QueryPath::Tree))
     }
 
     fn stats(&self) -> HashMap<String, usize> {
         let mut m = HashMap::new();
         m.insert("keyword_index_size".into(), self.keyword_index.len());
-        m.insert("suggestions_count".into(), self.suggestions.len());
-        m.insert("advertisers_count".into(), self.advertisers.len());
-        m.insert("url_templates_count".into(), self.url_templates.len());
-        m.insert("icons_count".into(), self.icons.len());
+        m.insert(
+            "keyword_exclusions".into(),
+            self.excluded.values().map(|lens| lens.len()).sum(),
+        );
+        m.insert("keyword_structure_bytes".into(), self.keyword_bytes());
+        let refs = self.keyword_index.values().map(|&(_, _, fk)| fk);
+        self.full_keywords.add_stats(refs, &mut m);
+        self.suggestions.add_stats(&mut m);
+        m.insert(
+            "infix_entries".into(),
+            self.infix.as_ref().map_or(0, |infix| infix.len()),
+        );
+        m.insert(
+            "infix_bytes".into(),
+            self.infix.as_ref().map_or(0, |infix| infix.size_bytes()),
+        );
         m
     }
+
+    fn explain_with_context(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<Explanation, Box<dyn std::error::Error>> {
+        let mut explanation = Explanation::new(query, QueryPath::Tree);
+        let qlen = explanation.query_len;
+        let range = (Included(query), Unbounded);
+
+        // same scan as `query_with_context`
+        for (key, (sidx, min_pref, fk)) in self.keyword_index.range::<str, _>(range) {
+            if !key.starts_with(query) {
+                break;
+            }
+            let block_id = self.suggestions.block_id(*sidx);
+            if qlen < *min_pref {
+                explanation.scanned(key, *min_pref, block_id, QueryPath::Tree, Verdict::TooShort);
+                continue;
+            }
+            if self.excludes(key, qlen) {
+                explanation.scanned(key, *min_pref, block_id, QueryPath::Tree, Verdict::Excluded);
+                continue;
+            }
+            if let Some(verdict) = self.rejection(*sidx, ctx) {
+                explanation.scanned(key, *min_pref, block_id, QueryPath::Tree, verdict);
+                continue;
+            }
+            explanation.scanned(key, *min_pref, block_id, QueryPath::Tree, Verdict::Selected);
+            explanation.full_keyword = Some(FullKeywordResolution::from_full_keyword(
+                &self.full_keywords.get(*fk),
+                key,
+            ));
+            return Ok(explanation);
+        }
+
+        if let Some(infix) = &self.infix {
+            explanation.path = QueryPath::Infix;
+            infix.explain(
+                &mut explanation,
+                query,
+                |sidx| self.rejection(sidx, ctx),
+                |sidx| self.suggestions.block_id(sidx),
+            );
+        }
+        Ok(explanation)
+    }
+
+    fn iter_prefix<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = KeywordEntry> + 'a> {
+        let prefix = prefix.to_string();
+        let range = (Included(prefix.as_str()), Unbounded);
+        let entries = self
+            .keyword_index
+            .range::<str, _>(range)
+            .take_while(move |(key, _)| key.starts_with(&prefix))
+            .map(|(key, &(sidx, min_pref, _))| {
+                (key.clone(), min_pref, self.suggestions.block_id(sidx))
+            });
+        Box::new(entries)
+    }
+
+    fn keywords_for(&self, block_id: i32) -> Vec<String> {
+        let mut keywords: Vec<String> = self
+            .iter_prefix("")
+            .filter(|(_, _, id)| *id == block_id)
+            .flat_map(|(key, min_pref, _)| {
+                expand_keyword(&key, min_pref)
+                    .into_iter()
+                    .zip(min_pref..)
+                    .filter(|(_, len)| !self.excludes(&key, *len))
+                    .map(|(keyword, _)| keyword)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        keywords.sort();
+        keywords
+    }
+}
+
+/// Builds a `BTreeAmpIndex` one record at a time
+pub struct BTreeIndexBuilder<'p> {
+    index: BTreeAmpIndex,
+    pool: PoolRef<'p>,
+    full_keywords: FullKeywordsBuilder,
+    suggestions: SuggestionTableBuilder,
+}
+
+impl<'p> BTreeIndexBuilder<'p> {
+    fn new(pool: PoolRef<'p>, options: &BuildOptions) -> Self {
+        BTreeIndexBuilder {
+            index: BTreeAmpIndex::empty(options.infix_min_chars.map(InfixIndex::new)),
+            pool,
+            full_keywords: FullKeywordsBuilder::new(),
+            suggestions: SuggestionTableBuilder::new()
+                .icon_prefix("icon://")
+                .compression(options.string_compression),
+        }
+    }
+}
+
+impl AmpIndexBuilder for BTreeIndexBuilder<'_> {
+    type Index = BTreeAmpIndex;
+
+    fn add(&mut self, amp: &OriginalAmp) -> Result<(), Box<dyn std::error::Error>> {
+        let index = &mut self.index;
+        let pool = &mut *self.pool;
+
+        let idx = self.suggestions.push(amp, pool);
+
+        add_intervals(index, &mut self.full_keywords, pool, idx, amp)?;
+
+        if let Some(infix) = &mut index.infix {
+            infix.add_suggestion(idx, amp, pool);
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<BTreeAmpIndex, Box<dyn std::error::Error>> {
+        self.index.full_keywords = self.full_keywords.finish();
+        self.index.suggestions = self.suggestions.finish();
+        if let Some(infix) = &mut self.index.infix {
+            infix.finish();
+        }
+        Ok(self.index)
+    }
+}
+
+impl IntervalSink for BTreeAmpIndex {
+    fn contains_key(&self, key: &str) -> bool {
+        self.keyword_index.contains_key(key)
+    }
+
+    fn insert(
+        &mut self,
+        suggestion_idx: usize,
+        interval: KeywordInterval,
+        full_keyword: FullKeywordRef,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !interval.excluded.is_empty() {
+            self.excluded
+                .insert(interval.key.clone(), interval.excluded.into_boxed_slice());
+        }
+        self.keyword_index.insert(
+            interval.key,
+            (suggestion_idx, interval.min_len, full_keyword),
+        );
+        Ok(())
+    }
 }
 
 impl BTreeAmpIndex {
+    /// An empty index, only ever handed to a builder
+    fn empty(infix: Option<InfixIndex>) -> Self {
+        BTreeAmpIndex {
+            keyword_index: BTreeMap::new(),
+            excluded: HashMap::new(),
+            full_keywords: FullKeywords::default(),
+            suggestions: SuggestionTable::default(),
+            infix,
+        }
+    }
+
+    /// Heap bytes of the keyword structure: the keys, their values and their exclusions,
+    /// without the B-tree's spare node slots
+    pub fn keyword_bytes(&self) -> usize {
+        let entries = self.keyword_index.len()
+            * std::mem::size_of::<(String, (usize, usize, FullKeywordRef))>()
+            + self
+                .keyword_index
+                .keys()
+                .map(String::capacity)
+                .sum::<usize>();
+        let exclusions = self.excluded.capacity() * std::mem::size_of::<(String, Box<[usize]>)>()
+            + self
+                .excluded
+                .iter()
+                .map(|(key, lens)| key.capacity() + std::mem::size_of_val(&**lens))
+                .sum::<usize>();
+        entries + exclusions
+    }
+
+    /// Whether the interval of `key` excludes its prefix of `len` chars
+    fn excludes(&self, key: &str, len: usize) -> bool {
+        self.excluded
+            .get(key)
+            .is_some_and(|lens| lens.binary_search(&len).is_ok())
+    }
+
+    /// Why `ctx` skips the suggestion at `sidx`, if it does
+    fn rejection(&self, sidx: usize, ctx: &QueryContext) -> Option<Verdict> {
+        self.suggestions.rejection(sidx, ctx)
+    }
+
     fn build_result(
         &self,
         keyword: &str,
         sidx: usize,
-        full_keyword: &FullKeyword,
+        full_keyword: FullKeywordRef,
+        params: &UrlParams,
         results: &mut Vec<AmpResult>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let sugg = &self.suggestions[sidx];
-
-        let url = Self::reconstruct(&self.url_templates, sugg.url_tid, &sugg.url_suf);
-        let click = Self::reconstruct(&self.click_templates, sugg.click_tid, &sugg.click_suf);
-        let imp = Self::reconstruct(&self.imp_templates, sugg.imp_tid, &sugg.imp_suf);
-        let adv = self
-            .advertisers
-            .get(&sugg.advertiser_id)
-            .cloned()
-            .unwrap_or_default();
-        let icon = self.icons.get(&sugg.icon_id).cloned().unwrap_or_default();
-
-        results.push(AmpResult {
-            title: sugg.title.clone(),
-            url,
-            click_url: click,
-            impression_url: imp,
-            advertiser: adv,
-            block_id: sugg.block_id,
-            iab_category: sugg.iab.clone(),
-            icon,
-            full_keyword: full_keyword.full_keyword(keyword),
-        });
+        let full_keyword = self.full_keywords.resolve(full_keyword, keyword);
+        results.push(self.suggestions.result(sidx, full_keyword, params));
         Ok(())
     }
 
-    pub fn dump(self: &BTreeAmpIndex) {
-        for (key, val) in self.keyword_index.iter() {
-            dbg!(key);
-            dbg!(val);
+    /// Print every collapsed keyword as `keyword<TAB>min_prefix_len<TAB>block_id`
+    pub fn dump(&self) {
+        for (key, min_pref, block_id) in self.iter_prefix("") {
+            println!("{}\t{}\t{}", key, min_pref, block_id);
         }
     }
-
-    fn reconstruct(dict: &HashMap<u32, String>, tid: u32, suffix: &str) -> String {
-        dict.get(&tid)
-            .map_or_else(|| suffix.to_string(), |t| format!("{}{}", t, suffix))
-    }
 }
@@ -1,5 +1,10 @@
-use serde::Deserialize;
-use std::collections::HashMap;
+use crate::explain::{Explanation, Verdict};
+use crate::full_keywords::{FullKeywordRef, FullKeywordsBuilder};
+use crate::url_params::UrlParams;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
 
 /// Original Amp structure from JSON
 #[derive(Clone, Debug, Deserialize)]
@@ -18,6 +23,39 @@ pub struct OriginalAmp {
     pub impression_url: String,
     #[serde(rename = "icon")]
     pub icon_id: String,
+    /// Start of the campaign, as a Unix timestamp in seconds
+    #[serde(default)]
+    pub start: Option<i64>,
+    /// End of the campaign (exclusive), as a Unix timestamp in seconds
+    #[serde(default)]
+    pub end: Option<i64>,
+}
+
+impl OriginalAmp {
+    /// The time window of the campaign, if it has one
+    pub fn window(&self) -> Option<TimeWindow> {
+        if self.start.is_none() && self.end.is_none() {
+            return None;
+        }
+        Some(TimeWindow {
+            start: self.start,
+            end: self.end,
+        })
+    }
+}
+
+/// When a suggestion may be shown, as Unix timestamps in seconds; either side may be open
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TimeWindow {
+    pub start: Option<i64>,
+    /// Exclusive
+    pub end: Option<i64>,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, now: i64) -> bool {
+        self.start.is_none_or(|start| start <= now) && self.end.is_none_or(|end| now < end)
+    }
 }
 
 /// Common result structure
@@ -35,7 +73,7 @@ pub struct AmpResult {
 }
 
 /// Full keyword for each keyword.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FullKeyword {
     /// If the full keyword is the same as the keyword.
     Same,
@@ -60,58 +98,441 @@ impl FullKeyword {
     }
 }
 
-/// Interface for all AMP indexers
-pub trait AmpIndexer {
-    /// Create a new index
-    fn new() -> Self;
+/// Options for building an index
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct BuildOptions {
+    /// Collapsed keywords of at most this many characters are served by the short prefix
+    /// cache of `HybridAmpIndex` rather than its trie.
+    pub short_cache_max_chars: usize,
+    /// Also index the word-boundary suffixes of full keywords, answering queries of at
+    /// least this many characters that match no keyword. Disabled when unset.
+    pub infix_min_chars: Option<usize>,
+    /// How suggestion titles and URL suffixes are stored
+    pub string_compression: StringCompression,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        BuildOptions {
+            short_cache_max_chars: 3,
+            infix_min_chars: None,
+            string_compression: StringCompression::None,
+        }
+    }
+}
+
+/// Compression of the suggestion strings, trading decoding time for memory
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StringCompression {
+    /// Plain strings, titles shared through the string pool
+    #[default]
+    None,
+    /// An FSST symbol table trained on the strings when the index is built
+    Fsst,
+}
 
-    /// Build the index from raw AMP data
-    fn build(&mut self, amps: &[OriginalAmp]) -> Result<(), Box<dyn std::error::Error>>;
+/// Lookup path that answered a query
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryPath {
+    /// The short prefix cache of `HybridAmpIndex`
+    ShortCache,
+    /// The main keyword tree of the index
+    Tree,
+    /// The word-boundary suffix index, searched when the tree has no answer
+    Infix,
+    /// The precomputed prefix answers of `PrefixTableAmpIndex`
+    AnswerTable,
+}
+
+impl QueryPath {
+    pub const ALL: [QueryPath; 4] = [
+        QueryPath::ShortCache,
+        QueryPath::Tree,
+        QueryPath::Infix,
+        QueryPath::AnswerTable,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QueryPath::ShortCache => "short_cache",
+            QueryPath::Tree => "tree",
+            QueryPath::Infix => "infix",
+            QueryPath::AnswerTable => "answer_table",
+        }
+    }
+}
+
+/// Per-request context of a query: the suggestions the user must not be shown, e.g. the
+/// ones they dismissed or the ones whose campaign isn't running, and the values filled into
+/// the URL placeholders of the results.
+///
+/// A query skips the keys of filtered out suggestions and answers with the next candidate
+/// instead.
+#[derive(Clone, Debug, Default)]
+pub struct QueryContext {
+    pub blocked_block_ids: HashSet<i32>,
+    /// Advertiser names, matched exactly
+    pub blocked_advertisers: HashSet<String>,
+    /// Current Unix timestamp in seconds: suggestions outside their time window are
+    /// skipped. Time windows are ignored when unset.
+    pub now: Option<i64>,
+    pub url_params: UrlParams,
+}
+
+impl QueryContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn block_id(mut self, block_id: i32) -> Self {
+        self.blocked_block_ids.insert(block_id);
+        self
+    }
+
+    pub fn block_advertiser(mut self, advertiser: impl Into<String>) -> Self {
+        self.blocked_advertisers.insert(advertiser.into());
+        self
+    }
+
+    /// Only show the suggestions whose time window contains `now`
+    pub fn at(mut self, now: i64) -> Self {
+        self.now = Some(now);
+        self
+    }
+
+    /// Fill the URL placeholders of the results from `url_params`
+    pub fn with_url_params(mut self, url_params: UrlParams) -> Self {
+        self.url_params = url_params;
+        self
+    }
+
+    /// Whether no suggestion is filtered out
+    pub fn skips_nothing(&self) -> bool {
+        self.blocked_block_ids.is_empty()
+            && self.blocked_advertisers.is_empty()
+            && self.now.is_none()
+    }
+
+    /// Why a suggestion must be skipped, if it must: `Verdict::Inactive` when `now` is
+    /// outside its `window`, `Verdict::Blocked` when its block id or advertiser is blocked
+    pub fn rejects(
+        &self,
+        block_id: i32,
+        advertiser: &str,
+        window: Option<&TimeWindow>,
+    ) -> Option<Verdict> {
+        if let (Some(now), Some(window)) = (self.now, window)
+            && !window.contains(now)
+        {
+            return Some(Verdict::Inactive);
+        }
+        if self.blocked_block_ids.contains(&block_id)
+            || self.blocked_advertisers.contains(advertiser)
+        {
+            return Some(Verdict::Blocked);
+        }
+        None
+    }
+}
+
+/// Query interface shared by all AMP indexers.
+///
+/// Unlike `AmpIndexer` it is object safe, so the backend can be picked at runtime as a
+/// `Box<dyn AmpQuery + Send + Sync>`.
+pub trait AmpQuery {
+    /// Query for suggestions matching a prefix in the context of a request
+    fn query_with_context(
+        &self,
+        prefix: &str,
+        ctx: &QueryContext,
+    ) -> Result<Vec<AmpResult>, Box<dyn Error>>;
 
     /// Query for suggestions matching a prefix
-    fn query(&self, prefix: &str) -> Result<Vec<AmpResult>, Box<dyn std::error::Error>>;
+    fn query(&self, prefix: &str) -> Result<Vec<AmpResult>, Box<dyn Error>> {
+        self.query_with_context(prefix, &QueryContext::default())
+    }
+
+    /// Like `query_with_context`, also telling which lookup path answered it
+    fn query_with_path(
+        &self,
+        prefix: &str,
+        ctx: &QueryContext,
+    ) -> Result<(Vec<AmpResult>, QueryPath), Box<dyn Error>> {
+        Ok((self.query_with_context(prefix, ctx)?, QueryPath::Tree))
+    }
 
     /// Get statistics about the index
     fn stats(&self) -> HashMap<String, usize>;
+
+    /// Answer a query like `query_with_context`, reporting the keys scanned and why the answer
+    /// won
+    fn explain_with_context(
+        &self,
+        prefix: &str,
+        ctx: &QueryContext,
+    ) -> Result<Explanation, Box<dyn Error>>;
+
+    /// Answer a query like `query`, reporting the keys scanned and why the answer won
+    fn explain(&self, prefix: &str) -> Result<Explanation, Box<dyn Error>> {
+        self.explain_with_context(prefix, &QueryContext::default())
+    }
+
+    /// Collapsed keywords starting with `prefix`, in key order, as
+    /// `(keyword, min_prefix_len, block_id)`
+    fn iter_prefix<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = KeywordEntry> + 'a>;
+
+    /// Every query that shows the suggestion `block_id`, i.e. its collapsed keywords
+    /// expanded back into their partials, sorted
+    fn keywords_for(&self, block_id: i32) -> Vec<String> {
+        let mut keywords: Vec<String> = self
+            .iter_prefix("")
+            .filter(|(_, _, id)| *id == block_id)
+            .flat_map(|(keyword, min_prefix_len, _)| expand_keyword(&keyword, min_prefix_len))
+            .collect();
+        keywords.sort();
+        keywords
+    }
+
+    /// The suggestion `block_id` as served for its first keyword in key order, if it has
+    /// any keyword. This scans the whole index.
+    fn lookup_block(&self, block_id: i32) -> Option<AmpResult> {
+        let (keyword, _, _) = self.iter_prefix("").find(|(_, _, id)| *id == block_id)?;
+        self.query(&keyword).ok()?.into_iter().next()
+    }
 }
 
-/// Run-End encoding for full keywords
-pub struct RunEndEncoding {
-    pub values: Vec<String>,
-    pub indices: Vec<usize>,
+/// A collapsed keyword of an index: `(keyword, min_prefix_len, block_id)`
+pub type KeywordEntry = (String, usize, i32);
+
+/// The partials a collapsed keyword stands for, shortest first
+/// e.g. ("foobar", 4) → ["foob", "fooba", "foobar"]
+pub fn expand_keyword(keyword: &str, min_prefix_len: usize) -> Vec<String> {
+    let mut partials = Vec::new();
+    let mut partial = String::new();
+    for (n, c) in keyword.chars().enumerate() {
+        partial.push(c);
+        if n + 1 >= min_prefix_len {
+            partials.push(partial.clone());
+        }
+    }
+    partials
 }
 
-impl Default for RunEndEncoding {
-    fn default() -> Self {
-        Self::new()
+impl<T: AmpQuery + ?Sized> AmpQuery for Box<T> {
+    fn query_with_context(
+        &self,
+        prefix: &str,
+        ctx: &QueryContext,
+    ) -> Result<Vec<AmpResult>, Box<dyn Error>> {
+        (**self).query_with_context(prefix, ctx)
+    }
+
+    fn query(&self, prefix: &str) -> Result<Vec<AmpResult>, Box<dyn Error>> {
+        (**self).query(prefix)
+    }
+
+    fn query_with_path(
+        &self,
+        prefix: &str,
+        ctx: &QueryContext,
+    ) -> Result<(Vec<AmpResult>, QueryPath), Box<dyn Error>> {
+        (**self).query_with_path(prefix, ctx)
+    }
+
+    fn stats(&self) -> HashMap<String, usize> {
+        (**self).stats()
+    }
+
+    fn explain_with_context(
+        &self,
+        prefix: &str,
+        ctx: &QueryContext,
+    ) -> Result<Explanation, Box<dyn Error>> {
+        (**self).explain_with_context(prefix, ctx)
+    }
+
+    fn explain(&self, prefix: &str) -> Result<Explanation, Box<dyn Error>> {
+        (**self).explain(prefix)
+    }
+
+    fn iter_prefix<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = KeywordEntry> + 'a> {
+        (**self).iter_prefix(prefix)
+    }
+
+    fn keywords_for(&self, block_id: i32) -> Vec<String> {
+        (**self).keywords_for(block_id)
+    }
+
+    fn lookup_block(&self, block_id: i32) -> Option<AmpResult> {
+        (**self).lookup_block(block_id)
+    }
+}
+
+impl<T: AmpQuery + ?Sized> AmpQuery for Arc<T> {
+    fn query_with_context(
+        &self,
+        prefix: &str,
+        ctx: &QueryContext,
+    ) -> Result<Vec<AmpResult>, Box<dyn Error>> {
+        (**self).query_with_context(prefix, ctx)
+    }
+
+    fn query(&self, prefix: &str) -> Result<Vec<AmpResult>, Box<dyn Error>> {
+        (**self).query(prefix)
+    }
+
+    fn query_with_path(
+        &self,
+        prefix: &str,
+        ctx: &QueryContext,
+    ) -> Result<(Vec<AmpResult>, QueryPath), Box<dyn Error>> {
+        (**self).query_with_path(prefix, ctx)
+    }
+
+    fn stats(&self) -> HashMap<String, usize> {
+        (**self).stats()
+    }
+
+    fn explain_with_context(
+        &self,
+        prefix: &str,
+        ctx: &QueryContext,
+    ) -> Result<Explanation, Box<dyn Error>> {
+        (**self).explain_with_context(prefix, ctx)
+    }
+
+    fn explain(&self, prefix: &str) -> Result<Explanation, Box<dyn Error>> {
+        (**self).explain(prefix)
+    }
+
+    fn iter_prefix<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = KeywordEntry> + 'a> {
+        (**self).iter_prefix(prefix)
     }
+
+    fn keywords_for(&self, block_id: i32) -> Vec<String> {
+        (**self).keywords_for(block_id)
+    }
+
+    fn lookup_block(&self, block_id: i32) -> Option<AmpResult> {
+        (**self).lookup_block(block_id)
+    }
+}
+
+/// Interface for all AMP indexers.
+///
+/// An indexer is immutable once built: it is produced by its `AmpIndexBuilder`, so it can
+/// be shared across threads as is and a half-built index can never be queried.
+pub trait AmpIndexer: AmpQuery + Sized + Send + Sync {
+    /// Incremental builder for this index
+    type Builder<'p>: AmpIndexBuilder<Index = Self>;
+
+    /// Start building an index that owns its dictionaries
+    fn builder(options: &BuildOptions) -> Self::Builder<'static>;
+
+    /// Start building an index, interning dictionary values into a shared pool so that
+    /// indexes built from overlapping datasets share their strings.
+    fn builder_with_pool<'p>(pool: &'p mut StringPool, options: &BuildOptions)
+    -> Self::Builder<'p>;
+
+    /// Build the index from raw AMP data
+    fn build(amps: &[OriginalAmp]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut builder = Self::builder(&BuildOptions::default());
+        builder.add_all(amps)?;
+        builder.finish()
+    }
+
+    /// Build the index from raw AMP data, interning dictionary values into a shared pool
+    fn build_with_pool(
+        amps: &[OriginalAmp],
+        pool: &mut StringPool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut builder = Self::builder_with_pool(pool, &BuildOptions::default());
+        builder.add_all(amps)?;
+        builder.finish()
+    }
+}
+
+/// Builds an index incrementally, one AMP record at a time
+pub trait AmpIndexBuilder {
+    type Index: AmpIndexer;
+
+    /// Add a record to the index
+    fn add(&mut self, amp: &OriginalAmp) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Add all the records to the index
+    fn add_all(&mut self, amps: &[OriginalAmp]) -> Result<(), Box<dyn std::error::Error>> {
+        for amp in amps {
+            self.add(amp)?;
+        }
+        Ok(())
+    }
+
+    /// Freeze the index
+    fn finish(self) -> Result<Self::Index, Box<dyn std::error::Error>>;
+}
+
+/// Interned strings shared by the dictionaries of one or more indexes
+#[derive(Debug, Default)]
+pub struct StringPool {
+    strings: HashSet<Arc<str>>,
 }
 
-impl RunEndEncoding {
+impl StringPool {
     pub fn new() -> Self {
-        RunEndEncoding {
-            values: Vec::new(),
-            indices: Vec::new(),
+        StringPool {
+            strings: HashSet::new(),
         }
     }
 
-    pub fn add(&mut self, value: String, count: usize) {
-        self.values.push(value);
-        let next_index = self.indices.last().map_or(count - 1, |last| last + count);
-        self.indices.push(next_index);
+    /// Return the pooled copy of `value`, adding it to the pool if needed.
+    pub fn intern(&mut self, value: &str) -> Arc<str> {
+        if let Some(pooled) = self.strings.get(value) {
+            return pooled.clone();
+        }
+        let pooled: Arc<str> = Arc::from(value);
+        self.strings.insert(pooled.clone());
+        pooled
     }
 
-    pub fn get(&self, index: usize) -> Option<&str> {
-        // Binary search to find the right segment
-        match self.indices.binary_search(&index) {
-            Ok(exact_idx) => Some(&self.values[exact_idx]),
-            Err(insertion_idx) => {
-                if insertion_idx == 0 {
-                    None // index is before the first element
-                } else {
-                    Some(&self.values[insertion_idx - 1])
-                }
-            }
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// Total bytes of the pooled strings
+    pub fn bytes(&self) -> usize {
+        self.strings.iter().map(|s| s.len()).sum()
+    }
+}
+
+/// A string pool either owned by a builder or borrowed from its caller
+pub enum PoolRef<'p> {
+    Owned(StringPool),
+    Borrowed(&'p mut StringPool),
+}
+
+impl std::ops::Deref for PoolRef<'_> {
+    type Target = StringPool;
+
+    fn deref(&self) -> &StringPool {
+        match self {
+            PoolRef::Owned(pool) => pool,
+            PoolRef::Borrowed(pool) => pool,
+        }
+    }
+}
+
+impl std::ops::DerefMut for PoolRef<'_> {
+    fn deref_mut(&mut self) -> &mut StringPool {
+        match self {
+            PoolRef::Owned(pool) => pool,
+            PoolRef::Borrowed(pool) => pool,
         }
     }
 }
@@ -120,17 +541,17 @@ impl RunEndEncoding {
 pub fn extract_template(
     url: &str,
     template_lookup: &mut HashMap<String, u32>,
-    templates: &mut HashMap<u32, String>,
+    templates: &mut HashMap<u32, Arc<str>>,
+    pool: &mut StringPool,
 ) -> (u32, String) {
-    let split_idx = url.find('?').unwrap_or_else(|| url.rfind('/').unwrap_or(0));
-    let (template, suffix) = url.split_at(split_idx);
+    let (template, suffix) = crate::suggestions::split_url(url);
 
     match template_lookup.get(template) {
         Some(&id) => (id, suffix.to_string()),
         None => {
             let id = template_lookup.len() as u32;
             template_lookup.insert(template.to_string(), id);
-            templates.insert(id, template.to_string());
+            templates.insert(id, pool.intern(template));
             (id, suffix.to_string())
         }
     }
@@ -176,20 +597,254 @@ pub fn collapse_keywords(keywords: &[String]) -> Vec<(String, usize)> {
     out
 }
 
+/// The prefixes of `key` of at least `min_len` chars, except the `excluded` ones, all
+/// standing for `full_keyword`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeywordInterval {
+    pub key: String,
+    pub min_len: usize,
+    /// Char lengths of the prefixes in the interval that aren't keywords, ascending
+    pub excluded: Vec<usize>,
+    pub full_keyword: FullKeyword,
+}
+
+impl KeywordInterval {
+    /// Whether the interval stands for `keyword`
+    pub fn contains(&self, keyword: &str) -> bool {
+        self.key.starts_with(keyword) && self.answers(keyword.chars().count())
+    }
+
+    /// Whether the prefix of the key of `len` chars is in the interval
+    pub fn answers(&self, len: usize) -> bool {
+        len >= self.min_len && self.excluded.binary_search(&len).is_err()
+    }
+
+    /// Every keyword of the interval, shortest first
+    pub fn keywords(&self) -> impl Iterator<Item = &str> {
+        char_prefixes(&self.key)
+            .enumerate()
+            .filter(|&(len, _)| self.answers(len))
+            .map(|(_, prefix)| prefix)
+    }
+}
+
+/// A keyword set as prefix intervals, sorted by key
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeywordIntervals {
+    pub intervals: Vec<KeywordInterval>,
+}
+
+impl KeywordIntervals {
+    /// Whether `keyword` is in the set
+    pub fn contains(&self, keyword: &str) -> bool {
+        self.intervals
+            .iter()
+            .any(|interval| interval.contains(keyword))
+    }
+
+    /// Every keyword of the set
+    pub fn keywords(&self) -> BTreeSet<String> {
+        self.intervals
+            .iter()
+            .flat_map(KeywordInterval::keywords)
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Number of exclusions over all the intervals
+    pub fn exclusions(&self) -> usize {
+        self.intervals
+            .iter()
+            .map(|interval| interval.excluded.len())
+            .sum()
+    }
+
+    /// Intervals and exclusions stored
+    pub fn entries(&self) -> usize {
+        self.intervals.len() + self.exclusions()
+    }
+}
+
+/// The prefixes of `s` by char count, from the empty one to `s` itself
+fn char_prefixes(s: &str) -> impl Iterator<Item = &str> {
+    std::iter::once(0)
+        .chain(s.char_indices().skip(1).map(|(i, _)| i))
+        .chain((!s.is_empty()).then_some(s.len()))
+        .map(|end| &s[..end])
+}
+
+/// The full keyword of each keyword, from the run-length encoded `full_keywords`.
+/// Keywords past the end of the runs are their own full keyword.
+pub(crate) fn pointwise_full_keywords<'a>(
+    keywords: &'a [String],
+    full_keywords: &'a [(String, usize)],
+) -> Vec<(&'a str, &'a str)> {
+    let fks = full_keywords
+        .iter()
+        .flat_map(|(full_keyword, repeat_for)| {
+            std::iter::repeat_n(Some(full_keyword.as_str()), *repeat_for)
+        })
+        .chain(std::iter::repeat(None));
+    keywords
+        .iter()
+        .map(String::as_str)
+        .zip(fks)
+        .map(|(keyword, fk)| (keyword, fk.unwrap_or(keyword)))
+        .collect()
+}
+
+/// Collapse any keyword set, in any order and with any gaps, into intervals. An interval
+/// only holds keywords of the same full keyword, which keywords past the end of the
+/// `full_keywords` runs are for themselves.
+///
+/// Within a full keyword, every keyword no other keyword extends needs an interval of its
+/// own, and each of them gets exactly one, so there are as few intervals as possible. The
+/// other keywords are then covered shortest first, each by lowering the `min_len` of the
+/// key extending it that excludes the fewest prefixes on the way.
+/// e.g. ["amazon", "am", "ama"] → [("amazon", 2)] excluding "amaz" and "amazo"
+pub fn collapse_intervals(
+    keywords: &[String],
+    full_keywords: &[(String, usize)],
+) -> KeywordIntervals {
+    let mut groups: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    for (keyword, full_keyword) in pointwise_full_keywords(keywords, full_keywords) {
+        groups.entry(full_keyword).or_default().insert(keyword);
+    }
+
+    let mut intervals: Vec<KeywordInterval> = groups
+        .into_iter()
+        .flat_map(|(full_keyword, set)| {
+            collapse_group(&set)
+                .into_iter()
+                .map(move |(key, min_len, excluded)| KeywordInterval {
+                    key: key.to_string(),
+                    min_len,
+                    excluded,
+                    full_keyword: FullKeyword::new(key, full_keyword),
+                })
+        })
+        .collect();
+    intervals.sort_by(|a, b| a.key.cmp(&b.key).then(a.min_len.cmp(&b.min_len)));
+    KeywordIntervals { intervals }
+}
+
+/// Where a builder stores the keyword intervals of its suggestions
+pub(crate) trait IntervalSink {
+    /// Whether a suggestion added before has the key
+    fn contains_key(&self, key: &str) -> bool;
+
+    /// Store the interval of a key no suggestion has yet, with the reference to its full
+    /// keyword
+    fn insert(
+        &mut self,
+        suggestion_idx: usize,
+        interval: KeywordInterval,
+        full_keyword: FullKeywordRef,
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+/// A key held by a builder until every key is known and they get encoded in order
+pub(crate) struct PendingKey {
+    pub suggestion_idx: usize,
+    pub min_len: usize,
+    pub excluded: Vec<usize>,
+    pub full_keyword: FullKeywordRef,
+}
+
+impl IntervalSink for BTreeMap<String, PendingKey> {
+    fn contains_key(&self, key: &str) -> bool {
+        BTreeMap::contains_key(self, key)
+    }
+
+    fn insert(
+        &mut self,
+        suggestion_idx: usize,
+        interval: KeywordInterval,
+        full_keyword: FullKeywordRef,
+    ) -> Result<(), Box<dyn Error>> {
+        let key = PendingKey {
+            suggestion_idx,
+            min_len: interval.min_len,
+            excluded: interval.excluded,
+            full_keyword,
+        };
+        BTreeMap::insert(self, interval.key, key);
+        Ok(())
+    }
+}
+
+/// Collapse the keywords of `amp` into intervals and store those whose key no suggestion
+/// has yet: when suggestions share a key, the first one added keeps it in every backend.
+/// Only the full keywords of the keys stored get interned.
+pub(crate) fn add_intervals(
+    sink: &mut impl IntervalSink,
+    full_keywords: &mut FullKeywordsBuilder,
+    pool: &mut StringPool,
+    suggestion_idx: usize,
+    amp: &OriginalAmp,
+) -> Result<(), Box<dyn Error>> {
+    for interval in collapse_intervals(&amp.keywords, &amp.full_keywords).intervals {
+        if sink.contains_key(&interval.key) {
+            continue;
+        }
+        let full_keyword = full_keywords.push(&interval.full_keyword, pool);
+        sink.insert(suggestion_idx, interval, full_keyword)?;
+    }
+    Ok(())
+}
+
+/// The intervals of the keywords of one full keyword, as `(key, min_len, excluded)`
+fn collapse_group<'a>(set: &BTreeSet<&'a str>) -> Vec<(&'a str, usize, Vec<usize>)> {
+    let sorted: Vec<&str> = set.iter().copied().collect();
+
+    // Whatever extends a keyword sorts right after it
+    let keys: Vec<&str> = sorted
+        .iter()
+        .enumerate()
+        .filter(|&(i, key)| !sorted.get(i + 1).is_some_and(|next| next.starts_with(key)))
+        .map(|(_, &key)| key)
+        .collect();
+    let mut min_lens: Vec<usize> = keys.iter().map(|key| key.chars().count()).collect();
+
+    // Char lengths of the prefixes of `key` in `lens` that aren't keywords
+    let gaps = |key: &str, lens: std::ops::Range<usize>| -> Vec<usize> {
+        char_prefixes(key)
+            .enumerate()
+            .filter(|(len, prefix)| lens.contains(len) && !set.contains(prefix))
+            .map(|(len, _)| len)
+            .collect()
+    };
+
+    for &keyword in &sorted {
+        let len = keyword.chars().count();
+        let first = keys.partition_point(|key| *key < keyword);
+        let extending =
+            first..first + keys[first..].partition_point(|key| key.starts_with(keyword));
+        if extending.clone().any(|k| min_lens[k] <= len) {
+            continue;
+        }
+        let best = extending
+            .min_by_key(|&k| gaps(keys[k], len..min_lens[k]).len())
+            .expect("a keyword extends itself");
+        min_lens[best] = len;
+    }
+
+    keys.iter()
+        .zip(min_lens)
+        .map(|(&key, min_len)| (key, min_len, gaps(key, min_len..usize::MAX)))
+        .collect()
+}
+
 /// Extent `collapse_keywords` to return a `FullKeyword` for each collapsed keyword.
+/// Keywords past the end of the `full_keywords` runs are their own full keyword.
 pub fn collapse_keywords_ex(
     keywords: &[String],
     full_keywords: &[(String, usize)],
 ) -> Vec<(String, usize, FullKeyword)> {
     let mut out: Vec<(String, usize, FullKeyword)> = Vec::new();
 
-    // Restore the pointwise full keywords sequence via the RLE encoded `full_keywords`.
-    let fks = full_keywords.iter().flat_map(|(full_keyword, repeat_for)| {
-        std::iter::repeat_n(full_keyword.as_str(), *repeat_for)
-    });
-
-    // Zip up the keywords with the full_keywords.
-    let keywords_ext: Vec<(_, _)> = keywords.iter().map(String::as_str).zip(fks).collect();
+    // Zip up the keywords with their full keyword
+    let keywords_ext = pointwise_full_keywords(keywords, full_keywords);
 
     let mut i = 0;
     while i < keywords_ext.len() {
@@ -200,10 +855,14 @@ pub fn collapse_keywords_ex(
         let mut n_collapsed = 0;
         let mut prev = curr;
 
-        // extend the run as long as each next is curr + exactly one char
+        // extend the run as long as each next is curr + exactly one char for the same
+        // full keyword, as the whole run gets the full keyword of its last element
         while j < keywords_ext.len() {
-            let (nxt, _) = keywords_ext[j];
-            if nxt.starts_with(prev) && nxt.chars().count() == curr_len + n_collapsed + 1 {
+            let (nxt, nxt_fk) = keywords_ext[j];
+            if nxt.starts_with(prev)
+                && nxt.chars().count() == curr_len + n_collapsed + 1
+                && nxt_fk == curr_fk
+            {
                 n_collapsed += 1;
                 j += 1;
                 prev = nxt;
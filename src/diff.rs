@@ -0,0 +1,329 @@
+//! Compare two versions of an AMP payload before deploying the new one.
+
+use crate::btree::BTreeAmpIndex;
+use crate::common::{AmpIndexer, AmpQuery, OriginalAmp, collapse_intervals};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+
+/// Number of prefixes sampled from the payloads when none are given
+pub const DEFAULT_SAMPLE_SIZE: usize = 1000;
+
+/// Options for `diff_payloads`
+#[derive(Clone, Debug)]
+pub struct DiffOptions {
+    /// Prefixes to compare the answers for. When `None`, prefixes are sampled from the
+    /// keywords of both payloads.
+    pub prefixes: Option<Vec<String>>,
+    /// Number of prefixes to sample, on top of the keywords gained or lost
+    pub sample_size: usize,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            prefixes: None,
+            sample_size: DEFAULT_SAMPLE_SIZE,
+        }
+    }
+}
+
+/// A suggestion that only exists in one of the payloads
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SuggestionSummary {
+    pub block_id: i32,
+    pub advertiser: String,
+    pub title: String,
+}
+
+/// Keywords gained and lost by an advertiser
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct KeywordChanges {
+    pub gained: Vec<String>,
+    pub lost: Vec<String>,
+}
+
+/// A field of a suggestion that changed between the payloads
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub block_id: i32,
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+/// A collapsed keyword produced by more than one suggestion
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Collision {
+    pub keyword: String,
+    pub block_ids: Vec<i32>,
+}
+
+/// A prefix whose answer changed, as the block id of the suggestion returned
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AnswerChange {
+    pub prefix: String,
+    pub old: Option<i32>,
+    pub new: Option<i32>,
+}
+
+/// Everything that changed between two payloads
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PayloadDiff {
+    pub added: Vec<SuggestionSummary>,
+    pub removed: Vec<SuggestionSummary>,
+    /// Keyword changes keyed by advertiser
+    pub keywords: BTreeMap<String, KeywordChanges>,
+    pub field_changes: Vec<FieldChange>,
+    /// Collisions in the new payload that were not in the old one
+    pub new_collisions: Vec<Collision>,
+    /// Number of prefixes whose answers were compared
+    pub prefixes_checked: usize,
+    pub answer_changes: Vec<AnswerChange>,
+}
+
+impl PayloadDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.keywords.is_empty()
+            && self.field_changes.is_empty()
+            && self.new_collisions.is_empty()
+            && self.answer_changes.is_empty()
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// Compare two payloads
+pub fn diff_payloads(
+    old: &[OriginalAmp],
+    new: &[OriginalAmp],
+    options: &DiffOptions,
+) -> Result<PayloadDiff, Box<dyn Error>> {
+    let old_by_id: BTreeMap<i32, &OriginalAmp> = old.iter().map(|a| (a.block_id, a)).collect();
+    let new_by_id: BTreeMap<i32, &OriginalAmp> = new.iter().map(|a| (a.block_id, a)).collect();
+
+    let mut diff = PayloadDiff::default();
+
+    // Suggestions added or removed, and fields changed on the common ones
+    for (id, amp) in &new_by_id {
+        match old_by_id.get(id) {
+            None => diff.added.push(summary(amp)),
+            Some(old_amp) => diff.field_changes.extend(field_changes(old_amp, amp)),
+        }
+    }
+    for (id, amp) in &old_by_id {
+        if !new_by_id.contains_key(id) {
+            diff.removed.push(summary(amp));
+        }
+    }
+
+    // Keywords gained or lost per advertiser
+    let old_keywords = keywords_by_advertiser(old);
+    let new_keywords = keywords_by_advertiser(new);
+    let advertisers: BTreeSet<&String> = old_keywords
+        .keys()
+        .chain(new_keywords.keys())
+        .copied()
+        .collect();
+    let empty = BTreeSet::new();
+    for advertiser in advertisers {
+        let before = old_keywords.get(advertiser).unwrap_or(&empty);
+        let after = new_keywords.get(advertiser).unwrap_or(&empty);
+        let changes = KeywordChanges {
+            gained: after.difference(before).map(|k| k.to_string()).collect(),
+            lost: before.difference(after).map(|k| k.to_string()).collect(),
+        };
+        if !changes.gained.is_empty() || !changes.lost.is_empty() {
+            diff.keywords.insert(advertiser.to_string(), changes);
+        }
+    }
+
+    // Collapsed keyword collisions that appeared
+    let old_collisions = collisions(old);
+    diff.new_collisions = collisions(new)
+        .into_iter()
+        .filter(|(keyword, _)| !old_collisions.contains_key(keyword))
+        .map(|(keyword, block_ids)| Collision { keyword, block_ids })
+        .collect();
+
+    // How the answers to a sample of prefixes change
+    let prefixes = match &options.prefixes {
+        Some(prefixes) => prefixes.clone(),
+        None => sample_prefixes(old, new, &diff, options.sample_size),
+    };
+    let old_index = BTreeAmpIndex::build(old)?;
+    let new_index = BTreeAmpIndex::build(new)?;
+    for prefix in &prefixes {
+        let before = old_index.query(prefix)?.first().map(|r| r.block_id);
+        let after = new_index.query(prefix)?.first().map(|r| r.block_id);
+        if before != after {
+            diff.answer_changes.push(AnswerChange {
+                prefix: prefix.clone(),
+                old: before,
+                new: after,
+            });
+        }
+    }
+    diff.prefixes_checked = prefixes.len();
+
+    Ok(diff)
+}
+
+fn summary(amp: &OriginalAmp) -> SuggestionSummary {
+    SuggestionSummary {
+        block_id: amp.block_id,
+        advertiser: amp.advertiser.clone(),
+        title: amp.title.clone(),
+    }
+}
+
+fn field_changes(old: &OriginalAmp, new: &OriginalAmp) -> Vec<FieldChange> {
+    let fields: [(&'static str, &String, &String); 4] = [
+        ("title", &old.title, &new.title),
+        ("url", &old.url, &new.url),
+        ("click_url", &old.click_url, &new.click_url),
+        ("impression_url", &old.impression_url, &new.impression_url),
+    ];
+    fields
+        .into_iter()
+        .filter(|(_, before, after)| before != after)
+        .map(|(field, before, after)| FieldChange {
+            block_id: new.block_id,
+            field,
+            old: before.clone(),
+            new: after.clone(),
+        })
+        .collect()
+}
+
+fn keywords_by_advertiser(amps: &[OriginalAmp]) -> HashMap<&String, BTreeSet<&str>> {
+    let mut out: HashMap<&String, BTreeSet<&str>> = HashMap::new();
+    for amp in amps {
+        out.entry(&amp.advertiser)
+            .or_default()
+            .extend(amp.keywords.iter().map(String::as_str));
+    }
+    out
+}
+
+/// Collapsed keywords produced by more than one suggestion, with their block ids. Keys are
+/// collapsed as the indexes do, so these are the keys only the first suggestion keeps.
+fn collisions(amps: &[OriginalAmp]) -> BTreeMap<String, Vec<i32>> {
+    let mut owners: BTreeMap<String, Vec<i32>> = BTreeMap::new();
+    for amp in amps {
+        for interval in collapse_intervals(&amp.keywords, &amp.full_keywords).intervals {
+            let ids = owners.entry(interval.key).or_default();
+            if !ids.contains(&amp.block_id) {
+                ids.push(amp.block_id);
+            }
+        }
+    }
+    owners.retain(|_, ids| ids.len() > 1);
+    owners
+}
+
+/// All the keywords gained or lost, plus `sample_size` keywords evenly spread over the
+/// sorted keywords of both payloads.
+fn sample_prefixes(
+    old: &[OriginalAmp],
+    new: &[OriginalAmp],
+    diff: &PayloadDiff,
+    sample_size: usize,
+) -> Vec<String> {
+    let mut prefixes: BTreeSet<String> = diff
+        .keywords
+        .values()
+        .flat_map(|c| c.gained.iter().chain(c.lost.iter()).cloned())
+        .collect();
+
+    let all: Vec<&String> = old
+        .iter()
+        .chain(new.iter())
+        .flat_map(|a| a.keywords.iter())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    if sample_size > 0 && !all.is_empty() {
+        let step = all.len().div_ceil(sample_size).max(1);
+        prefixes.extend(all.into_iter().step_by(step).cloned());
+    }
+    prefixes.into_iter().collect()
+}
+
+fn fmt_answer(answer: Option<i32>) -> String {
+    answer.map_or_else(|| "none".to_string(), |id| id.to_string())
+}
+
+impl fmt::Display for PayloadDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes ({} prefixes checked)", self.prefixes_checked);
+        }
+
+        writeln!(f, "Suggestions added: {}", self.added.len())?;
+        for s in &self.added {
+            writeln!(f, "  + [{}] {} - {}", s.block_id, s.advertiser, s.title)?;
+        }
+        writeln!(f, "Suggestions removed: {}", self.removed.len())?;
+        for s in &self.removed {
+            writeln!(f, "  - [{}] {} - {}", s.block_id, s.advertiser, s.title)?;
+        }
+
+        writeln!(f, "Keyword changes by advertiser: {}", self.keywords.len())?;
+        for (advertiser, changes) in &self.keywords {
+            writeln!(
+                f,
+                "  {}: +{} -{}",
+                advertiser,
+                changes.gained.len(),
+                changes.lost.len()
+            )?;
+            for keyword in &changes.gained {
+                writeln!(f, "    + {:?}", keyword)?;
+            }
+            for keyword in &changes.lost {
+                writeln!(f, "    - {:?}", keyword)?;
+            }
+        }
+
+        writeln!(f, "Field changes: {}", self.field_changes.len())?;
+        for c in &self.field_changes {
+            writeln!(
+                f,
+                "  [{}] {}: {:?} -> {:?}",
+                c.block_id, c.field, c.old, c.new
+            )?;
+        }
+
+        writeln!(
+            f,
+            "New collapsed keyword collisions: {}",
+            self.new_collisions.len()
+        )?;
+        for c in &self.new_collisions {
+            writeln!(f, "  {:?}: {:?}", c.keyword, c.block_ids)?;
+        }
+
+        writeln!(
+            f,
+            "Answer changes: {} of {} prefixes",
+            self.answer_changes.len(),
+            self.prefixes_checked
+        )?;
+        for c in &self.answer_changes {
+            writeln!(
+                f,
+                "  {:?}: {} -> {}",
+                c.prefix,
+                fmt_answer(c.old),
+                fmt_answer(c.new)
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+//! Query explanations: which collapsed keys an index looked at for a query, and why it
+//! answered the way it did.
+
+use crate::common::{FullKeyword, QueryPath};
+use serde::Serialize;
+use std::fmt;
+
+/// What an index made of a collapsed key it scanned
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// The key answered the query
+    Selected,
+    /// The query has fewer chars than the key's `min_prefix_len`
+    TooShort,
+    /// The query is a prefix the key's interval excludes, not one of its keywords
+    Excluded,
+    /// The key qualified, but its suggestion is blocked by the query filter
+    Blocked,
+    /// The key qualified, but the query time is outside its suggestion's time window
+    Inactive,
+    /// The key qualified, but a shorter one was preferred
+    Longer,
+}
+
+/// A collapsed key scanned while answering a query
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Candidate {
+    pub keyword: String,
+    pub min_prefix_len: usize,
+    pub block_id: i32,
+    /// The structure holding the key
+    pub path: QueryPath,
+    pub verdict: Verdict,
+}
+
+/// Where the full keyword of the answer came from
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FullKeywordSource {
+    /// The collapsed key is its own full keyword
+    SameAsKeyword,
+    /// Referred to by the collapsed key
+    Stored,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FullKeywordResolution {
+    pub full_keyword: String,
+    pub source: FullKeywordSource,
+}
+
+impl FullKeywordResolution {
+    pub(crate) fn from_full_keyword(full_keyword: &FullKeyword, keyword: &str) -> Self {
+        let source = match full_keyword {
+            FullKeyword::Same => FullKeywordSource::SameAsKeyword,
+            FullKeyword::Different(_) => FullKeywordSource::Stored,
+        };
+        FullKeywordResolution {
+            full_keyword: full_keyword.full_keyword(keyword),
+            source,
+        }
+    }
+}
+
+/// How an index answered a query
+#[derive(Clone, Debug, Serialize)]
+pub struct Explanation {
+    pub query: String,
+    /// Length of the query in chars, compared against `min_prefix_len`
+    pub query_len: usize,
+    /// The structure that answered the query, or was last searched on a miss
+    pub path: QueryPath,
+    /// Scanned keys, in scan order
+    pub candidates: Vec<Candidate>,
+    pub full_keyword: Option<FullKeywordResolution>,
+}
+
+impl Explanation {
+    pub(crate) fn new(query: &str, path: QueryPath) -> Self {
+        Explanation {
+            query: query.to_string(),
+            query_len: query.chars().count(),
+            path,
+            candidates: Vec::new(),
+            full_keyword: None,
+        }
+    }
+
+    pub(crate) fn scanned(
+        &mut self,
+        keyword: &str,
+        min_prefix_len: usize,
+        block_id: i32,
+        path: QueryPath,
+        verdict: Verdict,
+    ) {
+        self.candidates.push(Candidate {
+            keyword: keyword.to_string(),
+            min_prefix_len,
+            block_id,
+            path,
+            verdict,
+        });
+    }
+
+    /// The key that answered the query, if any
+    pub fn winner(&self) -> Option<&Candidate> {
+        self.candidates
+            .iter()
+            .find(|c| c.verdict == Verdict::Selected)
+    }
+
+    /// Keys rejected because the query is shorter than their `min_prefix_len`
+    pub fn too_short(&self) -> impl Iterator<Item = &Candidate> {
+        self.candidates
+            .iter()
+            .filter(|c| c.verdict == Verdict::TooShort)
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Query {:?} ({} chars)", self.query, self.query_len)?;
+        writeln!(f, "Served by: {}", self.path.as_str())?;
+        if self.candidates.is_empty() {
+            writeln!(f, "No collapsed key starts with the query")?;
+        }
+        for c in &self.candidates {
+            let verdict = match c.verdict {
+                Verdict::Selected => "selected".to_string(),
+                Verdict::TooShort => format!("rejected, needs {} chars", c.min_prefix_len),
+                Verdict::Excluded => "rejected, excluded from the key's interval".to_string(),
+                Verdict::Blocked => "skipped, blocked by the filter".to_string(),
+                Verdict::Inactive => "skipped, outside its time window".to_string(),
+                Verdict::Longer => "skipped, a shorter key qualifies".to_string(),
+            };
+            writeln!(
+                f,
+                "  [{}] {:?} (min {}, block {}): {}",
+                c.path.as_str(),
+                c.keyword,
+                c.min_prefix_len,
+                c.block_id,
+                verdict
+            )?;
+        }
+        match (self.winner(), &self.full_keyword) {
+            (Some(winner), Some(fk)) => {
+                let source = match fk.source {
+                    FullKeywordSource::SameAsKeyword => "same as the collapsed key",
+                    FullKeywordSource::Stored => "stored once, referred to by the collapsed key",
+                };
+                writeln!(
+                    f,
+                    "Winner: {:?} (block {})",
+                    winner.keyword, winner.block_id
+                )?;
+                writeln!(f, "Full keyword: {:?} ({})", fk.full_keyword, source)
+            }
+            _ => writeln!(f, "No suggestion"),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! FSST-style string compression: a table of up to 255 symbols of 1 to 8 bytes, trained on
+//! the strings to compress, replaces each symbol by a one-byte code. Bytes no symbol
+//! covers are escaped. Strings decompress on their own, without the other strings.
+//!
+//! See "FSST: Fast Random Access String Compression" (Boncz et al., VLDB 2020).
+
+use std::collections::HashMap;
+
+/// Code followed by a literal byte
+const ESCAPE: u8 = 255;
+const MAX_SYMBOLS: usize = 255;
+const MAX_SYMBOL_LEN: usize = 8;
+/// Training rounds, each recounting the symbols with the previous table
+const GENERATIONS: usize = 5;
+/// Bytes of strings sampled for training
+const SAMPLE_BYTES: usize = 1 << 16;
+
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    /// Symbol of each code, zero padded to 8 bytes so decoding always copies 8 bytes
+    symbols: Vec<[u8; MAX_SYMBOL_LEN]>,
+    lens: Vec<u8>,
+    /// Codes of the symbols starting with each byte, longest first
+    by_first_byte: HashMap<u8, Vec<u8>>,
+}
+
+impl SymbolTable {
+    fn new(symbols: &[&[u8]]) -> Self {
+        let mut by_first_byte: HashMap<u8, Vec<u8>> = HashMap::new();
+        for (code, symbol) in symbols.iter().enumerate() {
+            by_first_byte.entry(symbol[0]).or_default().push(code as u8);
+        }
+        for codes in by_first_byte.values_mut() {
+            codes.sort_by_key(|&code| std::cmp::Reverse(symbols[code as usize].len()));
+        }
+        SymbolTable {
+            symbols: symbols
+                .iter()
+                .map(|symbol| {
+                    let mut padded = [0; MAX_SYMBOL_LEN];
+                    padded[..symbol.len()].copy_from_slice(symbol);
+                    padded
+                })
+                .collect(),
+            lens: symbols.iter().map(|symbol| symbol.len() as u8).collect(),
+            by_first_byte,
+        }
+    }
+
+    fn symbol(&self, code: u8) -> &[u8] {
+        &self.symbols[code as usize][..self.lens[code as usize] as usize]
+    }
+
+    /// Train a table on a sample of `strings`
+    pub fn train<S: AsRef<str>>(strings: &[S]) -> Self {
+        let total: usize = strings.iter().map(|s| s.as_ref().len()).sum();
+        let step = total.div_ceil(SAMPLE_BYTES).max(1);
+        let sample: Vec<&[u8]> = strings
+            .iter()
+            .step_by(step)
+            .map(|s| s.as_ref().as_bytes())
+            .collect();
+
+        let mut table = SymbolTable::default();
+        for _ in 0..GENERATIONS {
+            // Count the symbols (or literal bytes) the current table parses the sample
+            // into, and the pairs of consecutive ones that could merge into a symbol
+            let mut counts: HashMap<&[u8], usize> = HashMap::new();
+            for &bytes in &sample {
+                let mut start = 0;
+                let mut prev: Option<usize> = None;
+                while start < bytes.len() {
+                    let len = table.longest_match(&bytes[start..]).map_or(1, <[u8]>::len);
+                    *counts.entry(&bytes[start..start + len]).or_default() += 1;
+                    if let Some(prev_start) = prev
+                        && start + len - prev_start <= MAX_SYMBOL_LEN
+                    {
+                        *counts.entry(&bytes[prev_start..start + len]).or_default() += 1;
+                    }
+                    prev = Some(start);
+                    start += len;
+                }
+            }
+
+            // Keep the symbols saving the most bytes
+            let mut candidates: Vec<(&[u8], usize)> = counts
+                .into_iter()
+                .map(|(symbol, count)| (symbol, count * symbol.len()))
+                .collect();
+            candidates.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+            candidates.truncate(MAX_SYMBOLS);
+            let symbols: Vec<&[u8]> = candidates.into_iter().map(|(s, _)| s).collect();
+            table = SymbolTable::new(&symbols);
+        }
+        table
+    }
+
+    fn longest_match(&self, bytes: &[u8]) -> Option<&[u8]> {
+        let codes = self.by_first_byte.get(bytes.first()?)?;
+        codes
+            .iter()
+            .map(|&code| self.symbol(code))
+            .find(|symbol| bytes.starts_with(symbol))
+    }
+
+    /// Append the compressed `text` to `out`
+    pub fn compress(&self, text: &str, out: &mut Vec<u8>) {
+        let bytes = text.as_bytes();
+        let mut start = 0;
+        while start < bytes.len() {
+            let rest = &bytes[start..];
+            let codes = self
+                .by_first_byte
+                .get(&rest[0])
+                .map_or(&[][..], Vec::as_slice);
+            match codes
+                .iter()
+                .find(|&&code| rest.starts_with(self.symbol(code)))
+            {
+                Some(&code) => {
+                    out.push(code);
+                    start += self.lens[code as usize] as usize;
+                }
+                None => {
+                    out.extend([ESCAPE, rest[0]]);
+                    start += 1;
+                }
+            }
+        }
+    }
+
+    /// Decompress a string compressed by this table
+    pub fn decompress(&self, compressed: &[u8]) -> String {
+        let mut out = Vec::with_capacity(compressed.len() * MAX_SYMBOL_LEN);
+        let mut i = 0;
+        while i < compressed.len() {
+            let code = compressed[i];
+            if code == ESCAPE {
+                out.push(compressed[i + 1]);
+                i += 2;
+            } else {
+                // Copying the whole padded symbol is cheaper than copying its length
+                let len = out.len();
+                out.extend_from_slice(&self.symbols[code as usize]);
+                out.truncate(len + self.lens[code as usize] as usize);
+                i += 1;
+            }
+        }
+        String::from_utf8(out).expect("compressed from a string")
+    }
+
+    /// Number of symbols
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Heap bytes held
+    pub fn size_bytes(&self) -> usize {
+        self.symbols.capacity() * MAX_SYMBOL_LEN
+            + self.lens.capacity()
+            + self
+                .by_first_byte
+                .values()
+                .map(|codes| codes.capacity() + 32)
+                .sum::<usize>()
+    }
+}
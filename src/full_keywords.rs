@@ -0,0 +1,124 @@
+//! Full keywords of the collapsed keys, shared by all the backends.
+//!
+//! Most collapsed keys are their own full keyword (e.g. "amazon fresh"), so they store
+//! nothing but `FullKeywordRef::SAME`. The others refer to one of the distinct full
+//! keywords of the index, each interned once through the string pool.
+
+use crate::common::{FullKeyword, StringPool};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The full keyword of a collapsed key
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FullKeywordRef(u32);
+
+impl FullKeywordRef {
+    /// The key is its own full keyword
+    pub const SAME: FullKeywordRef = FullKeywordRef(0);
+
+    pub fn is_same(self) -> bool {
+        self == Self::SAME
+    }
+
+    /// The reference as stored in a packed column
+    pub fn to_bits(self) -> u64 {
+        self.0 as u64
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        FullKeywordRef(bits as u32)
+    }
+}
+
+/// Builds `FullKeywords`, handing out a reference for each collapsed key
+#[derive(Debug, Default)]
+pub struct FullKeywordsBuilder {
+    values: Vec<Arc<str>>,
+    ids: HashMap<Arc<str>, u32>,
+}
+
+impl FullKeywordsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The reference to store with a collapsed key for its full keyword
+    pub fn push(&mut self, full_keyword: &FullKeyword, pool: &mut StringPool) -> FullKeywordRef {
+        let FullKeyword::Different(full_keyword) = full_keyword else {
+            return FullKeywordRef::SAME;
+        };
+        if let Some(&id) = self.ids.get(full_keyword.as_str()) {
+            return FullKeywordRef(id);
+        }
+        let value = pool.intern(full_keyword);
+        self.values.push(value.clone());
+        // 0 is `SAME`, so ids start at 1
+        let id = self.values.len() as u32;
+        self.ids.insert(value, id);
+        FullKeywordRef(id)
+    }
+
+    pub fn finish(mut self) -> FullKeywords {
+        self.values.shrink_to_fit();
+        FullKeywords {
+            values: self.values,
+        }
+    }
+}
+
+/// The distinct full keywords referred to by the collapsed keys of an index
+#[derive(Debug, Default)]
+pub struct FullKeywords {
+    values: Vec<Arc<str>>,
+}
+
+impl FullKeywords {
+    /// Number of distinct full keywords stored
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn get(&self, full_keyword: FullKeywordRef) -> FullKeyword {
+        match full_keyword.0 {
+            0 => FullKeyword::Same,
+            id => FullKeyword::Different(self.values[id as usize - 1].to_string()),
+        }
+    }
+
+    /// The full keyword of the collapsed key `keyword`
+    pub fn resolve(&self, full_keyword: FullKeywordRef, keyword: &str) -> String {
+        match full_keyword.0 {
+            0 => keyword.to_string(),
+            id => self.values[id as usize - 1].to_string(),
+        }
+    }
+
+    /// Heap bytes held, excluding the pooled strings
+    pub fn size_bytes(&self) -> usize {
+        self.values.capacity() * std::mem::size_of::<Arc<str>>()
+    }
+
+    /// Add the full keyword stats, given the reference of every collapsed key
+    pub fn add_stats(
+        &self,
+        refs: impl Iterator<Item = FullKeywordRef>,
+        stats: &mut HashMap<String, usize>,
+    ) {
+        let (mut implicit, mut referenced) = (0, 0);
+        for full_keyword in refs {
+            if full_keyword.is_same() {
+                implicit += 1;
+            } else {
+                referenced += 1;
+            }
+        }
+        stats.insert("full_keywords_count".into(), self.len());
+        stats.insert("full_keywords_implicit".into(), implicit);
+        stats.insert("full_keywords_referenced".into(), referenced);
+        stats.insert("full_keywords_bytes".into(), self.size_bytes());
+    }
+}
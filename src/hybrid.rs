@@ -1,31 +1,36 @@
 use crate::common::{
-    AmpIndexer, AmpResult, OriginalAmp, RunEndEncoding, collapse_keywords, extract_template,
+    AmpIndexBuilder, AmpIndexer, AmpQuery, AmpResult, BuildOptions, IntervalSink, KeywordEntry,
+    KeywordInterval, OriginalAmp, PoolRef, QueryContext, QueryPath, StringPool, add_intervals,
+    expand_keyword,
 };
+use crate::explain::{Explanation, FullKeywordResolution, Verdict};
+use crate::full_keywords::{FullKeywordRef, FullKeywords, FullKeywordsBuilder};
+use crate::infix::InfixIndex;
+use crate::suggestions::{SuggestionTable, SuggestionTableBuilder};
+use crate::url_params::UrlParams;
 use qp_trie::Trie;
 use std::collections::HashMap;
 
-/// Compact AMP suggestion with maximum dictionary encoding
-#[derive(Clone, Debug)]
-struct CompactAmpSuggestion {
-    title_id: u32, // Dictionary-encoded title
-    url_template_id: u32,
-    url_suffix: String,
-    click_url_template_id: u32,
-    click_url_suffix: String,
-    impression_url_template_id: u32,
-    impression_url_suffix: String,
-    advertiser_id: u32,
-    block_id: i32,
-    iab_category_id: u32, // Dictionary-encoded IAB category
-    icon_id: u32,         // Dictionary-encoded icon
-}
-
 /// Value stored in the hybrid index
 #[derive(Clone, Debug)]
 struct IndexValue {
     suggestion_idx: usize,
-    full_kw_idx: usize,
+    full_keyword: FullKeywordRef,
     min_prefix_len: usize,
+    /// Char lengths of the prefixes the key's interval excludes, ascending
+    excluded: Box<[usize]>,
+}
+
+impl IndexValue {
+    /// Whether the key answers its prefix of `len` chars
+    fn answers(&self, len: usize) -> bool {
+        len >= self.min_prefix_len && !self.excludes(len)
+    }
+
+    /// Whether the interval of the key excludes its prefix of `len` chars
+    fn excludes(&self, len: usize) -> bool {
+        self.excluded.binary_search(&len).is_ok()
+    }
 }
 
 /// Fast lookup cache for very short prefixes
@@ -47,21 +52,32 @@ impl ShortPrefixCache {
         self.exact_matches.insert(key.to_string(), value);
     }
 
-    fn lookup(&self, query: &str, query_len: usize) -> Option<&IndexValue> {
+    /// The shortest key starting with the query that qualifies and is `allowed`
+    fn lookup(
+        &self,
+        query: &str,
+        query_len: usize,
+        allowed: impl Fn(&IndexValue) -> bool,
+    ) -> Option<(&str, &IndexValue)> {
         // Try exact match first
-        if let Some(value) = self.exact_matches.get(query) {
-            if query_len >= value.min_prefix_len {
-                return Some(value);
-            }
+        if let Some((key, value)) = self.exact_matches.get_key_value(query)
+            && value.answers(query_len)
+            && allowed(value)
+        {
+            return Some((key, value));
         }
 
         // Try prefix matches - find the shortest key that starts with the query
-        let mut best_match: Option<&IndexValue> = None;
+        let mut best_match: Option<(&str, &IndexValue)> = None;
         let mut best_key_len = usize::MAX;
 
         for (key, value) in &self.exact_matches {
-            if key.starts_with(query) && query_len >= value.min_prefix_len && key.len() < best_key_len {
-                best_match = Some(value);
+            if key.starts_with(query)
+                && value.answers(query_len)
+                && key.len() < best_key_len
+                && allowed(value)
+            {
+                best_match = Some((key, value));
                 best_key_len = key.len();
             }
         }
@@ -75,204 +91,130 @@ pub struct HybridAmpIndex {
     /// QP-trie for efficient prefix matching of longer keys
     main_trie: Trie<Vec<u8>, IndexValue>,
 
-    /// Fast cache for very short prefixes (1-3 chars)
+    /// Fast cache for very short prefixes (up to `short_cache_max_chars`, 3 by default)
     short_cache: ShortPrefixCache,
 
     /// Compact suggestion storage with maximum dictionary encoding
-    suggestions: Vec<CompactAmpSuggestion>,
+    suggestions: SuggestionTable,
+
+    /// Full keywords of the keys that aren't their own
+    full_keywords: FullKeywords,
 
-    /// Run-end encoding for full keywords
-    full_keywords: RunEndEncoding,
+    /// Max length in chars of the keys held by the short prefix cache
+    short_cache_max_chars: usize,
 
-    /// Dictionary structures for all repeated fields
-    advertisers: HashMap<u32, String>,
-    titles: HashMap<u32, String>,
-    url_templates: HashMap<u32, String>,
-    click_url_templates: HashMap<u32, String>,
-    impression_url_templates: HashMap<u32, String>,
-    iab_categories: HashMap<u32, String>,
-    icons: HashMap<u32, String>,
+    /// Word-boundary suffixes of the full keywords, when enabled
+    infix: Option<InfixIndex>,
 
     /// Statistics
     keyword_count: usize,
 }
 
 impl AmpIndexer for HybridAmpIndex {
-    fn new() -> Self {
-        HybridAmpIndex {
-            main_trie: Trie::new(),
-            short_cache: ShortPrefixCache::new(),
-            suggestions: Vec::new(),
-            full_keywords: RunEndEncoding::new(),
-            advertisers: HashMap::new(),
-            titles: HashMap::new(),
-            url_templates: HashMap::new(),
-            click_url_templates: HashMap::new(),
-            impression_url_templates: HashMap::new(),
-            iab_categories: HashMap::new(),
-            icons: HashMap::new(),
-            keyword_count: 0,
-        }
-    }
+    type Builder<'p> = HybridIndexBuilder<'p>;
 
-    fn build(&mut self, amps: &[OriginalAmp]) -> Result<(), Box<dyn std::error::Error>> {
-        // Dictionary lookup tables for building phase
-        let mut advertiser_lookup = HashMap::new();
-        let mut title_lookup = HashMap::new();
-        let mut url_lookup = HashMap::new();
-        let mut click_lookup = HashMap::new();
-        let mut imp_lookup = HashMap::new();
-        let mut iab_lookup = HashMap::new();
-        let mut icon_lookup = HashMap::new();
-
-        for amp in amps {
-            // Dictionary encode all repeated fields - using static method to avoid borrowing conflicts
-            let advertiser_id = Self::intern_string_static(
-                &amp.advertiser,
-                &mut advertiser_lookup,
-                &mut self.advertisers,
-            );
-            let title_id =
-                Self::intern_string_static(&amp.title, &mut title_lookup, &mut self.titles);
-            let iab_id = Self::intern_string_static(
-                &amp.iab_category,
-                &mut iab_lookup,
-                &mut self.iab_categories,
-            );
-            let icon_id =
-                Self::intern_string_static(&amp.icon_id, &mut icon_lookup, &mut self.icons);
-
-            // URL template extraction
-            let (url_tid, url_suf) =
-                extract_template(&amp.url, &mut url_lookup, &mut self.url_templates);
-            let (clk_tid, clk_suf) = extract_template(
-                &amp.click_url,
-                &mut click_lookup,
-                &mut self.click_url_templates,
-            );
-            let (imp_tid, imp_suf) = extract_template(
-                &amp.impression_url,
-                &mut imp_lookup,
-                &mut self.impression_url_templates,
-            );
-
-            // Store compact suggestion
-            let sidx = self.suggestions.len();
-            self.suggestions.push(CompactAmpSuggestion {
-                title_id,
-                url_template_id: url_tid,
-                url_suffix: url_suf,
-                click_url_template_id: clk_tid,
-                click_url_suffix: clk_suf,
-                impression_url_template_id: imp_tid,
-                impression_url_suffix: imp_suf,
-                advertiser_id,
-                block_id: amp.block_id,
-                iab_category_id: iab_id,
-                icon_id,
-            });
-
-            // Encode full keywords
-            let fkw_start = self.full_keywords.indices.len();
-            if !amp.full_keywords.is_empty() {
-                for (full_kw, count) in &amp.full_keywords {
-                    self.full_keywords.add(full_kw.clone(), *count);
-                }
-            } else {
-                self.full_keywords.add(amp.advertiser.clone(), 1);
-            }
-
-            // Process collapsed keywords and distribute between cache and trie
-            if !amp.keywords.is_empty() {
-                for (i, (kw, min_pref)) in collapse_keywords(&amp.keywords).into_iter().enumerate()
-                {
-                    let value = IndexValue {
-                        suggestion_idx: sidx,
-                        full_kw_idx: fkw_start + i,
-                        min_prefix_len: min_pref,
-                    };
-
-                    let kw_chars: Vec<char> = kw.chars().collect();
-
-                    // Short keys (including those with spaces) go to cache, longer keys go to trie
-                    if kw_chars.len() <= 3 {
-                        self.short_cache.insert(&kw, value);
-                    } else {
-                        // Convert to bytes for QP-trie
-                        self.main_trie.insert(kw.as_bytes().to_vec(), value);
-                    }
-
-                    self.keyword_count += 1;
-                }
-            }
-        }
-
-        // Optimize cache by sorting entries by relevance
-        self.optimize_cache();
+    fn builder(options: &BuildOptions) -> Self::Builder<'static> {
+        HybridIndexBuilder::new(PoolRef::Owned(StringPool::new()), options)
+    }
 
-        self.suggestions.shrink_to_fit();
+    fn builder_with_pool<'p>(
+        pool: &'p mut StringPool,
+        options: &BuildOptions,
+    ) -> Self::Builder<'p> {
+        HybridIndexBuilder::new(PoolRef::Borrowed(pool), options)
+    }
+}
 
-        Ok(())
+impl AmpQuery for HybridAmpIndex {
+    fn query_with_context(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<Vec<AmpResult>, Box<dyn std::error::Error>> {
+        Ok(self.query_with_path(query, ctx)?.0)
     }
 
-    fn query(&self, query: &str) -> Result<Vec<AmpResult>, Box<dyn std::error::Error>> {
+    fn query_with_path(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<(Vec<AmpResult>, QueryPath), Box<dyn std::error::Error>> {
         let mut results = Vec::new();
 
         // Don't trim the query - preserve spaces as they might be significant
         let qlen = query.chars().count();
 
         // First try the short prefix cache for very fast lookups
-        if qlen <= 3 {
-            if let Some(value) = self.short_cache.lookup(query, qlen) {
-                if qlen >= value.min_prefix_len {
-                    self.build_result(value.suggestion_idx, value.full_kw_idx, &mut results)?;
-                    return Ok(results);
-                }
-            }
+        if qlen <= self.short_cache_max_chars
+            && let Some((key, value)) = self.short_cache.lookup(query, qlen, |value| {
+                self.rejection(value.suggestion_idx, ctx).is_none()
+            })
+        {
+            self.build_result(
+                value.suggestion_idx,
+                self.full_keywords.resolve(value.full_keyword, key),
+                &ctx.url_params,
+                &mut results,
+            )?;
+            return Ok((results, QueryPath::ShortCache));
         }
 
         // Fall back to trie for longer queries or cache misses
-        let query_bytes = query.as_bytes();
-
-        // Try exact match first
-        if let Some(value) = self.main_trie.get(query_bytes) {
-            if qlen >= value.min_prefix_len {
-                self.build_result(value.suggestion_idx, value.full_kw_idx, &mut results)?;
-                return Ok(results);
-            }
+        if let Some((key, value)) = self.trie_lookup(query, qlen, ctx) {
+            self.build_result(
+                value.suggestion_idx,
+                self.full_keywords.resolve(value.full_keyword, &key),
+                &ctx.url_params,
+                &mut results,
+            )?;
+            return Ok((results, QueryPath::Tree));
         }
 
-        // Prefix search with optimization for shortest match
-        let mut best_match: Option<&IndexValue> = None;
-        let mut best_len = usize::MAX;
-
-        for (key, value) in self.main_trie.iter_prefix(query_bytes) {
-            if qlen >= value.min_prefix_len && key.len() < best_len {
-                best_match = Some(value);
-                best_len = key.len();
+        // Then to the suffixes of full keywords
+        if let Some(infix) = &self.infix {
+            if let Some(m) = infix.find(query, |idx| self.rejection(idx, ctx)) {
+                self.build_result(
+                    m.suggestion_idx,
+                    m.full_keyword.to_string(),
+                    &ctx.url_params,
+                    &mut results,
+                )?;
             }
+            return Ok((results, QueryPath::Infix));
         }
 
-        if let Some(value) = best_match {
-            self.build_result(value.suggestion_idx, value.full_kw_idx, &mut results)?;
-        }
-
-        Ok(results)
+        Ok((results, QueryPath::Tree))
     }
 
     fn stats(&self) -> HashMap<String, usize> {
         let mut stats = HashMap::new();
         stats.insert("keyword_count".into(), self.keyword_count);
-        stats.insert("suggestions_count".into(), self.suggestions.len());
         stats.insert(
-            "full_keywords_count".into(),
-            self.full_keywords.indices.len(),
+            "keyword_exclusions".into(),
+            self.short_cache
+                .exact_matches
+                .values()
+                .chain(self.main_trie.values())
+                .map(|value| value.excluded.len())
+                .sum(),
+        );
+        stats.insert("keyword_structure_bytes".into(), self.keyword_bytes());
+        let refs = self
+            .short_cache
+            .exact_matches
+            .values()
+            .chain(self.main_trie.values())
+            .map(|value| value.full_keyword);
+        self.full_keywords.add_stats(refs, &mut stats);
+        self.suggestions.add_stats(&mut stats);
+        stats.insert(
+            "infix_entries".into(),
+            self.infix.as_ref().map_or(0, |infix| infix.len()),
+        );
+        stats.insert(
+            "infix_bytes".into(),
+            self.infix.as_ref().map_or(0, |infix| infix.size_bytes()),
         );
-        stats.insert("advertisers_count".into(), self.advertisers.len());
-        stats.insert("titles_count".into(), self.titles.len());
-        stats.insert("url_templates_count".into(), self.url_templates.len());
-        stats.insert("iab_categories_count".into(), self.iab_categories.len());
-        stats.insert("icons_count".into(), self.icons.len());
         stats.insert(
             "cache_exact_matches".into(),
             self.short_cache.exact_matches.len(),
@@ -285,95 +227,329 @@ impl AmpIndexer for HybridAmpIndex {
         );
         stats
     }
+
+    fn explain_with_context(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<Explanation, Box<dyn std::error::Error>> {
+        let mut explanation = Explanation::new(query, QueryPath::ShortCache);
+        let qlen = explanation.query_len;
+        let mut winner = None;
+
+        // Same lookups as `query_with_path`
+        if qlen <= self.short_cache_max_chars {
+            winner = self
+                .short_cache
+                .lookup(query, qlen, |value| {
+                    self.rejection(value.suggestion_idx, ctx).is_none()
+                })
+                .map(|(key, value)| (key.to_string(), value));
+            let candidates = self
+                .short_cache
+                .exact_matches
+                .iter()
+                .filter(|(key, _)| key.starts_with(query))
+                .map(|(key, value)| (key.clone(), value));
+            self.explain_candidates(
+                &mut explanation,
+                candidates,
+                winner.as_ref().map(|(_, value)| *value),
+                ctx,
+                QueryPath::ShortCache,
+            );
+        }
+
+        if winner.is_none() {
+            explanation.path = QueryPath::Tree;
+            winner = self.trie_lookup(query, qlen, ctx);
+            let winning_value = winner.as_ref().map(|(_, value)| *value);
+            match (winning_value, self.main_trie.get(query.as_bytes())) {
+                // An exact match is served without scanning the other keys
+                (Some(value), Some(exact)) if std::ptr::eq(value, exact) => {
+                    let candidates = std::iter::once((query.to_string(), value));
+                    self.explain_candidates(
+                        &mut explanation,
+                        candidates,
+                        winning_value,
+                        ctx,
+                        QueryPath::Tree,
+                    );
+                }
+                _ => {
+                    let candidates = self
+                        .main_trie
+                        .iter_prefix(query.as_bytes())
+                        .map(|(key, value)| (String::from_utf8_lossy(key).into_owned(), value));
+                    self.explain_candidates(
+                        &mut explanation,
+                        candidates,
+                        winning_value,
+                        ctx,
+                        QueryPath::Tree,
+                    );
+                }
+            }
+        }
+
+        if let Some((key, value)) = &winner {
+            explanation.full_keyword = Some(FullKeywordResolution::from_full_keyword(
+                &self.full_keywords.get(value.full_keyword),
+                key,
+            ));
+        } else if let Some(infix) = &self.infix {
+            explanation.path = QueryPath::Infix;
+            infix.explain(
+                &mut explanation,
+                query,
+                |idx| self.rejection(idx, ctx),
+                |idx| self.suggestions.block_id(idx),
+            );
+        }
+        Ok(explanation)
+    }
+
+    fn iter_prefix<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = KeywordEntry> + 'a> {
+        // The short cache is unordered, so merge both parts and sort
+        let cached = self
+            .short_cache
+            .exact_matches
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone(), value));
+        let in_trie = self
+            .main_trie
+            .iter_prefix(prefix.as_bytes())
+            .map(|(key, value)| (String::from_utf8_lossy(key).into_owned(), value));
+
+        let mut entries: Vec<KeywordEntry> = cached
+            .chain(in_trie)
+            .map(|(key, value)| {
+                let block_id = self.suggestions.block_id(value.suggestion_idx);
+                (key, value.min_prefix_len, block_id)
+            })
+            .collect();
+        entries.sort();
+        Box::new(entries.into_iter())
+    }
+
+    fn keywords_for(&self, block_id: i32) -> Vec<String> {
+        let values = self
+            .short_cache
+            .exact_matches
+            .iter()
+            .map(|(key, value)| (key.as_str(), value))
+            .chain(
+                self.main_trie
+                    .iter()
+                    .map(|(key, value)| (std::str::from_utf8(key).unwrap_or_default(), value)),
+            );
+        let mut keywords: Vec<String> = values
+            .filter(|(_, value)| self.suggestions.block_id(value.suggestion_idx) == block_id)
+            .flat_map(|(key, value)| {
+                expand_keyword(key, value.min_prefix_len)
+                    .into_iter()
+                    .zip(value.min_prefix_len..)
+                    .filter(|(_, len)| !value.excludes(*len))
+                    .map(|(keyword, _)| keyword)
+            })
+            .collect();
+        keywords.sort();
+        keywords
+    }
 }
 
-impl HybridAmpIndex {
-    /// Intern a string into a dictionary, returning its ID (static version to avoid borrowing conflicts)
-    fn intern_string_static(
-        value: &str,
-        lookup: &mut HashMap<String, u32>,
-        dict: &mut HashMap<u32, String>,
-    ) -> u32 {
-        if let Some(&id) = lookup.get(value) {
-            id
+/// Builds a `HybridAmpIndex` one record at a time
+pub struct HybridIndexBuilder<'p> {
+    index: HybridAmpIndex,
+    pool: PoolRef<'p>,
+    full_keywords: FullKeywordsBuilder,
+    suggestions: SuggestionTableBuilder,
+}
+
+impl<'p> HybridIndexBuilder<'p> {
+    fn new(pool: PoolRef<'p>, options: &BuildOptions) -> Self {
+        HybridIndexBuilder {
+            index: HybridAmpIndex::empty(
+                options.short_cache_max_chars,
+                options.infix_min_chars.map(InfixIndex::new),
+            ),
+            pool,
+            full_keywords: FullKeywordsBuilder::new(),
+            suggestions: SuggestionTableBuilder::new().compression(options.string_compression),
+        }
+    }
+}
+
+impl AmpIndexBuilder for HybridIndexBuilder<'_> {
+    type Index = HybridAmpIndex;
+
+    fn add(&mut self, amp: &OriginalAmp) -> Result<(), Box<dyn std::error::Error>> {
+        let index = &mut self.index;
+        let pool = &mut *self.pool;
+
+        let sidx = self.suggestions.push(amp, pool);
+
+        add_intervals(index, &mut self.full_keywords, pool, sidx, amp)?;
+
+        if let Some(infix) = &mut index.infix {
+            infix.add_suggestion(sidx, amp, pool);
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<HybridAmpIndex, Box<dyn std::error::Error>> {
+        self.index.full_keywords = self.full_keywords.finish();
+        self.index.suggestions = self.suggestions.finish();
+        if let Some(infix) = &mut self.index.infix {
+            infix.finish();
+        }
+
+        Ok(self.index)
+    }
+}
+
+impl IntervalSink for HybridAmpIndex {
+    fn contains_key(&self, key: &str) -> bool {
+        self.short_cache.exact_matches.contains_key(key)
+            || self.main_trie.contains_key(key.as_bytes())
+    }
+
+    fn insert(
+        &mut self,
+        suggestion_idx: usize,
+        interval: KeywordInterval,
+        full_keyword: FullKeywordRef,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let value = IndexValue {
+            suggestion_idx,
+            full_keyword,
+            min_prefix_len: interval.min_len,
+            excluded: interval.excluded.into_boxed_slice(),
+        };
+
+        // Short keys (including those with spaces) go to cache, longer keys go to trie
+        if interval.key.chars().count() <= self.short_cache_max_chars {
+            self.short_cache.insert(&interval.key, value);
         } else {
-            let id = lookup.len() as u32;
-            lookup.insert(value.to_string(), id);
-            dict.insert(id, value.to_string());
-            id
+            // Convert to bytes for QP-trie
+            self.main_trie.insert(interval.key.into_bytes(), value);
+        }
+
+        self.keyword_count += 1;
+        Ok(())
+    }
+}
+
+impl HybridAmpIndex {
+    /// An empty index, only ever handed to a builder
+    fn empty(short_cache_max_chars: usize, infix: Option<InfixIndex>) -> Self {
+        HybridAmpIndex {
+            main_trie: Trie::new(),
+            short_cache: ShortPrefixCache::new(),
+            suggestions: SuggestionTable::default(),
+            full_keywords: FullKeywords::default(),
+            short_cache_max_chars,
+            infix,
+            keyword_count: 0,
         }
     }
 
-    /// Optimize the cache by sorting and deduplicating entries
-    fn optimize_cache(&mut self) {
-        // The new cache structure doesn't need optimization since it uses exact matches only
-        // No duplicates possible with HashMap
+    /// Heap bytes of the keyword structure: the keys and values of the trie, without its
+    /// inner nodes, and the short prefix cache
+    pub fn keyword_bytes(&self) -> usize {
+        let trie: usize = self
+            .main_trie
+            .iter()
+            .map(|(key, value)| {
+                std::mem::size_of::<(Vec<u8>, IndexValue)>()
+                    + key.capacity()
+                    + std::mem::size_of_val(&*value.excluded)
+            })
+            .sum();
+        let cache = &self.short_cache.exact_matches;
+        trie + cache.capacity() * std::mem::size_of::<(String, IndexValue)>()
+            + cache
+                .iter()
+                .map(|(key, value)| key.capacity() + std::mem::size_of_val(&*value.excluded))
+                .sum::<usize>()
     }
 
-    /// Build a result from the compact storage
-    fn build_result(
+    /// The shortest trie key starting with the query that qualifies and isn't filtered out
+    fn trie_lookup(
         &self,
-        sugg_idx: usize,
-        fkw_idx: usize,
-        results: &mut Vec<AmpResult>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(sug) = self.suggestions.get(sugg_idx) {
-            // Reconstruct all fields from dictionaries
-            let title = self.titles.get(&sug.title_id).cloned().unwrap_or_default();
-            let advertiser = self
-                .advertisers
-                .get(&sug.advertiser_id)
-                .cloned()
-                .unwrap_or_default();
-            let iab_category = self
-                .iab_categories
-                .get(&sug.iab_category_id)
-                .cloned()
-                .unwrap_or_default();
-            let icon = self.icons.get(&sug.icon_id).cloned().unwrap_or_default();
-
-            let full_keyword = match self.full_keywords.get(fkw_idx) {
-                Some(kw) => kw.to_string(),
-                None => advertiser.clone(),
-            };
+        query: &str,
+        qlen: usize,
+        ctx: &QueryContext,
+    ) -> Option<(String, &IndexValue)> {
+        let query_bytes = query.as_bytes();
 
-            // Reconstruct URLs
-            let url =
-                self.reconstruct_url(sug.url_template_id, &sug.url_suffix, &self.url_templates);
-            let click_url = self.reconstruct_url(
-                sug.click_url_template_id,
-                &sug.click_url_suffix,
-                &self.click_url_templates,
-            );
-            let impression_url = self.reconstruct_url(
-                sug.impression_url_template_id,
-                &sug.impression_url_suffix,
-                &self.impression_url_templates,
-            );
+        // Try exact match first
+        if let Some(value) = self.main_trie.get(query_bytes)
+            && value.answers(qlen)
+            && self.rejection(value.suggestion_idx, ctx).is_none()
+        {
+            return Some((query.to_string(), value));
+        }
+
+        // Prefix search with optimization for shortest match
+        let mut best_match: Option<(&[u8], &IndexValue)> = None;
+
+        for (key, value) in self.main_trie.iter_prefix(query_bytes) {
+            if value.answers(qlen)
+                && best_match.is_none_or(|(best, _)| key.len() < best.len())
+                && self.rejection(value.suggestion_idx, ctx).is_none()
+            {
+                best_match = Some((key, value));
+            }
+        }
+
+        best_match.map(|(key, value)| (String::from_utf8_lossy(key).into_owned(), value))
+    }
 
-            results.push(AmpResult {
-                title,
-                url,
-                click_url,
-                impression_url,
-                advertiser,
-                block_id: sug.block_id,
-                iab_category,
-                icon,
-                full_keyword,
-            });
+    /// Why `ctx` skips the suggestion at `sugg_idx`, if it does
+    fn rejection(&self, sugg_idx: usize, ctx: &QueryContext) -> Option<Verdict> {
+        self.suggestions.rejection(sugg_idx, ctx)
+    }
+
+    /// Record the keys of one structure that start with the query, sorted
+    fn explain_candidates<'a>(
+        &self,
+        explanation: &mut Explanation,
+        candidates: impl Iterator<Item = (String, &'a IndexValue)>,
+        winner: Option<&IndexValue>,
+        ctx: &QueryContext,
+        path: QueryPath,
+    ) {
+        let mut candidates: Vec<_> = candidates.collect();
+        candidates.sort_by(|a, b| a.0.cmp(&b.0));
+        for (key, value) in candidates {
+            let verdict = if winner.is_some_and(|w| std::ptr::eq(w, value)) {
+                Verdict::Selected
+            } else if explanation.query_len < value.min_prefix_len {
+                Verdict::TooShort
+            } else if value.excludes(explanation.query_len) {
+                Verdict::Excluded
+            } else if let Some(verdict) = self.rejection(value.suggestion_idx, ctx) {
+                verdict
+            } else {
+                Verdict::Longer
+            };
+            let block_id = self.suggestions.block_id(value.suggestion_idx);
+            explanation.scanned(&key, value.min_prefix_len, block_id, path, verdict);
         }
-        Ok(())
     }
 
-    fn reconstruct_url(
+    /// Build a result from the compact storage
+    fn build_result(
         &self,
-        template_id: u32,
-        suffix: &str,
-        dict: &HashMap<u32, String>,
-    ) -> String {
-        dict.get(&template_id)
-            .map(|t| format!("{}{}", t, suffix))
-            .unwrap_or_else(|| suffix.to_string())
+        sugg_idx: usize,
+        full_keyword: String,
+        params: &UrlParams,
+        results: &mut Vec<AmpResult>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        results.push(self.suggestions.result(sugg_idx, full_keyword, params));
+        Ok(())
     }
 }
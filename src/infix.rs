@@ -0,0 +1,168 @@
+//! Secondary index over the word-boundary suffixes of full keywords, so that "prime video"
+//! finds "amazon prime video" and "fresh" finds "amazon fresh".
+//!
+//! Every backend keeps one next to its keyword index when `BuildOptions::infix_min_chars`
+//! is set. Its matches rank below prefix matches: it is only searched when the keyword
+//! index has no answer.
+
+use crate::common::{OriginalAmp, QueryPath, StringPool, pointwise_full_keywords};
+use crate::explain::{Explanation, FullKeywordResolution, FullKeywordSource, Verdict};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+/// The suffixes of `full_keyword` starting at each word but the first
+/// e.g. "amazon prime video" → ["prime video", "video"]
+pub fn word_suffixes(full_keyword: &str) -> impl Iterator<Item = &str> {
+    full_keyword
+        .char_indices()
+        .zip(full_keyword.chars().skip(1))
+        .filter(|&((_, c), next)| c == ' ' && next != ' ')
+        .map(move |((i, _), _)| &full_keyword[i + 1..])
+}
+
+struct InfixEntry {
+    suffix: Box<str>,
+    suggestion_idx: u32,
+    full_keyword: Arc<str>,
+}
+
+/// A word-boundary suffix matching a query
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InfixMatch<'a> {
+    pub suffix: &'a str,
+    pub suggestion_idx: usize,
+    pub full_keyword: &'a str,
+}
+
+/// Word-boundary suffixes of full keywords, sorted, each with its suggestion
+pub struct InfixIndex {
+    min_chars: usize,
+    entries: Vec<InfixEntry>,
+}
+
+impl InfixIndex {
+    /// An empty index answering queries of at least `min_chars` chars
+    pub fn new(min_chars: usize) -> Self {
+        InfixIndex {
+            min_chars,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn min_chars(&self) -> usize {
+        self.min_chars
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Heap bytes of the entries and their suffixes; the full keywords are interned
+    pub fn size_bytes(&self) -> usize {
+        self.entries.capacity() * std::mem::size_of::<InfixEntry>()
+            + self.entries.iter().map(|e| e.suffix.len()).sum::<usize>()
+    }
+
+    /// Index the word-boundary suffixes of `full_keyword` for the suggestion `suggestion_idx`
+    pub fn add(&mut self, suggestion_idx: usize, full_keyword: &str, pool: &mut StringPool) {
+        let mut interned = None;
+        for suffix in word_suffixes(full_keyword) {
+            let full_keyword = interned.get_or_insert_with(|| pool.intern(full_keyword));
+            self.entries.push(InfixEntry {
+                suffix: suffix.into(),
+                suggestion_idx: suggestion_idx as u32,
+                full_keyword: Arc::clone(full_keyword),
+            });
+        }
+    }
+
+    /// Index the word-boundary suffixes of the full keywords of `amp`, one per keyword, so
+    /// that keywords past the end of its runs count as their own full keyword
+    pub fn add_suggestion(
+        &mut self,
+        suggestion_idx: usize,
+        amp: &OriginalAmp,
+        pool: &mut StringPool,
+    ) {
+        let full_keywords: BTreeSet<&str> =
+            pointwise_full_keywords(&amp.keywords, &amp.full_keywords)
+                .into_iter()
+                .map(|(_, full_keyword)| full_keyword)
+                .collect();
+        for full_keyword in full_keywords {
+            self.add(suggestion_idx, full_keyword, pool);
+        }
+    }
+
+    /// Sort the entries, keeping one per suffix and suggestion; called once all are added
+    pub fn finish(&mut self) {
+        self.entries
+            .sort_by(|a, b| (&a.suffix, a.suggestion_idx).cmp(&(&b.suffix, b.suggestion_idx)));
+        self.entries
+            .dedup_by(|a, b| a.suffix == b.suffix && a.suggestion_idx == b.suggestion_idx);
+        self.entries.shrink_to_fit();
+    }
+
+    /// Suffixes starting with `query`, in key order. Queries shorter than `min_chars`
+    /// match nothing.
+    pub fn matches<'a>(&'a self, query: &'a str) -> impl Iterator<Item = InfixMatch<'a>> {
+        let start = if query.chars().count() < self.min_chars {
+            self.entries.len()
+        } else {
+            self.entries.partition_point(|e| &*e.suffix < query)
+        };
+        self.entries[start..]
+            .iter()
+            .take_while(move |e| e.suffix.starts_with(query))
+            .map(|e| InfixMatch {
+                suffix: &e.suffix,
+                suggestion_idx: e.suggestion_idx as usize,
+                full_keyword: &e.full_keyword,
+            })
+    }
+
+    /// The first match whose suggestion isn't skipped by `rejection`
+    pub fn find<'a>(
+        &'a self,
+        query: &'a str,
+        rejection: impl Fn(usize) -> Option<Verdict>,
+    ) -> Option<InfixMatch<'a>> {
+        self.matches(query)
+            .find(|m| rejection(m.suggestion_idx).is_none())
+    }
+
+    /// Same scan as `find`, recording the matches up to the answer in `explanation`
+    pub(crate) fn explain<'a>(
+        &'a self,
+        explanation: &mut Explanation,
+        query: &'a str,
+        rejection: impl Fn(usize) -> Option<Verdict>,
+        block_id: impl Fn(usize) -> i32,
+    ) -> Option<InfixMatch<'a>> {
+        for m in self.matches(query) {
+            let block_id = block_id(m.suggestion_idx);
+            let verdict = rejection(m.suggestion_idx);
+            let path = QueryPath::Infix;
+            explanation.scanned(
+                m.suffix,
+                self.min_chars,
+                block_id,
+                path,
+                verdict.unwrap_or(Verdict::Selected),
+            );
+            if verdict.is_none() {
+                explanation.path = path;
+                explanation.full_keyword = Some(FullKeywordResolution {
+                    full_keyword: m.full_keyword.to_string(),
+                    source: FullKeywordSource::Stored,
+                });
+                return Some(m);
+            }
+        }
+        None
+    }
+}
@@ -1,7 +1,28 @@
+pub mod analysis;
+pub mod bits;
 pub mod blart;
 pub mod btree;
 pub mod common;
+pub mod diff;
+pub mod explain;
+pub mod fsst;
+pub mod full_keywords;
 pub mod hybrid;
+pub mod infix;
+pub mod louds;
+pub mod memory_report;
+pub mod metrics;
+pub mod mph;
+pub mod multi;
+pub mod prefix_table;
+pub mod registry;
+pub mod remote_settings;
+pub mod schedule;
+pub mod sorted_array;
+pub mod stream;
+pub mod suggestions;
+pub mod url_params;
+pub mod workload;
 
 #[cfg(feature = "python")]
 pub mod python_bridge;
@@ -13,8 +34,22 @@ use std::path::Path;
 
 pub use blart::BlartAmpIndex;
 pub use btree::BTreeAmpIndex;
-pub use common::{AmpIndexer, AmpResult, OriginalAmp};
+pub use common::{
+    AmpIndexBuilder, AmpIndexer, AmpQuery, AmpResult, BuildOptions, OriginalAmp, QueryContext,
+    QueryPath, StringCompression, StringPool, TimeWindow,
+};
+pub use explain::Explanation;
 pub use hybrid::HybridAmpIndex;
+pub use louds::LoudsAmpIndex;
+pub use metrics::{InstrumentedIndex, MetricsSnapshot};
+pub use multi::{FormFactor, IndexKey, MultiIndex};
+pub use prefix_table::PrefixTableAmpIndex;
+pub use registry::{BackendRegistry, DynAmpIndex, IndexConfig};
+pub use remote_settings::{FileRecordSource, RecordSource};
+pub use schedule::{Clock, Schedule, SystemClock};
+pub use sorted_array::SortedArrayAmpIndex;
+pub use stream::{build_from_file, stream_amp_data};
+pub use url_params::{UrlParam, UrlParams};
 
 /// Utility function to load AMP data from a JSON file
 pub fn load_amp_data<P: AsRef<Path>>(path: P) -> Result<Vec<OriginalAmp>, Box<dyn Error>> {
@@ -0,0 +1,496 @@
+use crate::bits::{BitVec, PackedInts};
+use crate::common::{
+    AmpIndexBuilder, AmpIndexer, AmpQuery, AmpResult, BuildOptions, FullKeyword, KeywordEntry,
+    OriginalAmp, PendingKey, PoolRef, QueryContext, QueryPath, StringPool, add_intervals,
+    expand_keyword,
+};
+use crate::explain::{Explanation, FullKeywordResolution, Verdict};
+use crate::full_keywords::{FullKeywordRef, FullKeywords, FullKeywordsBuilder};
+use crate::infix::InfixIndex;
+use crate::suggestions::{SuggestionTable, SuggestionTableBuilder};
+use crate::url_params::UrlParams;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// Byte-wise trie of the collapsed keywords in LOUDS (level-order unary degree sequence)
+/// form.
+///
+/// Nodes are numbered in breadth-first order, the root being 0. `louds` holds `10` for a
+/// virtual super-root, then `1` per child followed by a `0` for every node in order, so
+/// the children of node `n` are described between its `n`-th and `n + 1`-th zeros. Node
+/// `n > 0` is the `n`-th one and its edge label is `labels[n - 1]`; siblings are sorted
+/// by label, so a preorder walk lists the keys in byte order.
+struct LoudsTrie {
+    louds: BitVec,
+    labels: Vec<u8>,
+    /// Whether the path to each node is a key
+    terminal: BitVec,
+}
+
+impl LoudsTrie {
+    /// Encode the keys, which must be sorted and distinct. Also returns the position in
+    /// `keys` of each key in trie order, the order of the value positions.
+    fn new<K: AsRef<[u8]>>(keys: &[K]) -> (Self, Vec<usize>) {
+        let mut louds = BitVec::new();
+        let mut labels = Vec::new();
+        let mut terminal = BitVec::new();
+        let mut order = Vec::with_capacity(keys.len());
+        louds.push(true);
+        louds.push(false);
+
+        // Each node stands for the range of keys sharing its path of length `depth`
+        let mut queue = VecDeque::from([(0, keys.len(), 0)]);
+        while let Some((lo, hi, depth)) = queue.pop_front() {
+            let mut i = lo;
+            // The key ending here sorts first
+            let is_key = i < hi && keys[i].as_ref().len() == depth;
+            terminal.push(is_key);
+            if is_key {
+                order.push(i);
+                i += 1;
+            }
+            while i < hi {
+                let label = keys[i].as_ref()[depth];
+                let mut j = i + 1;
+                while j < hi && keys[j].as_ref()[depth] == label {
+                    j += 1;
+                }
+                louds.push(true);
+                labels.push(label);
+                queue.push_back((i, j, depth + 1));
+                i = j;
+            }
+            louds.push(false);
+        }
+
+        louds.finish();
+        terminal.finish();
+        labels.shrink_to_fit();
+        let trie = LoudsTrie {
+            louds,
+            labels,
+            terminal,
+        };
+        (trie, order)
+    }
+
+    /// Ids of the children of `node`, as a range
+    fn children(&self, node: usize) -> std::ops::Range<usize> {
+        let start = self.louds.select0(node).unwrap() + 1;
+        let end = self.louds.select0(node + 1).unwrap();
+        // `start` is preceded by `node + 1` zeros, the rest are ones
+        let first = start - node - 1;
+        first..first + (end - start)
+    }
+
+    fn label(&self, node: usize) -> u8 {
+        self.labels[node - 1]
+    }
+
+    /// The node reached by following `path` from the root
+    fn descend(&self, path: &[u8]) -> Option<usize> {
+        let mut node = 0;
+        for &byte in path {
+            let children = self.children(node);
+            let labels = &self.labels[children.start - 1..children.end - 1];
+            node = children.start + labels.binary_search(&byte).ok()?;
+        }
+        Some(node)
+    }
+
+    /// Position of the value of the key ending at `node`, if one does
+    fn value_idx(&self, node: usize) -> Option<usize> {
+        self.terminal.get(node).then(|| self.terminal.rank1(node))
+    }
+
+    /// Walk the keys starting with `prefix`, in byte order
+    fn walk(&self, prefix: &str) -> Walk<'_> {
+        let stack = match self.descend(prefix.as_bytes()) {
+            Some(node) => vec![(node, prefix.len())],
+            None => Vec::new(),
+        };
+        Walk {
+            trie: self,
+            key: prefix.as_bytes().to_vec(),
+            root_depth: prefix.len(),
+            stack,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.terminal.count_ones()
+    }
+
+    fn node_count(&self) -> usize {
+        self.terminal.len()
+    }
+
+    /// Heap bytes held by the trie
+    fn size_bytes(&self) -> usize {
+        self.louds.size_bytes() + self.labels.capacity() + self.terminal.size_bytes()
+    }
+}
+
+/// Preorder walk of a subtree of a `LoudsTrie`, stopping at each key
+struct Walk<'a> {
+    trie: &'a LoudsTrie,
+    key: Vec<u8>,
+    root_depth: usize,
+    /// Nodes left to visit, with the length of their path
+    stack: Vec<(usize, usize)>,
+}
+
+impl Walk<'_> {
+    /// Move to the next key, returning the position of its value
+    fn next_key(&mut self) -> Option<usize> {
+        while let Some((node, depth)) = self.stack.pop() {
+            if depth > self.root_depth {
+                self.key.truncate(depth - 1);
+                self.key.push(self.trie.label(node));
+            }
+            self.stack.extend(
+                self.trie
+                    .children(node)
+                    .rev()
+                    .map(|child| (child, depth + 1)),
+            );
+            if let Some(value_idx) = self.trie.value_idx(node) {
+                return Some(value_idx);
+            }
+        }
+        None
+    }
+
+    /// The key last moved to
+    fn key(&self) -> &str {
+        // Keys are UTF-8 and end on a char boundary
+        std::str::from_utf8(&self.key).unwrap_or_default()
+    }
+}
+
+/// AMP Index using a succinct LOUDS trie: the keyword structure takes a few bits per key
+/// instead of a node allocation per key
+pub struct LoudsAmpIndex {
+    keyword_trie: LoudsTrie,
+
+    /// Value columns, by rank of the key's node among the key nodes
+    suggestion_idx: PackedInts,
+    min_prefix_len: PackedInts,
+    full_keyword_ref: PackedInts,
+    /// `(value position, char length)` of the prefixes the intervals exclude, sorted
+    excluded: Vec<(u32, u32)>,
+    full_keywords: FullKeywords,
+
+    /// Storage for suggestions
+    suggestions: SuggestionTable,
+
+    /// Word-boundary suffixes of the full keywords, when enabled
+    infix: Option<InfixIndex>,
+}
+
+impl AmpIndexer for LoudsAmpIndex {
+    type Builder<'p> = LoudsIndexBuilder<'p>;
+
+    fn builder(options: &BuildOptions) -> Self::Builder<'static> {
+        LoudsIndexBuilder::new(PoolRef::Owned(StringPool::new()), options)
+    }
+
+    fn builder_with_pool<'p>(
+        pool: &'p mut StringPool,
+        options: &BuildOptions,
+    ) -> Self::Builder<'p> {
+        LoudsIndexBuilder::new(PoolRef::Borrowed(pool), options)
+    }
+}
+
+impl AmpQuery for LoudsAmpIndex {
+    fn query_with_context(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<Vec<AmpResult>, Box<dyn std::error::Error>> {
+        Ok(self.query_with_path(query, ctx)?.0)
+    }
+
+    fn query_with_path(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<(Vec<AmpResult>, QueryPath), Box<dyn std::error::Error>> {
+        let mut results = Vec::new();
+        let query_len = query.chars().count();
+
+        // Keys come in order, so the first one that qualifies is the shortest
+        let mut walk = self.keyword_trie.walk(query);
+        while let Some(value_idx) = walk.next_key() {
+            let suggestion_idx = self.suggestion_idx.get(value_idx) as usize;
+            if query_len >= self.min_prefix_len.get(value_idx) as usize
+                && !self.excludes(value_idx, query_len)
+                && self.rejection(suggestion_idx, ctx).is_none()
+            {
+                let full_keyword = self.resolve_full_keyword(value_idx, walk.key());
+                self.build_result(suggestion_idx, full_keyword, &ctx.url_params, &mut results)?;
+                return Ok((results, QueryPath::Tree));
+            }
+        }
+
+        // Fall back to the suffixes of full keywords
+        if let Some(infix) = &self.infix {
+            if let Some(m) = infix.find(query, |idx| self.rejection(idx, ctx)) {
+                self.build_result(
+                    m.suggestion_idx,
+                    m.full_keyword.to_string(),
+                    &ctx.url_params,
+                    &mut results,
+                )?;
+            }
+            return Ok((results, QueryPath::Infix));
+        }
+
+        Ok((results, QueryPath::Tree))
+    }
+
+    fn stats(&self) -> HashMap<String, usize> {
+        let mut stats = HashMap::new();
+
+        stats.insert("keyword_count".into(), self.keyword_trie.len());
+        stats.insert("trie_nodes".into(), self.keyword_trie.node_count());
+        stats.insert("louds_bits".into(), self.keyword_trie.louds.len());
+        stats.insert("keyword_exclusions".into(), self.excluded.len());
+        stats.insert("keyword_structure_bytes".into(), self.keyword_bytes());
+        let refs = (0..self.full_keyword_ref.len()).map(|i| self.full_keyword_ref(i));
+        self.full_keywords.add_stats(refs, &mut stats);
+        self.suggestions.add_stats(&mut stats);
+        stats.insert(
+            "infix_entries".into(),
+            self.infix.as_ref().map_or(0, |infix| infix.len()),
+        );
+        stats.insert(
+            "infix_bytes".into(),
+            self.infix.as_ref().map_or(0, |infix| infix.size_bytes()),
+        );
+
+        stats
+    }
+
+    fn explain_with_context(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<Explanation, Box<dyn std::error::Error>> {
+        let mut explanation = Explanation::new(query, QueryPath::Tree);
+        let query_len = explanation.query_len;
+
+        // Same walk as `query_with_path`
+        let mut walk = self.keyword_trie.walk(query);
+        while let Some(value_idx) = walk.next_key() {
+            let keyword = walk.key();
+            let suggestion_idx = self.suggestion_idx.get(value_idx) as usize;
+            let min_prefix_len = self.min_prefix_len.get(value_idx) as usize;
+            let block_id = self.suggestions.block_id(suggestion_idx);
+            let verdict = if query_len < min_prefix_len {
+                Verdict::TooShort
+            } else if self.excludes(value_idx, query_len) {
+                Verdict::Excluded
+            } else {
+                self.rejection(suggestion_idx, ctx)
+                    .unwrap_or(Verdict::Selected)
+            };
+            explanation.scanned(keyword, min_prefix_len, block_id, QueryPath::Tree, verdict);
+            if verdict == Verdict::Selected {
+                explanation.full_keyword = Some(FullKeywordResolution::from_full_keyword(
+                    &self.full_keyword(value_idx),
+                    keyword,
+                ));
+                return Ok(explanation);
+            }
+        }
+
+        if let Some(infix) = &self.infix {
+            explanation.path = QueryPath::Infix;
+            infix.explain(
+                &mut explanation,
+                query,
+                |idx| self.rejection(idx, ctx),
+                |idx| self.suggestions.block_id(idx),
+            );
+        }
+        Ok(explanation)
+    }
+
+    fn iter_prefix<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = KeywordEntry> + 'a> {
+        let mut walk = self.keyword_trie.walk(prefix);
+        Box::new(std::iter::from_fn(move || {
+            let value_idx = walk.next_key()?;
+            let suggestion_idx = self.suggestion_idx.get(value_idx) as usize;
+            Some((
+                walk.key().to_string(),
+                self.min_prefix_len.get(value_idx) as usize,
+                self.suggestions.block_id(suggestion_idx),
+            ))
+        }))
+    }
+
+    fn keywords_for(&self, block_id: i32) -> Vec<String> {
+        let mut keywords: Vec<String> = Vec::new();
+        let mut walk = self.keyword_trie.walk("");
+        while let Some(value_idx) = walk.next_key() {
+            let suggestion_idx = self.suggestion_idx.get(value_idx) as usize;
+            if self.suggestions.block_id(suggestion_idx) != block_id {
+                continue;
+            }
+            let min_prefix_len = self.min_prefix_len.get(value_idx) as usize;
+            keywords.extend(
+                expand_keyword(walk.key(), min_prefix_len)
+                    .into_iter()
+                    .zip(min_prefix_len..)
+                    .filter(|(_, len)| !self.excludes(value_idx, *len))
+                    .map(|(keyword, _)| keyword),
+            );
+        }
+        keywords.sort();
+        keywords
+    }
+}
+
+/// Builds a `LoudsAmpIndex` one record at a time. The trie is encoded in `finish`, once
+/// every key is known.
+pub struct LoudsIndexBuilder<'p> {
+    index: LoudsAmpIndex,
+    pool: PoolRef<'p>,
+    keys: BTreeMap<String, PendingKey>,
+    full_keywords: FullKeywordsBuilder,
+    suggestions: SuggestionTableBuilder,
+}
+
+impl<'p> LoudsIndexBuilder<'p> {
+    fn new(pool: PoolRef<'p>, options: &BuildOptions) -> Self {
+        LoudsIndexBuilder {
+            index: LoudsAmpIndex::empty(options.infix_min_chars.map(InfixIndex::new)),
+            pool,
+            keys: BTreeMap::new(),
+            full_keywords: FullKeywordsBuilder::new(),
+            suggestions: SuggestionTableBuilder::new().compression(options.string_compression),
+        }
+    }
+}
+
+impl AmpIndexBuilder for LoudsIndexBuilder<'_> {
+    type Index = LoudsAmpIndex;
+
+    fn add(&mut self, amp: &OriginalAmp) -> Result<(), Box<dyn std::error::Error>> {
+        let index = &mut self.index;
+        let pool = &mut *self.pool;
+
+        let sidx = self.suggestions.push(amp, pool);
+
+        add_intervals(&mut self.keys, &mut self.full_keywords, pool, sidx, amp)?;
+
+        if let Some(infix) = &mut index.infix {
+            infix.add_suggestion(sidx, amp, pool);
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<LoudsAmpIndex, Box<dyn std::error::Error>> {
+        let index = &mut self.index;
+
+        let values: Vec<&PendingKey> = self.keys.values().collect();
+        let keys: Vec<&String> = self.keys.keys().collect();
+        let (trie, order) = LoudsTrie::new(&keys);
+        index.keyword_trie = trie;
+
+        // Value columns, in trie order
+        let mut suggestion_idx = Vec::with_capacity(keys.len());
+        let mut min_prefix_len = Vec::with_capacity(keys.len());
+        let mut full_keyword_ref = Vec::with_capacity(keys.len());
+        for (value_idx, key) in order.into_iter().map(|i| values[i]).enumerate() {
+            suggestion_idx.push(key.suggestion_idx as u64);
+            min_prefix_len.push(key.min_len as u64);
+            full_keyword_ref.push(key.full_keyword.to_bits());
+            index.excluded.extend(
+                key.excluded
+                    .iter()
+                    .map(|&len| (value_idx as u32, len as u32)),
+            );
+        }
+        index.excluded.shrink_to_fit();
+        index.suggestion_idx = PackedInts::from_values(&suggestion_idx);
+        index.min_prefix_len = PackedInts::from_values(&min_prefix_len);
+        index.full_keyword_ref = PackedInts::from_values(&full_keyword_ref);
+        index.full_keywords = self.full_keywords.finish();
+
+        index.suggestions = self.suggestions.finish();
+        if let Some(infix) = &mut index.infix {
+            infix.finish();
+        }
+        Ok(self.index)
+    }
+}
+
+impl LoudsAmpIndex {
+    /// An empty index, only ever handed to a builder
+    fn empty(infix: Option<InfixIndex>) -> Self {
+        LoudsAmpIndex {
+            keyword_trie: LoudsTrie::new::<&str>(&[]).0,
+            suggestion_idx: PackedInts::default(),
+            min_prefix_len: PackedInts::default(),
+            full_keyword_ref: PackedInts::default(),
+            excluded: Vec::new(),
+            full_keywords: FullKeywords::default(),
+            suggestions: SuggestionTable::default(),
+            infix,
+        }
+    }
+
+    /// Heap bytes of the keyword structure: the trie, its value columns and the
+    /// exclusions, excluding the full keyword strings
+    pub fn keyword_bytes(&self) -> usize {
+        self.keyword_trie.size_bytes()
+            + self.suggestion_idx.size_bytes()
+            + self.min_prefix_len.size_bytes()
+            + self.full_keyword_ref.size_bytes()
+            + self.excluded.capacity() * std::mem::size_of::<(u32, u32)>()
+    }
+
+    /// Whether the interval of the key at `value_idx` excludes its prefix of `len` chars
+    fn excludes(&self, value_idx: usize, len: usize) -> bool {
+        self.excluded
+            .binary_search(&(value_idx as u32, len as u32))
+            .is_ok()
+    }
+
+    fn full_keyword_ref(&self, value_idx: usize) -> FullKeywordRef {
+        FullKeywordRef::from_bits(self.full_keyword_ref.get(value_idx))
+    }
+
+    fn full_keyword(&self, value_idx: usize) -> FullKeyword {
+        self.full_keywords.get(self.full_keyword_ref(value_idx))
+    }
+
+    /// The full keyword of the key at `value_idx`, which is `keyword`
+    fn resolve_full_keyword(&self, value_idx: usize, keyword: &str) -> String {
+        self.full_keywords
+            .resolve(self.full_keyword_ref(value_idx), keyword)
+    }
+
+    /// Why `ctx` skips the suggestion at `suggestion_idx`, if it does
+    fn rejection(&self, suggestion_idx: usize, ctx: &QueryContext) -> Option<Verdict> {
+        self.suggestions.rejection(suggestion_idx, ctx)
+    }
+
+    /// Build result from a suggestion and dictionaries
+    fn build_result(
+        &self,
+        suggestion_idx: usize,
+        full_keyword: String,
+        params: &UrlParams,
+        results: &mut Vec<AmpResult>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        results.push(
+            self.suggestions
+                .result(suggestion_idx, full_keyword, params),
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+//! Machine-readable memory reports of the backends, as written by `memory_comparison`, and
+//! their comparison against a baseline report to catch memory regressions in review.
+
+use crate::common::StringCompression;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// What building one backend over one dataset cost
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Measurement {
+    pub dataset: String,
+    pub backend: String,
+    pub string_compression: StringCompression,
+    /// Bytes the built index keeps allocated
+    pub allocated_bytes: usize,
+    /// High-water mark of the bytes allocated while building
+    pub peak_bytes: usize,
+    pub build_ms: f64,
+    /// Average time to answer every keyword once
+    pub ns_per_query: f64,
+    /// Number of collapsed keys
+    pub key_count: usize,
+    /// Heap bytes of each component of the index, named by its stat
+    pub components: BTreeMap<String, usize>,
+    /// The other index stats, mostly counts
+    pub counts: BTreeMap<String, usize>,
+}
+
+/// The index stats giving the heap bytes of a component, every backend reporting them
+pub const COMPONENT_STATS: [&str; 4] = [
+    "keyword_structure_bytes",
+    "suggestion_table_bytes",
+    "full_keywords_bytes",
+    "infix_bytes",
+];
+
+/// Split index stats into the component sizes and the other stats
+pub fn split_stats(
+    stats: HashMap<String, usize>,
+) -> (BTreeMap<String, usize>, BTreeMap<String, usize>) {
+    stats
+        .into_iter()
+        .partition(|(name, _)| COMPONENT_STATS.contains(&name.as_str()))
+}
+
+impl Measurement {
+    /// Identifies the same measurement across reports
+    fn key(&self) -> (&str, &str, StringCompression) {
+        (&self.dataset, &self.backend, self.string_compression)
+    }
+}
+
+/// The measurements of one run
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemoryReport {
+    pub measurements: Vec<Measurement>,
+}
+
+/// A measurement that grew past the threshold since the baseline
+#[derive(Clone, Debug, PartialEq)]
+pub struct Regression {
+    pub dataset: String,
+    pub backend: String,
+    pub string_compression: StringCompression,
+    pub metric: &'static str,
+    pub baseline: usize,
+    pub current: usize,
+}
+
+impl Regression {
+    /// Growth over the baseline, in percent
+    pub fn growth_pct(&self) -> f64 {
+        100.0 * (self.current as f64 - self.baseline as f64) / self.baseline.max(1) as f64
+    }
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} on {} ({:?}): {} grew {:.1}% ({} -> {})",
+            self.backend,
+            self.dataset,
+            self.string_compression,
+            self.metric,
+            self.growth_pct(),
+            self.baseline,
+            self.current
+        )
+    }
+}
+
+impl MemoryReport {
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// One `dataset,backend,string_compression,metric,value` row per metric, component and
+    /// count
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("dataset,backend,string_compression,metric,value\n");
+        for m in &self.measurements {
+            let compression = match m.string_compression {
+                StringCompression::None => "none",
+                StringCompression::Fsst => "fsst",
+            };
+            let metrics = [
+                ("allocated_bytes", m.allocated_bytes.to_string()),
+                ("peak_bytes", m.peak_bytes.to_string()),
+                ("build_ms", format!("{:.3}", m.build_ms)),
+                ("ns_per_query", format!("{:.1}", m.ns_per_query)),
+                ("key_count", m.key_count.to_string()),
+            ];
+            let components = m
+                .components
+                .iter()
+                .chain(&m.counts)
+                .map(|(name, value)| (name.as_str(), value.to_string()));
+            for (metric, value) in metrics.into_iter().chain(components) {
+                csv.push_str(&format!(
+                    "{},{},{},{},{}\n",
+                    csv_field(&m.dataset),
+                    csv_field(&m.backend),
+                    compression,
+                    csv_field(metric),
+                    value
+                ));
+            }
+        }
+        csv
+    }
+
+    /// Memory metrics that grew by more than `threshold_pct` percent over the same
+    /// measurement in `baseline`. Measurements missing from the baseline are skipped.
+    pub fn regressions(&self, baseline: &MemoryReport, threshold_pct: f64) -> Vec<Regression> {
+        let baseline: HashMap<_, _> = baseline.measurements.iter().map(|m| (m.key(), m)).collect();
+        let mut regressions = Vec::new();
+        for current in &self.measurements {
+            let Some(base) = baseline.get(&current.key()) else {
+                continue;
+            };
+            for (metric, base_value, value) in [
+                (
+                    "allocated_bytes",
+                    base.allocated_bytes,
+                    current.allocated_bytes,
+                ),
+                ("peak_bytes", base.peak_bytes, current.peak_bytes),
+            ] {
+                let regression = Regression {
+                    dataset: current.dataset.clone(),
+                    backend: current.backend.clone(),
+                    string_compression: current.string_compression,
+                    metric,
+                    baseline: base_value,
+                    current: value,
+                };
+                if regression.growth_pct() > threshold_pct {
+                    regressions.push(regression);
+                }
+            }
+        }
+        regressions
+    }
+}
+
+/// Quote a CSV field when needed
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,424 @@
+//! Optional query instrumentation: wrap any index to count queries, hits and latencies.
+//!
+//! All the counters are atomics updated on the query path without taking any lock.
+//! Advertisers are registered up front; hits for an advertiser the wrapper doesn't know
+//! about are counted under `other`.
+
+use crate::common::{
+    AmpIndexer, AmpQuery, AmpResult, KeywordEntry, OriginalAmp, QueryContext, QueryPath,
+};
+use crate::explain::Explanation;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Upper bounds of the latency histogram buckets, in microseconds
+pub const LATENCY_BUCKETS_US: [u64; 12] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 5000, 10000];
+
+/// Queries of this many characters or more share a latency histogram
+pub const MAX_QUERY_LENGTH: usize = 10;
+
+/// Label of the advertiser hits that aren't registered
+pub const OTHER_ADVERTISER: &str = "other";
+
+/// A latency histogram with fixed buckets
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Per-bucket counts, the last one being the overflow (+Inf) bucket
+    buckets: [AtomicU64; LATENCY_BUCKETS_US.len() + 1],
+    sum_ns: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, nanos: u64) {
+        let bucket = LATENCY_BUCKETS_US
+            .iter()
+            .position(|&bound| nanos <= bound * 1000)
+            .unwrap_or(LATENCY_BUCKETS_US.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_ns.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self, query_length: String) -> LatencySnapshot {
+        let buckets: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        LatencySnapshot {
+            query_length,
+            count: buckets.iter().sum(),
+            sum_ns: self.sum_ns.load(Ordering::Relaxed),
+            buckets,
+        }
+    }
+}
+
+/// Counters shared by an `InstrumentedIndex` and whoever reads its metrics
+#[derive(Debug)]
+pub struct QueryMetrics {
+    queries: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    errors: AtomicU64,
+    /// Indexed like `QueryPath::ALL`
+    paths: [AtomicU64; QueryPath::ALL.len()],
+    /// Sorted by advertiser so that lookups are binary searches
+    advertiser_hits: Vec<(String, AtomicU64)>,
+    other_advertiser_hits: AtomicU64,
+    /// Indexed by query length in characters, capped at `MAX_QUERY_LENGTH`
+    latency: [Histogram; MAX_QUERY_LENGTH + 1],
+}
+
+impl QueryMetrics {
+    /// Metrics counting hits for the given advertisers
+    pub fn new<I, S>(advertisers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let advertisers: BTreeSet<String> = advertisers.into_iter().map(Into::into).collect();
+        QueryMetrics {
+            queries: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            paths: Default::default(),
+            advertiser_hits: advertisers
+                .into_iter()
+                .map(|a| (a, AtomicU64::new(0)))
+                .collect(),
+            other_advertiser_hits: AtomicU64::new(0),
+            latency: Default::default(),
+        }
+    }
+
+    fn record(
+        &self,
+        query: &str,
+        result: &Result<(Vec<AmpResult>, QueryPath), Box<dyn Error>>,
+        nanos: u64,
+    ) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        let length = query.chars().count().min(MAX_QUERY_LENGTH);
+        self.latency[length].observe(nanos);
+
+        let (results, path) = match result {
+            Ok(answer) => answer,
+            Err(_) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        };
+        let path_idx = QueryPath::ALL.iter().position(|p| p == path).unwrap();
+        self.paths[path_idx].fetch_add(1, Ordering::Relaxed);
+
+        if results.is_empty() {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        for result in results {
+            let counter = match self
+                .advertiser_hits
+                .binary_search_by(|(a, _)| a.as_str().cmp(&result.advertiser))
+            {
+                Ok(i) => &self.advertiser_hits[i].1,
+                Err(_) => &self.other_advertiser_hits,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// A consistent-enough copy of the counters: each counter is read atomically, but
+    /// queries running meanwhile may be partially accounted for.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let mut advertiser_hits: BTreeMap<String, u64> = self
+            .advertiser_hits
+            .iter()
+            .map(|(a, c)| (a.clone(), c.load(Ordering::Relaxed)))
+            .collect();
+        let other = self.other_advertiser_hits.load(Ordering::Relaxed);
+        if other > 0 {
+            *advertiser_hits
+                .entry(OTHER_ADVERTISER.to_string())
+                .or_default() += other;
+        }
+
+        MetricsSnapshot {
+            queries: self.queries.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            paths: QueryPath::ALL
+                .iter()
+                .zip(&self.paths)
+                .map(|(p, c)| (p.as_str().to_string(), c.load(Ordering::Relaxed)))
+                .collect(),
+            advertiser_hits,
+            latency: self
+                .latency
+                .iter()
+                .enumerate()
+                .map(|(len, h)| h.snapshot(length_label(len)))
+                .collect(),
+        }
+    }
+}
+
+fn length_label(len: usize) -> String {
+    if len == MAX_QUERY_LENGTH {
+        format!("{}+", len)
+    } else {
+        len.to_string()
+    }
+}
+
+/// Latency histogram of the queries of one length
+#[derive(Clone, Debug, Serialize)]
+pub struct LatencySnapshot {
+    /// Query length in characters, e.g. "3" or "10+"
+    pub query_length: String,
+    pub count: u64,
+    pub sum_ns: u64,
+    /// Non-cumulative counts per bucket of `LATENCY_BUCKETS_US`, plus the overflow bucket
+    pub buckets: Vec<u64>,
+}
+
+/// Point-in-time copy of the query metrics
+#[derive(Clone, Debug, Serialize)]
+pub struct MetricsSnapshot {
+    pub queries: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub errors: u64,
+    /// Queries answered by each `QueryPath`
+    pub paths: BTreeMap<String, u64>,
+    /// Suggestions returned per advertiser
+    pub advertiser_hits: BTreeMap<String, u64>,
+    /// Latency histograms by query length
+    pub latency: Vec<LatencySnapshot>,
+}
+
+impl MetricsSnapshot {
+    /// Ratio of the queries that returned at least one suggestion
+    pub fn hit_rate(&self) -> f64 {
+        if self.queries == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.queries as f64
+        }
+    }
+
+    /// Flat counters keyed like index stats, e.g. `advertiser_hits.Amazon` or
+    /// `latency.3.count`
+    pub fn counters(&self) -> HashMap<String, u64> {
+        let mut counters = HashMap::new();
+        counters.insert("queries".into(), self.queries);
+        counters.insert("hits".into(), self.hits);
+        counters.insert("misses".into(), self.misses);
+        counters.insert("errors".into(), self.errors);
+        for (path, count) in &self.paths {
+            counters.insert(format!("paths.{}", path), *count);
+        }
+        for (advertiser, count) in &self.advertiser_hits {
+            counters.insert(format!("advertiser_hits.{}", advertiser), *count);
+        }
+        for latency in &self.latency {
+            counters.insert(
+                format!("latency.{}.count", latency.query_length),
+                latency.count,
+            );
+            counters.insert(
+                format!("latency.{}.sum_ns", latency.query_length),
+                latency.sum_ns,
+            );
+        }
+        counters
+    }
+
+    /// Render in the Prometheus text exposition format, with metric names prefixed
+    /// by `namespace`
+    pub fn to_prometheus(&self, namespace: &str) -> String {
+        let mut out = String::new();
+        let mut counter = |name: &str, help: &str, samples: &[(String, u64)]| {
+            let _ = writeln!(out, "# HELP {}_{} {}", namespace, name, help);
+            let _ = writeln!(out, "# TYPE {}_{} counter", namespace, name);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}_{}{} {}", namespace, name, labels, value);
+            }
+        };
+
+        counter(
+            "queries_total",
+            "Queries served.",
+            &[(String::new(), self.queries)],
+        );
+        counter(
+            "query_results_total",
+            "Queries by outcome.",
+            &[
+                ("{result=\"hit\"}".to_string(), self.hits),
+                ("{result=\"miss\"}".to_string(), self.misses),
+                ("{result=\"error\"}".to_string(), self.errors),
+            ],
+        );
+        counter(
+            "query_path_total",
+            "Queries by the lookup path that answered them.",
+            &self
+                .paths
+                .iter()
+                .map(|(p, c)| (format!("{{path=\"{}\"}}", p), *c))
+                .collect::<Vec<_>>(),
+        );
+        counter(
+            "advertiser_hits_total",
+            "Suggestions returned by advertiser.",
+            &self
+                .advertiser_hits
+                .iter()
+                .map(|(a, c)| (format!("{{advertiser=\"{}\"}}", escape_label(a)), *c))
+                .collect::<Vec<_>>(),
+        );
+
+        let name = format!("{}_query_duration_seconds", namespace);
+        let _ = writeln!(out, "# HELP {} Query latency by query length.", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for latency in &self.latency {
+            let length = &latency.query_length;
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS_US.iter().zip(&latency.buckets) {
+                cumulative += count;
+                let le = *bound as f64 / 1e6;
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{query_length=\"{}\",le=\"{}\"}} {}",
+                    name, length, le, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{{query_length=\"{}\",le=\"+Inf\"}} {}",
+                name, length, latency.count
+            );
+            let _ = writeln!(
+                out,
+                "{}_sum{{query_length=\"{}\"}} {}",
+                name,
+                length,
+                latency.sum_ns as f64 / 1e9
+            );
+            let _ = writeln!(
+                out,
+                "{}_count{{query_length=\"{}\"}} {}",
+                name, length, latency.count
+            );
+        }
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Wraps an index, recording metrics for every query it serves
+pub struct InstrumentedIndex<T: AmpQuery> {
+    inner: T,
+    metrics: Arc<QueryMetrics>,
+}
+
+impl<T: AmpQuery> InstrumentedIndex<T> {
+    /// Instrument `inner`, counting hits for the given advertisers
+    pub fn new<I, S>(inner: T, advertisers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        InstrumentedIndex {
+            inner,
+            metrics: Arc::new(QueryMetrics::new(advertisers)),
+        }
+    }
+
+    /// Instrument `inner`, counting hits for the advertisers of the data it was built from
+    pub fn for_amps(inner: T, amps: &[OriginalAmp]) -> Self {
+        Self::new(inner, amps.iter().map(|amp| amp.advertiser.as_str()))
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// The metrics of this index; they can be kept and read after the index is dropped
+    pub fn metrics(&self) -> Arc<QueryMetrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+}
+
+impl<T: AmpIndexer> InstrumentedIndex<T> {
+    /// Build an instrumented index of type `T` from raw AMP data
+    pub fn build(amps: &[OriginalAmp]) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::for_amps(T::build(amps)?, amps))
+    }
+}
+
+impl<T: AmpQuery> AmpQuery for InstrumentedIndex<T> {
+    fn query_with_context(
+        &self,
+        prefix: &str,
+        ctx: &QueryContext,
+    ) -> Result<Vec<AmpResult>, Box<dyn Error>> {
+        Ok(self.query_with_path(prefix, ctx)?.0)
+    }
+
+    fn query_with_path(
+        &self,
+        prefix: &str,
+        ctx: &QueryContext,
+    ) -> Result<(Vec<AmpResult>, QueryPath), Box<dyn Error>> {
+        let start = Instant::now();
+        let result = self.inner.query_with_path(prefix, ctx);
+        let nanos = start.elapsed().as_nanos().min(u64::MAX as u128) as u64;
+        self.metrics.record(prefix, &result, nanos);
+        result
+    }
+
+    fn stats(&self) -> HashMap<String, usize> {
+        self.inner.stats()
+    }
+
+    // Not counted as a query
+    fn explain_with_context(
+        &self,
+        prefix: &str,
+        ctx: &QueryContext,
+    ) -> Result<Explanation, Box<dyn Error>> {
+        self.inner.explain_with_context(prefix, ctx)
+    }
+
+    fn iter_prefix<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = KeywordEntry> + 'a> {
+        self.inner.iter_prefix(prefix)
+    }
+
+    fn keywords_for(&self, block_id: i32) -> Vec<String> {
+        self.inner.keywords_for(block_id)
+    }
+
+    // Not counted as a query
+    fn lookup_block(&self, block_id: i32) -> Option<AmpResult> {
+        self.inner.lookup_block(block_id)
+    }
+}
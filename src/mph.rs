@@ -0,0 +1,143 @@
+//! A minimal perfect hash function built with hash-and-displace (CHD): keys are grouped
+//! into small buckets, and each bucket stores the displacement that moves all its keys to
+//! free slots.
+
+use crate::bits::PackedInts;
+
+/// Average number of keys per bucket, trading build time for size
+const KEYS_PER_BUCKET: usize = 4;
+
+/// Seeds to try before giving up. A seed fails when some bucket finds no displacement,
+/// which mostly happens to small key sets where one bucket gets most of the keys.
+const MAX_SEEDS: u64 = 64;
+
+/// 64-bit hash of `bytes`: FNV-1a followed by a final mix so that all the bits are usable
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    mix(hash)
+}
+
+/// splitmix64 finalizer
+pub(crate) fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Map a hash to `[0, n)` without a division
+fn reduce(hash: u64, n: usize) -> usize {
+    ((hash as u128 * n as u128) >> 64) as usize
+}
+
+/// Maps each of the `n` hashes it was built from to a distinct slot in `[0, n)`. Other
+/// hashes map to arbitrary slots, so callers verify what they find there.
+#[derive(Clone, Debug, Default)]
+pub struct Mph {
+    seed: u64,
+    displacements: PackedInts,
+    len: usize,
+}
+
+impl Mph {
+    /// Build the function for `hashes`, which must be distinct
+    pub fn new(hashes: &[u64]) -> Result<Self, String> {
+        let len = hashes.len();
+        let mut sorted = hashes.to_vec();
+        sorted.sort_unstable();
+        if let Some(w) = sorted.windows(2).find(|w| w[0] == w[1]) {
+            return Err(format!("Duplicate hash {:#x}", w[0]));
+        }
+
+        (0..MAX_SEEDS)
+            .find_map(|seed| Self::with_seed(hashes, seed))
+            .ok_or_else(|| format!("No perfect hash found for {} keys", len))
+    }
+
+    fn with_seed(hashes: &[u64], seed: u64) -> Option<Self> {
+        let len = hashes.len();
+        let bucket_count = len.div_ceil(KEYS_PER_BUCKET).max(1);
+        let mut buckets = vec![Vec::new(); bucket_count];
+        for &hash in hashes {
+            let hash = mix(hash ^ seed);
+            buckets[reduce(hash, bucket_count)].push(hash);
+        }
+
+        // Place the largest buckets first, while most slots are still free
+        let mut order: Vec<usize> = (0..bucket_count).collect();
+        order.sort_by_key(|&b| std::cmp::Reverse(buckets[b].len()));
+
+        let mut taken = vec![false; len];
+        let mut displacements = vec![0u64; bucket_count];
+        let mut slots = Vec::with_capacity(KEYS_PER_BUCKET * 4);
+        for b in order {
+            let bucket = &buckets[b];
+            if bucket.is_empty() {
+                break;
+            }
+            let mut displacement = 0u64;
+            loop {
+                slots.clear();
+                slots.extend(bucket.iter().map(|&h| slot(h, displacement, len)));
+                slots.sort_unstable();
+                let distinct = slots.windows(2).all(|w| w[0] != w[1]);
+                if distinct && slots.iter().all(|&s| !taken[s]) {
+                    break;
+                }
+                displacement += 1;
+                if displacement > (len as u64 + 1) * 64 {
+                    return None;
+                }
+            }
+            for &s in &slots {
+                taken[s] = true;
+            }
+            displacements[b] = displacement;
+        }
+
+        Some(Mph {
+            seed,
+            displacements: PackedInts::from_values(&displacements),
+            len,
+        })
+    }
+
+    /// Number of slots, i.e. of hashes the function was built from
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Slot of `hash`; `None` if the function was built from no hash
+    pub fn slot(&self, hash: u64) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        let hash = mix(hash ^ self.seed);
+        let bucket = reduce(hash, self.displacements.len());
+        Some(slot(hash, self.displacements.get(bucket), self.len))
+    }
+
+    /// Bits per bucket displacement
+    pub fn displacement_bits(&self) -> u32 {
+        self.displacements.width()
+    }
+
+    /// Heap bytes held
+    pub fn size_bytes(&self) -> usize {
+        self.displacements.size_bytes()
+    }
+}
+
+fn slot(hash: u64, displacement: u64, len: usize) -> usize {
+    reduce(
+        mix(hash ^ displacement.wrapping_mul(0x9e37_79b9_7f4a_7c15)),
+        len,
+    )
+}
@@ -0,0 +1,163 @@
+use crate::common::{AmpIndexer, AmpResult, OriginalAmp, StringPool};
+use crate::stream::build_from_file_with_pool;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Form factor of the client a dataset is served to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FormFactor {
+    Desktop,
+    Phone,
+}
+
+impl FromStr for FormFactor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "desktop" => Ok(FormFactor::Desktop),
+            // Older payloads are named `amp-us-mobile.json`
+            "phone" | "mobile" => Ok(FormFactor::Phone),
+            other => Err(format!("Unknown form factor '{}'", other)),
+        }
+    }
+}
+
+impl fmt::Display for FormFactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormFactor::Desktop => write!(f, "desktop"),
+            FormFactor::Phone => write!(f, "phone"),
+        }
+    }
+}
+
+/// Identifies one dataset, e.g. US/desktop
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct IndexKey {
+    /// Lowercase ISO 3166 country code
+    pub country: String,
+    pub form_factor: FormFactor,
+}
+
+impl IndexKey {
+    pub fn new(country: &str, form_factor: FormFactor) -> Self {
+        IndexKey {
+            country: country.to_ascii_lowercase(),
+            form_factor,
+        }
+    }
+
+    /// Parse a payload file name of the form `amp-<country>-<form factor>.json`
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let stem = name.strip_prefix("amp-")?.strip_suffix(".json")?;
+        let (country, form_factor) = stem.split_once('-')?;
+        let form_factor = form_factor.parse().ok()?;
+        Some(IndexKey::new(country, form_factor))
+    }
+}
+
+impl fmt::Display for IndexKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.country, self.form_factor)
+    }
+}
+
+/// A set of AMP indexes, one per (country, form factor), sharing their dictionary strings
+pub struct MultiIndex<T: AmpIndexer> {
+    indexes: HashMap<IndexKey, T>,
+    /// Dictionary values shared across all the indexes
+    pool: StringPool,
+}
+
+impl<T: AmpIndexer> Default for MultiIndex<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: AmpIndexer> MultiIndex<T> {
+    pub fn new() -> Self {
+        MultiIndex {
+            indexes: HashMap::new(),
+            pool: StringPool::new(),
+        }
+    }
+
+    /// Build the index for every `amp-<country>-<form factor>.json` payload in `dir`,
+    /// streaming each payload straight into its index. Other files are ignored.
+    pub fn load_dir<P: AsRef<Path>>(dir: P) -> Result<Self, Box<dyn Error>> {
+        let mut multi = Self::new();
+        let mut payloads = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let key = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(IndexKey::from_file_name);
+            if let Some(key) = key {
+                payloads.push((key, path));
+            }
+        }
+        // Build in a stable order so that dictionary ids are deterministic
+        payloads.sort();
+
+        for (key, path) in payloads {
+            let index = build_from_file_with_pool(&path, &mut multi.pool)?;
+            multi.indexes.insert(key, index);
+        }
+        Ok(multi)
+    }
+
+    /// Build an index for `key` from raw AMP data, replacing any existing one.
+    pub fn insert(&mut self, key: IndexKey, amps: &[OriginalAmp]) -> Result<(), Box<dyn Error>> {
+        let index = T::build_with_pool(amps, &mut self.pool)?;
+        self.indexes.insert(key, index);
+        Ok(())
+    }
+
+    /// Query the index selected by `key` for suggestions matching a prefix
+    pub fn query(&self, key: &IndexKey, prefix: &str) -> Result<Vec<AmpResult>, Box<dyn Error>> {
+        let index = self
+            .indexes
+            .get(key)
+            .ok_or_else(|| format!("No index for {}", key))?;
+        index.query(prefix)
+    }
+
+    pub fn get(&self, key: &IndexKey) -> Option<&T> {
+        self.indexes.get(key)
+    }
+
+    /// Keys of all the loaded indexes, sorted
+    pub fn keys(&self) -> Vec<&IndexKey> {
+        let mut keys: Vec<_> = self.indexes.keys().collect();
+        keys.sort();
+        keys
+    }
+
+    pub fn len(&self) -> usize {
+        self.indexes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indexes.is_empty()
+    }
+
+    /// Per-index statistics prefixed by the index key, plus the shared pool size
+    pub fn stats(&self) -> HashMap<String, usize> {
+        let mut stats = HashMap::new();
+        for (key, index) in &self.indexes {
+            for (name, value) in index.stats() {
+                stats.insert(format!("{}.{}", key, name), value);
+            }
+        }
+        stats.insert("index_count".into(), self.indexes.len());
+        stats.insert("shared_strings_count".into(), self.pool.len());
+        stats.insert("shared_strings_bytes".into(), self.pool.bytes());
+        stats
+    }
+}
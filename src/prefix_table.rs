@@ -0,0 +1,217 @@
+use crate::bits::PackedInts;
+use crate::common::{
+    AmpIndexBuilder, AmpIndexer, AmpQuery, AmpResult, BuildOptions, KeywordEntry, OriginalAmp,
+    QueryContext, QueryPath, StringPool,
+};
+use crate::explain::{Explanation, FullKeywordResolution, Verdict};
+use crate::mph::{Mph, hash_bytes};
+use crate::sorted_array::{SortedArrayAmpIndex, SortedArrayIndexBuilder};
+use std::collections::HashMap;
+
+/// Answers every typeable prefix with a single hash probe.
+///
+/// The answer to a prefix only depends on the keys, so `finish` computes it for every
+/// prefix of every key that passes the key's `min_prefix_len`, and stores the position of
+/// the answering key in a table indexed by a minimal perfect hash of the prefix. A 16-bit
+/// fingerprint per slot turns away most misses without decoding a key; the rest are
+/// caught by checking that the answering key extends the query, at a length its interval
+/// doesn't exclude.
+///
+/// Keys, suggestions and dictionaries live in a `SortedArrayAmpIndex`, which also answers
+/// the queries the table can't: those whose precomputed answer is filtered out by the
+/// query context, and word-boundary matches when the infix index is enabled.
+pub struct PrefixTableAmpIndex {
+    index: SortedArrayAmpIndex,
+    mph: Mph,
+    /// Low 16 bits of the hash of the prefix in each slot
+    fingerprints: Vec<u16>,
+    /// Position of the answering key of the prefix in each slot
+    answers: PackedInts,
+}
+
+impl PrefixTableAmpIndex {
+    /// Position and text of the key answering `query` when nothing is filtered out
+    fn answer(&self, query: &str) -> Option<(usize, String)> {
+        let hash = hash_bytes(query.as_bytes());
+        let slot = self.mph.slot(hash)?;
+        if self.fingerprints[slot] != hash as u16 {
+            return None;
+        }
+        let pos = self.answers.get(slot) as usize;
+        let (_, min_prefix_len) = self.index.value_at(pos);
+        let key = self.index.key_at(pos);
+        let qlen = query.chars().count();
+        (key.starts_with(query) && qlen >= min_prefix_len && !self.index.excludes(pos, qlen))
+            .then_some((pos, key))
+    }
+
+    /// Heap bytes of the answer table
+    pub fn table_bytes(&self) -> usize {
+        self.mph.size_bytes() + self.fingerprints.capacity() * 2 + self.answers.size_bytes()
+    }
+
+    /// Heap bytes of the keyword structure: the sorted keys and the answer table
+    pub fn keyword_bytes(&self) -> usize {
+        self.index.keyword_bytes() + self.table_bytes()
+    }
+}
+
+impl AmpIndexer for PrefixTableAmpIndex {
+    type Builder<'p> = PrefixTableIndexBuilder<'p>;
+
+    fn builder(options: &BuildOptions) -> Self::Builder<'static> {
+        PrefixTableIndexBuilder {
+            inner: SortedArrayAmpIndex::builder(options),
+        }
+    }
+
+    fn builder_with_pool<'p>(
+        pool: &'p mut StringPool,
+        options: &BuildOptions,
+    ) -> Self::Builder<'p> {
+        PrefixTableIndexBuilder {
+            inner: SortedArrayAmpIndex::builder_with_pool(pool, options),
+        }
+    }
+}
+
+impl AmpQuery for PrefixTableAmpIndex {
+    fn query_with_context(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<Vec<AmpResult>, Box<dyn std::error::Error>> {
+        Ok(self.query_with_path(query, ctx)?.0)
+    }
+
+    fn query_with_path(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<(Vec<AmpResult>, QueryPath), Box<dyn std::error::Error>> {
+        let mut results = Vec::new();
+        match self.answer(query) {
+            Some((pos, key)) => {
+                let (suggestion_idx, _) = self.index.value_at(pos);
+                if self.index.rejection(suggestion_idx, ctx).is_some() {
+                    // Scan past the filtered out answer
+                    return self.index.query_with_path(query, ctx);
+                }
+                let full_keyword = self.index.resolve_full_keyword(pos, &key);
+                self.index.build_result(
+                    suggestion_idx,
+                    full_keyword,
+                    &ctx.url_params,
+                    &mut results,
+                )?;
+                Ok((results, QueryPath::AnswerTable))
+            }
+            None if self.index.has_infix() => self.index.query_with_path(query, ctx),
+            None => Ok((results, QueryPath::AnswerTable)),
+        }
+    }
+
+    fn stats(&self) -> HashMap<String, usize> {
+        let mut stats = self.index.stats();
+        stats.insert("answer_table_entries".into(), self.mph.len());
+        stats.insert("answer_table_bytes".into(), self.table_bytes());
+        stats.insert(
+            "displacement_bits".into(),
+            self.mph.displacement_bits() as usize,
+        );
+        stats.insert("keyword_structure_bytes".into(), self.keyword_bytes());
+        stats
+    }
+
+    fn explain_with_context(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<Explanation, Box<dyn std::error::Error>> {
+        // The table only knows the winner when it isn't filtered out; the scan of the
+        // sorted keys explains everything else
+        if let Some((pos, keyword)) = self.answer(query) {
+            let (suggestion_idx, min_prefix_len) = self.index.value_at(pos);
+            if self.index.rejection(suggestion_idx, ctx).is_none() {
+                let mut explanation = Explanation::new(query, QueryPath::AnswerTable);
+                explanation.scanned(
+                    &keyword,
+                    min_prefix_len,
+                    self.index.block_id(suggestion_idx),
+                    QueryPath::AnswerTable,
+                    Verdict::Selected,
+                );
+                explanation.full_keyword = Some(FullKeywordResolution::from_full_keyword(
+                    &self.index.full_keyword(pos),
+                    &keyword,
+                ));
+                return Ok(explanation);
+            }
+        }
+        self.index.explain_with_context(query, ctx)
+    }
+
+    fn iter_prefix<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = KeywordEntry> + 'a> {
+        self.index.iter_prefix(prefix)
+    }
+
+    fn keywords_for(&self, block_id: i32) -> Vec<String> {
+        self.index.keywords_for(block_id)
+    }
+}
+
+/// Builds a `PrefixTableAmpIndex`: records go to a `SortedArrayAmpIndex` builder, and the
+/// answer table is computed from its keys in `finish`.
+pub struct PrefixTableIndexBuilder<'p> {
+    inner: SortedArrayIndexBuilder<'p>,
+}
+
+impl AmpIndexBuilder for PrefixTableIndexBuilder<'_> {
+    type Index = PrefixTableAmpIndex;
+
+    fn add(&mut self, amp: &OriginalAmp) -> Result<(), Box<dyn std::error::Error>> {
+        self.inner.add(amp)
+    }
+
+    fn finish(self) -> Result<PrefixTableAmpIndex, Box<dyn std::error::Error>> {
+        let index = self.inner.finish()?;
+        let keys: Vec<KeywordEntry> = index.iter_prefix("").collect();
+
+        // Keys come in order, so the first key claiming a prefix is the one a scan from
+        // that prefix would pick
+        let mut answers: HashMap<&str, usize> = HashMap::new();
+        for (pos, (key, min_prefix_len, _)) in keys.iter().enumerate() {
+            let ends = key
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain(std::iter::once(key.len()));
+            for (chars, end) in ends.enumerate() {
+                if chars >= *min_prefix_len && !index.excludes(pos, chars) {
+                    answers.entry(&key[..end]).or_insert(pos);
+                }
+            }
+        }
+
+        let entries: Vec<(u64, usize)> = answers
+            .into_iter()
+            .map(|(prefix, pos)| (hash_bytes(prefix.as_bytes()), pos))
+            .collect();
+        let hashes: Vec<u64> = entries.iter().map(|&(hash, _)| hash).collect();
+        let mph = Mph::new(&hashes)?;
+
+        let mut fingerprints = vec![0u16; entries.len()];
+        let mut slot_answers = vec![0u64; entries.len()];
+        for &(hash, pos) in &entries {
+            let slot = mph.slot(hash).expect("built from this hash");
+            fingerprints[slot] = hash as u16;
+            slot_answers[slot] = pos as u64;
+        }
+
+        Ok(PrefixTableAmpIndex {
+            index,
+            mph,
+            fingerprints,
+            answers: PackedInts::from_values(&slot_answers),
+        })
+    }
+}
@@ -4,7 +4,13 @@ use serde_json;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
-use crate::{AmpIndexer, AmpResult, BlartAmpIndex, OriginalAmp};
+use crate::metrics::QueryMetrics;
+use crate::registry::DEFAULT_BACKEND;
+use crate::url_params::UrlParams;
+use crate::{
+    AmpResult, BackendRegistry, BuildOptions, DynAmpIndex, InstrumentedIndex, OriginalAmp,
+    QueryContext,
+};
 
 #[pyclass]
 #[derive(Clone)]
@@ -45,12 +51,73 @@ impl From<AmpResult> for PyAmpResult {
     }
 }
 
-// Thread-safe index wrapper
-type IndexHandle = Arc<RwLock<BlartAmpIndex>>;
+// Built indexes are immutable, so they can be shared across threads as is
+#[derive(Clone)]
+struct IndexHandle {
+    index: Arc<DynAmpIndex>,
+    /// Set if the index was built with `instrument=True`
+    metrics: Option<Arc<QueryMetrics>>,
+}
+
+fn parse_url_params(values: HashMap<String, String>) -> PyResult<UrlParams> {
+    let mut params = UrlParams::default();
+    for (name, value) in values {
+        params.set(&name, &value).map_err(PyValueError::new_err)?;
+    }
+    Ok(params)
+}
 
 #[pyclass]
 pub struct AmpIndexManager {
     indexes: Arc<RwLock<HashMap<String, IndexHandle>>>,
+    registry: BackendRegistry,
+}
+
+impl AmpIndexManager {
+    fn build_index(
+        &self,
+        index_name: String,
+        backend: &str,
+        amps: &[OriginalAmp],
+        instrument: bool,
+    ) -> PyResult<()> {
+        let index = self
+            .registry
+            .build(backend, amps, &BuildOptions::default())
+            .map_err(|e| PyValueError::new_err(format!("Failed to build index: {}", e)))?;
+
+        let handle = if instrument {
+            let instrumented = InstrumentedIndex::for_amps(index, amps);
+            let metrics = instrumented.metrics();
+            IndexHandle {
+                index: Arc::new(Box::new(instrumented)),
+                metrics: Some(metrics),
+            }
+        } else {
+            IndexHandle {
+                index: Arc::new(index),
+                metrics: None,
+            }
+        };
+
+        let mut indexes = self.indexes.write().unwrap();
+        indexes.insert(index_name, handle);
+        Ok(())
+    }
+
+    fn handle(&self, index_name: &str) -> PyResult<IndexHandle> {
+        let indexes = self.indexes.read().unwrap();
+        indexes
+            .get(index_name)
+            .cloned()
+            .ok_or_else(|| PyKeyError::new_err(format!("Index '{}' not found", index_name)))
+    }
+
+    fn metrics_of(&self, index_name: &str) -> PyResult<Arc<QueryMetrics>> {
+        self.handle(index_name)?.metrics.ok_or_else(|| {
+            PyValueError::new_err(format!("Index '{}' is not instrumented", index_name))
+        })
+    }
 }
 
 #[pymethods]
@@ -59,54 +126,128 @@ impl AmpIndexManager {
     fn new() -> PyResult<Self> {
         Ok(AmpIndexManager {
             indexes: Arc::new(RwLock::new(HashMap::new())),
+            registry: BackendRegistry::with_defaults(),
         })
     }
 
     /// Build index from JSON file
-    fn build_from_file(&self, index_name: String, json_path: String) -> PyResult<()> {
+    #[pyo3(signature = (index_name, json_path, backend = DEFAULT_BACKEND, instrument = false))]
+    fn build_from_file(
+        &self,
+        index_name: String,
+        json_path: String,
+        backend: &str,
+        instrument: bool,
+    ) -> PyResult<()> {
         let amps = crate::load_amp_data(&json_path)
             .map_err(|e| PyIOError::new_err(format!("Failed to load JSON: {}", e)))?;
-
-        let mut index = BlartAmpIndex::new();
-        index
-            .build(&amps)
-            .map_err(|e| PyValueError::new_err(format!("Failed to build index: {}", e)))?;
-
-        let mut indexes = self.indexes.write().unwrap();
-        indexes.insert(index_name, Arc::new(RwLock::new(index)));
-        Ok(())
+        self.build_index(index_name, backend, &amps, instrument)
     }
 
     /// Build index from JSON string
-    fn build_from_json(&self, index_name: String, json_data: String) -> PyResult<()> {
+    #[pyo3(signature = (index_name, json_data, backend = DEFAULT_BACKEND, instrument = false))]
+    fn build_from_json(
+        &self,
+        index_name: String,
+        json_data: String,
+        backend: &str,
+        instrument: bool,
+    ) -> PyResult<()> {
         let amps: Vec<OriginalAmp> = serde_json::from_str(&json_data)
             .map_err(|e| PyValueError::new_err(format!("Invalid JSON: {}", e)))?;
-
-        let mut index = BlartAmpIndex::new();
-        index
-            .build(&amps)
-            .map_err(|e| PyValueError::new_err(format!("Failed to build index: {}", e)))?;
-
-        let mut indexes = self.indexes.write().unwrap();
-        indexes.insert(index_name, Arc::new(RwLock::new(index)));
-        Ok(())
+        self.build_index(index_name, backend, &amps, instrument)
     }
 
-    /// Query index
-    fn query(&self, index_name: String, query: String) -> PyResult<Vec<PyAmpResult>> {
-        let indexes = self.indexes.read().unwrap();
-        let index_handle = indexes
-            .get(&index_name)
-            .ok_or_else(|| PyKeyError::new_err(format!("Index '{}' not found", index_name)))?;
+    /// Names of the available backends
+    fn backends(&self) -> Vec<String> {
+        self.registry
+            .names()
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
 
-        let index = index_handle.read().unwrap();
-        let results = index
-            .query(&query)
+    /// Query index, skipping the suggestions of blocked block ids and advertisers, and the
+    /// ones outside their time window at `now` (a Unix timestamp) when given. `url_params`
+    /// fill the URL placeholders, e.g. `{"request_id": "...", "form_factor": "phone"}`.
+    #[pyo3(signature = (index_name, query, blocked_block_ids = Vec::new(), blocked_advertisers = Vec::new(), now = None, url_params = HashMap::new()))]
+    fn query(
+        &self,
+        index_name: String,
+        query: String,
+        blocked_block_ids: Vec<i32>,
+        blocked_advertisers: Vec<String>,
+        now: Option<i64>,
+        url_params: HashMap<String, String>,
+    ) -> PyResult<Vec<PyAmpResult>> {
+        let ctx = QueryContext {
+            blocked_block_ids: blocked_block_ids.into_iter().collect(),
+            blocked_advertisers: blocked_advertisers.into_iter().collect(),
+            now,
+            url_params: parse_url_params(url_params)?,
+        };
+        let results = self
+            .handle(&index_name)?
+            .index
+            .query_with_context(&query, &ctx)
             .map_err(|e| PyValueError::new_err(format!("Query failed: {}", e)))?;
 
         Ok(results.into_iter().map(PyAmpResult::from).collect())
     }
 
+    /// Explain how the index answers a query, as a JSON document
+    fn explain(&self, index_name: String, query: String) -> PyResult<String> {
+        let explanation = self
+            .handle(&index_name)?
+            .index
+            .explain(&query)
+            .map_err(|e| PyValueError::new_err(format!("Query failed: {}", e)))?;
+        explanation
+            .to_json()
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    /// Every query that shows the suggestion `block_id`
+    fn keywords_for(&self, index_name: String, block_id: i32) -> PyResult<Vec<String>> {
+        Ok(self.handle(&index_name)?.index.keywords_for(block_id))
+    }
+
+    /// Collapsed keywords starting with `prefix`, as `(keyword, min_prefix_len, block_id)`
+    fn iter_prefix(
+        &self,
+        index_name: String,
+        prefix: String,
+    ) -> PyResult<Vec<(String, usize, i32)>> {
+        Ok(self
+            .handle(&index_name)?
+            .index
+            .iter_prefix(&prefix)
+            .collect())
+    }
+
+    /// The suggestion `block_id`, or `None` if it has no keyword in the index
+    fn lookup_block(&self, index_name: String, block_id: i32) -> PyResult<Option<PyAmpResult>> {
+        Ok(self
+            .handle(&index_name)?
+            .index
+            .lookup_block(block_id)
+            .map(PyAmpResult::from))
+    }
+
+    /// Query metrics of an index built with `instrument=True`, as flat counters
+    fn metrics(&self, index_name: String) -> PyResult<HashMap<String, u64>> {
+        Ok(self.metrics_of(&index_name)?.snapshot().counters())
+    }
+
+    /// Query metrics of an index built with `instrument=True`, in the Prometheus text format
+    #[pyo3(signature = (index_name, namespace = "amp"))]
+    fn metrics_prometheus(&self, index_name: String, namespace: &str) -> PyResult<String> {
+        Ok(self
+            .metrics_of(&index_name)?
+            .snapshot()
+            .to_prometheus(namespace))
+    }
+
     /// Delete index
     fn delete(&self, index_name: String) -> PyResult<()> {
         let mut indexes = self.indexes.write().unwrap();
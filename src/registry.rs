@@ -0,0 +1,135 @@
+//! Runtime backend selection: build any registered indexer by name, as a trait object.
+
+use crate::blart::BlartAmpIndex;
+use crate::btree::BTreeAmpIndex;
+use crate::common::{AmpIndexBuilder, AmpIndexer, AmpQuery, BuildOptions, OriginalAmp};
+use crate::hybrid::HybridAmpIndex;
+use crate::louds::LoudsAmpIndex;
+use crate::prefix_table::PrefixTableAmpIndex;
+use crate::sorted_array::SortedArrayAmpIndex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// An index of any backend
+pub type DynAmpIndex = Box<dyn AmpQuery + Send + Sync>;
+
+/// Builds an index of one backend from raw AMP data
+pub type BackendFactory = fn(&[OriginalAmp], &BuildOptions) -> Result<DynAmpIndex, Box<dyn Error>>;
+
+/// Backend used when the configuration does not name one
+pub const DEFAULT_BACKEND: &str = "blart";
+
+/// Index configuration, e.g. read from a service's settings:
+///
+/// ```json
+/// {"backend": "hybrid", "short_cache_max_chars": 4}
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+pub struct IndexConfig {
+    #[serde(default = "default_backend")]
+    pub backend: String,
+    #[serde(flatten)]
+    pub options: BuildOptions,
+}
+
+fn default_backend() -> String {
+    DEFAULT_BACKEND.to_string()
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        IndexConfig {
+            backend: default_backend(),
+            options: BuildOptions::default(),
+        }
+    }
+}
+
+/// Build an index of type `T` and erase its type
+fn build_dyn<T: AmpIndexer + 'static>(
+    amps: &[OriginalAmp],
+    options: &BuildOptions,
+) -> Result<DynAmpIndex, Box<dyn Error>> {
+    let mut builder = T::builder(options);
+    builder.add_all(amps)?;
+    Ok(Box::new(builder.finish()?))
+}
+
+/// Maps backend names to the factories building them
+#[derive(Clone)]
+pub struct BackendRegistry {
+    factories: BTreeMap<String, BackendFactory>,
+}
+
+impl Default for BackendRegistry {
+    fn default() -> Self {
+        Self::with_defaults()
+    }
+}
+
+impl BackendRegistry {
+    /// A registry without any backend
+    pub fn new() -> Self {
+        BackendRegistry {
+            factories: BTreeMap::new(),
+        }
+    }
+
+    /// A registry with all the backends of this crate
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::new();
+        registry.register_indexer::<BTreeAmpIndex>("btree");
+        registry.register_indexer::<BlartAmpIndex>("blart");
+        registry.register_indexer::<HybridAmpIndex>("hybrid");
+        registry.register_indexer::<LoudsAmpIndex>("louds");
+        registry.register_indexer::<PrefixTableAmpIndex>("prefix_table");
+        registry.register_indexer::<SortedArrayAmpIndex>("sorted_array");
+        registry
+    }
+
+    /// Register a factory under `name`, replacing any previous one
+    pub fn register(&mut self, name: &str, factory: BackendFactory) {
+        self.factories.insert(name.to_string(), factory);
+    }
+
+    /// Register an `AmpIndexer` under `name`
+    pub fn register_indexer<T: AmpIndexer + 'static>(&mut self, name: &str) {
+        self.register(name, build_dyn::<T>);
+    }
+
+    /// Names of the registered backends, sorted
+    pub fn names(&self) -> Vec<&str> {
+        self.factories.keys().map(String::as_str).collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.factories.contains_key(name)
+    }
+
+    /// Build an index with the backend registered under `name`
+    pub fn build(
+        &self,
+        name: &str,
+        amps: &[OriginalAmp],
+        options: &BuildOptions,
+    ) -> Result<DynAmpIndex, Box<dyn Error>> {
+        let factory = self.factories.get(name).ok_or_else(|| {
+            format!(
+                "Unknown backend '{}', expected one of: {}",
+                name,
+                self.names().join(", ")
+            )
+        })?;
+        factory(amps, options)
+    }
+
+    /// Build an index as described by `config`
+    pub fn build_from_config(
+        &self,
+        config: &IndexConfig,
+        amps: &[OriginalAmp],
+    ) -> Result<DynAmpIndex, Box<dyn Error>> {
+        self.build(&config.backend, amps, &config.options)
+    }
+}
@@ -0,0 +1,194 @@
+//! Ingest AMP payloads the way they are delivered in production: as remote-settings
+//! collection records, each pointing to a JSON attachment.
+
+use crate::common::{AmpIndexer, OriginalAmp};
+use crate::multi::{FormFactor, IndexKey, MultiIndex};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::path::{Component, Path, PathBuf};
+
+/// Attachment metadata of a record
+#[derive(Clone, Debug, Deserialize)]
+pub struct Attachment {
+    /// Hex encoded SHA-256 of the attachment content
+    pub hash: String,
+    pub size: usize,
+    /// Location of the attachment relative to the attachments base
+    pub location: String,
+    #[serde(default)]
+    pub filename: Option<String>,
+}
+
+/// A record of the collection listing. The collection mixes AMP records with other record
+/// types, which may have no country, form factor or attachment.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Record {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    #[serde(default)]
+    pub country: Option<String>,
+    #[serde(default)]
+    pub form_factor: Option<String>,
+    #[serde(default)]
+    pub attachment: Option<Attachment>,
+}
+
+impl Record {
+    /// The dataset this record belongs to, if its country and form factor are valid
+    pub fn index_key(&self) -> Option<IndexKey> {
+        let form_factor: FormFactor = self.form_factor.as_deref()?.parse().ok()?;
+        Some(IndexKey::new(self.country.as_deref()?, form_factor))
+    }
+
+    /// Whether this is an AMP record with an attachment to load suggestions from
+    fn is_amp(&self) -> bool {
+        self.record_type == AMP_RECORD_TYPE && self.attachment.is_some()
+    }
+}
+
+/// Response of the records endpoint, e.g. `/buckets/main/collections/quicksuggest/records`
+#[derive(Debug, Deserialize)]
+struct RecordsListing {
+    data: Vec<Record>,
+}
+
+/// Record type of the AMP suggestion records
+pub const AMP_RECORD_TYPE: &str = "amp";
+
+/// Where records and their attachments come from
+pub trait RecordSource {
+    /// List all the records of the collection
+    fn records(&self) -> Result<Vec<Record>, Box<dyn Error>>;
+
+    /// Fetch the raw content of an attachment
+    fn fetch_attachment(&self, attachment: &Attachment) -> Result<Vec<u8>, Box<dyn Error>>;
+}
+
+/// A record source backed by a local directory, laid out as:
+///
+/// ```text
+/// <root>/records.json        the records listing
+/// <root>/attachments/<loc>   the attachment for each `attachment.location`
+/// ```
+pub struct FileRecordSource {
+    root: PathBuf,
+}
+
+impl FileRecordSource {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        FileRecordSource { root: root.into() }
+    }
+}
+
+impl RecordSource for FileRecordSource {
+    fn records(&self) -> Result<Vec<Record>, Box<dyn Error>> {
+        let data = std::fs::read(self.root.join("records.json"))?;
+        let listing: RecordsListing = serde_json::from_slice(&data)?;
+        Ok(listing.data)
+    }
+
+    fn fetch_attachment(&self, attachment: &Attachment) -> Result<Vec<u8>, Box<dyn Error>> {
+        // Only follow plain relative locations, so the listing can't read outside the root
+        let location = Path::new(&attachment.location);
+        if !location
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(format!("Invalid attachment location '{}'", attachment.location).into());
+        }
+        let path = self.root.join("attachments").join(location);
+        Ok(std::fs::read(path)?)
+    }
+}
+
+/// Check the size and SHA-256 hash of an attachment's content against its metadata
+pub fn verify_attachment(attachment: &Attachment, content: &[u8]) -> Result<(), Box<dyn Error>> {
+    if content.len() != attachment.size {
+        return Err(format!(
+            "Attachment '{}' size mismatch: expected {}, got {}",
+            attachment.location,
+            attachment.size,
+            content.len()
+        )
+        .into());
+    }
+
+    let hash: String = Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if !hash.eq_ignore_ascii_case(&attachment.hash) {
+        return Err(format!(
+            "Attachment '{}' hash mismatch: expected {}, got {}",
+            attachment.location, attachment.hash, hash
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// AMP records of the source for the given dataset, sorted by id. Records missing their
+/// country, form factor or attachment are skipped.
+pub fn amp_records(
+    source: &impl RecordSource,
+    key: &IndexKey,
+) -> Result<Vec<Record>, Box<dyn Error>> {
+    let mut records: Vec<Record> = source
+        .records()?
+        .into_iter()
+        .filter(|r| r.is_amp() && r.index_key().as_ref() == Some(key))
+        .collect();
+    records.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(records)
+}
+
+/// Fetch, verify and parse the attachments of all the AMP records for the given dataset
+pub fn load_amps(
+    source: &impl RecordSource,
+    key: &IndexKey,
+) -> Result<Vec<OriginalAmp>, Box<dyn Error>> {
+    let mut amps = Vec::new();
+    for record in amp_records(source, key)? {
+        let Some(attachment) = &record.attachment else {
+            continue;
+        };
+        let content = source.fetch_attachment(attachment)?;
+        verify_attachment(attachment, &content)?;
+        let mut suggestions: Vec<OriginalAmp> = serde_json::from_slice(&content)
+            .map_err(|e| format!("Invalid attachment for record '{}': {}", record.id, e))?;
+        amps.append(&mut suggestions);
+    }
+    Ok(amps)
+}
+
+/// Build an index for the given dataset from the records of the source
+pub fn build_index<T: AmpIndexer>(
+    source: &impl RecordSource,
+    key: &IndexKey,
+) -> Result<T, Box<dyn Error>> {
+    let amps = load_amps(source, key)?;
+    T::build(&amps)
+}
+
+/// Build an index for every dataset that has AMP records in the source
+pub fn build_multi_index<T: AmpIndexer>(
+    source: &impl RecordSource,
+) -> Result<MultiIndex<T>, Box<dyn Error>> {
+    let mut keys: Vec<IndexKey> = source
+        .records()?
+        .iter()
+        .filter(|r| r.is_amp())
+        .filter_map(Record::index_key)
+        .collect();
+    keys.sort();
+    keys.dedup();
+
+    let mut multi = MultiIndex::new();
+    for key in keys {
+        let amps = load_amps(source, &key)?;
+        multi.insert(key, &amps)?;
+    }
+    Ok(multi)
+}
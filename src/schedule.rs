@@ -0,0 +1,128 @@
+//! Campaign scheduling: when suggestions start and stop being served.
+//!
+//! Time windows are checked at query time (see `QueryContext::at`), so an index never needs
+//! a rebuild when a campaign starts or ends. `Schedule` lists those transitions, e.g. to
+//! know until when a cached answer stays valid.
+
+use crate::common::OriginalAmp;
+use serde::Serialize;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the current time, as a Unix timestamp in seconds
+pub trait Clock: Send + Sync {
+    fn now(&self) -> i64;
+}
+
+/// The system clock
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64)
+    }
+}
+
+/// A clock that only moves when told to, for tests and replays
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicI64,
+}
+
+impl ManualClock {
+    pub fn new(now: i64) -> Self {
+        ManualClock {
+            now: AtomicI64::new(now),
+        }
+    }
+
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, secs: i64) {
+        self.now.fetch_add(secs, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> i64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransitionKind {
+    /// The suggestion stops being served
+    End,
+    /// The suggestion starts being served
+    Start,
+}
+
+/// A suggestion starting or ending at a point in time
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Transition {
+    /// Unix timestamp in seconds; the suggestion is served from a start on, and no longer
+    /// from an end on
+    pub at: i64,
+    pub kind: TransitionKind,
+    pub block_id: i32,
+}
+
+/// The starts and ends of the suggestions of a dataset, in time order
+#[derive(Clone, Debug, Default)]
+pub struct Schedule {
+    transitions: Vec<Transition>,
+}
+
+impl Schedule {
+    pub fn new(amps: &[OriginalAmp]) -> Self {
+        let mut transitions = Vec::new();
+        for amp in amps {
+            if let Some(at) = amp.start {
+                transitions.push(Transition {
+                    at,
+                    kind: TransitionKind::Start,
+                    block_id: amp.block_id,
+                });
+            }
+            if let Some(at) = amp.end {
+                transitions.push(Transition {
+                    at,
+                    kind: TransitionKind::End,
+                    block_id: amp.block_id,
+                });
+            }
+        }
+        transitions.sort_by_key(|t| (t.at, t.kind, t.block_id));
+        Schedule { transitions }
+    }
+
+    /// Every transition, in time order
+    pub fn transitions(&self) -> &[Transition] {
+        &self.transitions
+    }
+
+    /// Transitions strictly after `now`, in time order
+    pub fn upcoming(&self, now: i64) -> &[Transition] {
+        let first = self.transitions.partition_point(|t| t.at <= now);
+        &self.transitions[first..]
+    }
+
+    /// When the set of served suggestions next changes after `now`, if it ever does
+    pub fn next_change(&self, now: i64) -> Option<i64> {
+        self.upcoming(now).first().map(|t| t.at)
+    }
+
+    pub fn len(&self) -> usize {
+        self.transitions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transitions.is_empty()
+    }
+}
@@ -0,0 +1,537 @@
+use crate::bits::PackedInts;
+use crate::common::{
+    AmpIndexBuilder, AmpIndexer, AmpQuery, AmpResult, BuildOptions, FullKeyword, KeywordEntry,
+    OriginalAmp, PendingKey, PoolRef, QueryContext, QueryPath, StringPool, add_intervals,
+    expand_keyword,
+};
+use crate::explain::{Explanation, FullKeywordResolution, Verdict};
+use crate::full_keywords::{FullKeywordRef, FullKeywords, FullKeywordsBuilder};
+use crate::infix::InfixIndex;
+use crate::suggestions::{SuggestionTable, SuggestionTableBuilder};
+use crate::url_params::UrlParams;
+use std::collections::{BTreeMap, HashMap};
+
+/// Keys per front-coded block
+const BLOCK_SIZE: usize = 16;
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], offset: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*offset];
+        *offset += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte < 0x80 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+/// Sorted keys, front-coded in blocks like a Lucene term dictionary.
+///
+/// Each key is written as `shared prefix length, suffix length, suffix` against the
+/// previous key, the first key of a block sharing nothing so that blocks decode on their
+/// own. `block_offsets` points at each block, whose first key is binary searched.
+struct FrontCodedKeys {
+    bytes: Vec<u8>,
+    block_offsets: Vec<u32>,
+    len: usize,
+}
+
+impl FrontCodedKeys {
+    /// Encode the keys, which must be sorted
+    fn new<K: AsRef<[u8]>>(keys: &[K]) -> Self {
+        let mut bytes = Vec::new();
+        let mut block_offsets = Vec::with_capacity(keys.len().div_ceil(BLOCK_SIZE));
+        let mut previous: &[u8] = &[];
+        for (i, key) in keys.iter().enumerate() {
+            let key = key.as_ref();
+            let shared = if i % BLOCK_SIZE == 0 {
+                block_offsets.push(bytes.len() as u32);
+                0
+            } else {
+                previous.iter().zip(key).take_while(|(a, b)| a == b).count()
+            };
+            write_varint(&mut bytes, shared);
+            write_varint(&mut bytes, key.len() - shared);
+            bytes.extend_from_slice(&key[shared..]);
+            previous = key;
+        }
+        bytes.shrink_to_fit();
+        FrontCodedKeys {
+            bytes,
+            block_offsets,
+            len: keys.len(),
+        }
+    }
+
+    /// The first key of `block`, stored whole
+    fn first_key(&self, block: usize) -> &[u8] {
+        let mut offset = self.block_offsets[block] as usize;
+        read_varint(&self.bytes, &mut offset);
+        let len = read_varint(&self.bytes, &mut offset);
+        &self.bytes[offset..offset + len]
+    }
+
+    /// A cursor on the first key not less than `query`
+    fn seek(&self, query: &str) -> Cursor<'_> {
+        let query = query.as_bytes();
+        // The last block starting before `query` holds the first key not less than it, or
+        // that key starts the next block
+        let block = self.blocks_before(query).saturating_sub(1);
+        let mut cursor = Cursor {
+            keys: self,
+            next: block * BLOCK_SIZE,
+            offset: self.block_offsets.get(block).map_or(0, |&o| o as usize),
+            key: Vec::new(),
+            pending: false,
+        };
+        while cursor.next_key().is_some() {
+            if cursor.key.as_slice() >= query {
+                cursor.pending = true;
+                break;
+            }
+        }
+        cursor
+    }
+
+    /// The key at `pos`, decoded from the start of its block
+    fn key_at(&self, pos: usize) -> String {
+        let block = pos / BLOCK_SIZE;
+        let mut cursor = Cursor {
+            keys: self,
+            next: block * BLOCK_SIZE,
+            offset: self.block_offsets[block] as usize,
+            key: Vec::new(),
+            pending: false,
+        };
+        while cursor.next_key().is_some_and(|p| p < pos) {}
+        cursor.key().to_string()
+    }
+
+    /// Number of blocks whose first key is less than `query`
+    fn blocks_before(&self, query: &[u8]) -> usize {
+        let (mut lo, mut hi) = (0, self.block_offsets.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.first_key(mid) < query {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+
+    /// Heap bytes held
+    fn size_bytes(&self) -> usize {
+        self.bytes.capacity() + self.block_offsets.capacity() * 4
+    }
+}
+
+/// Sequential decoder over `FrontCodedKeys`
+struct Cursor<'a> {
+    keys: &'a FrontCodedKeys,
+    /// Position of the next key to decode
+    next: usize,
+    /// Byte offset of the next key to decode
+    offset: usize,
+    key: Vec<u8>,
+    /// The current key was decoded by `seek` and is yet to be returned
+    pending: bool,
+}
+
+impl Cursor<'_> {
+    /// Move to the next key, returning its position
+    fn next_key(&mut self) -> Option<usize> {
+        if self.pending {
+            self.pending = false;
+            return Some(self.next - 1);
+        }
+        if self.next >= self.keys.len {
+            return None;
+        }
+        let bytes = &self.keys.bytes;
+        let shared = read_varint(bytes, &mut self.offset);
+        let suffix_len = read_varint(bytes, &mut self.offset);
+        self.key.truncate(shared);
+        self.key
+            .extend_from_slice(&bytes[self.offset..self.offset + suffix_len]);
+        self.offset += suffix_len;
+        self.next += 1;
+        Some(self.next - 1)
+    }
+
+    /// The key last moved to
+    fn key(&self) -> &str {
+        // Keys are UTF-8 and stored whole
+        std::str::from_utf8(&self.key).unwrap_or_default()
+    }
+}
+
+/// AMP Index over a sorted, front-coded array of the keyword interval keys, searched by
+/// binary search over its blocks then a short sequential scan
+pub struct SortedArrayAmpIndex {
+    keys: FrontCodedKeys,
+
+    /// Value columns, by key position
+    suggestion_idx: PackedInts,
+    min_prefix_len: PackedInts,
+    full_keyword_ref: PackedInts,
+    /// `(key position, char length)` of the prefixes the intervals exclude, sorted
+    excluded: Vec<(u32, u32)>,
+    full_keywords: FullKeywords,
+
+    /// Storage for suggestions
+    suggestions: SuggestionTable,
+
+    /// Word-boundary suffixes of the full keywords, when enabled
+    infix: Option<InfixIndex>,
+}
+
+impl AmpIndexer for SortedArrayAmpIndex {
+    type Builder<'p> = SortedArrayIndexBuilder<'p>;
+
+    fn builder(options: &BuildOptions) -> Self::Builder<'static> {
+        SortedArrayIndexBuilder::new(PoolRef::Owned(StringPool::new()), options)
+    }
+
+    fn builder_with_pool<'p>(
+        pool: &'p mut StringPool,
+        options: &BuildOptions,
+    ) -> Self::Builder<'p> {
+        SortedArrayIndexBuilder::new(PoolRef::Borrowed(pool), options)
+    }
+}
+
+impl AmpQuery for SortedArrayAmpIndex {
+    fn query_with_context(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<Vec<AmpResult>, Box<dyn std::error::Error>> {
+        Ok(self.query_with_path(query, ctx)?.0)
+    }
+
+    fn query_with_path(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<(Vec<AmpResult>, QueryPath), Box<dyn std::error::Error>> {
+        let mut results = Vec::new();
+        let query_len = query.chars().count();
+
+        // Scan keys in order from the query, picking the first one that qualifies
+        let mut cursor = self.keys.seek(query);
+        while let Some(pos) = cursor.next_key() {
+            if !cursor.key().starts_with(query) {
+                break;
+            }
+            let suggestion_idx = self.suggestion_idx.get(pos) as usize;
+            if query_len >= self.min_prefix_len.get(pos) as usize
+                && !self.excludes(pos, query_len)
+                && self.rejection(suggestion_idx, ctx).is_none()
+            {
+                let full_keyword = self.resolve_full_keyword(pos, cursor.key());
+                self.build_result(suggestion_idx, full_keyword, &ctx.url_params, &mut results)?;
+                return Ok((results, QueryPath::Tree));
+            }
+        }
+
+        // Fall back to the suffixes of full keywords
+        if let Some(infix) = &self.infix {
+            if let Some(m) = infix.find(query, |idx| self.rejection(idx, ctx)) {
+                self.build_result(
+                    m.suggestion_idx,
+                    m.full_keyword.to_string(),
+                    &ctx.url_params,
+                    &mut results,
+                )?;
+            }
+            return Ok((results, QueryPath::Infix));
+        }
+
+        Ok((results, QueryPath::Tree))
+    }
+
+    fn stats(&self) -> HashMap<String, usize> {
+        let mut stats = HashMap::new();
+
+        stats.insert("keyword_count".into(), self.keys.len);
+        stats.insert("key_blocks".into(), self.keys.block_offsets.len());
+        stats.insert("keyword_exclusions".into(), self.excluded.len());
+        stats.insert("keyword_structure_bytes".into(), self.keyword_bytes());
+        let refs = (0..self.full_keyword_ref.len()).map(|i| self.full_keyword_ref(i));
+        self.full_keywords.add_stats(refs, &mut stats);
+        self.suggestions.add_stats(&mut stats);
+        stats.insert(
+            "infix_entries".into(),
+            self.infix.as_ref().map_or(0, |infix| infix.len()),
+        );
+        stats.insert(
+            "infix_bytes".into(),
+            self.infix.as_ref().map_or(0, |infix| infix.size_bytes()),
+        );
+
+        stats
+    }
+
+    fn explain_with_context(
+        &self,
+        query: &str,
+        ctx: &QueryContext,
+    ) -> Result<Explanation, Box<dyn std::error::Error>> {
+        let mut explanation = Explanation::new(query, QueryPath::Tree);
+        let query_len = explanation.query_len;
+
+        // Same scan as `query_with_path`
+        let mut cursor = self.keys.seek(query);
+        while let Some(pos) = cursor.next_key() {
+            let keyword = cursor.key();
+            if !keyword.starts_with(query) {
+                break;
+            }
+            let suggestion_idx = self.suggestion_idx.get(pos) as usize;
+            let min_prefix_len = self.min_prefix_len.get(pos) as usize;
+            let block_id = self.suggestions.block_id(suggestion_idx);
+            let verdict = if query_len < min_prefix_len {
+                Verdict::TooShort
+            } else if self.excludes(pos, query_len) {
+                Verdict::Excluded
+            } else {
+                self.rejection(suggestion_idx, ctx)
+                    .unwrap_or(Verdict::Selected)
+            };
+            explanation.scanned(keyword, min_prefix_len, block_id, QueryPath::Tree, verdict);
+            if verdict == Verdict::Selected {
+                explanation.full_keyword = Some(FullKeywordResolution::from_full_keyword(
+                    &self.full_keyword(pos),
+                    keyword,
+                ));
+                return Ok(explanation);
+            }
+        }
+
+        if let Some(infix) = &self.infix {
+            explanation.path = QueryPath::Infix;
+            infix.explain(
+                &mut explanation,
+                query,
+                |idx| self.rejection(idx, ctx),
+                |idx| self.suggestions.block_id(idx),
+            );
+        }
+        Ok(explanation)
+    }
+
+    fn iter_prefix<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = KeywordEntry> + 'a> {
+        let mut cursor = self.keys.seek(prefix);
+        let prefix = prefix.to_string();
+        Box::new(std::iter::from_fn(move || {
+            let pos = cursor.next_key()?;
+            if !cursor.key().starts_with(&prefix) {
+                return None;
+            }
+            let suggestion_idx = self.suggestion_idx.get(pos) as usize;
+            Some((
+                cursor.key().to_string(),
+                self.min_prefix_len.get(pos) as usize,
+                self.suggestions.block_id(suggestion_idx),
+            ))
+        }))
+    }
+
+    fn keywords_for(&self, block_id: i32) -> Vec<String> {
+        let mut keywords: Vec<String> = Vec::new();
+        let mut cursor = self.keys.seek("");
+        while let Some(pos) = cursor.next_key() {
+            let (suggestion_idx, min_prefix_len) = self.value_at(pos);
+            if self.suggestions.block_id(suggestion_idx) != block_id {
+                continue;
+            }
+            keywords.extend(
+                expand_keyword(cursor.key(), min_prefix_len)
+                    .into_iter()
+                    .zip(min_prefix_len..)
+                    .filter(|(_, len)| !self.excludes(pos, *len))
+                    .map(|(keyword, _)| keyword),
+            );
+        }
+        keywords.sort();
+        keywords
+    }
+}
+
+/// Builds a `SortedArrayAmpIndex` one record at a time. Keys are sorted and encoded in
+/// `finish`.
+pub struct SortedArrayIndexBuilder<'p> {
+    index: SortedArrayAmpIndex,
+    pool: PoolRef<'p>,
+    keys: BTreeMap<String, PendingKey>,
+    full_keywords: FullKeywordsBuilder,
+    suggestions: SuggestionTableBuilder,
+}
+
+impl<'p> SortedArrayIndexBuilder<'p> {
+    fn new(pool: PoolRef<'p>, options: &BuildOptions) -> Self {
+        SortedArrayIndexBuilder {
+            index: SortedArrayAmpIndex::empty(options.infix_min_chars.map(InfixIndex::new)),
+            pool,
+            keys: BTreeMap::new(),
+            full_keywords: FullKeywordsBuilder::new(),
+            suggestions: SuggestionTableBuilder::new().compression(options.string_compression),
+        }
+    }
+}
+
+impl AmpIndexBuilder for SortedArrayIndexBuilder<'_> {
+    type Index = SortedArrayAmpIndex;
+
+    fn add(&mut self, amp: &OriginalAmp) -> Result<(), Box<dyn std::error::Error>> {
+        let index = &mut self.index;
+        let pool = &mut *self.pool;
+
+        let sidx = self.suggestions.push(amp, pool);
+
+        add_intervals(&mut self.keys, &mut self.full_keywords, pool, sidx, amp)?;
+
+        if let Some(infix) = &mut index.infix {
+            infix.add_suggestion(sidx, amp, pool);
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<SortedArrayAmpIndex, Box<dyn std::error::Error>> {
+        let index = &mut self.index;
+
+        let keys: Vec<&String> = self.keys.keys().collect();
+        index.keys = FrontCodedKeys::new(&keys);
+
+        // Value columns, in key order
+        let mut suggestion_idx = Vec::with_capacity(keys.len());
+        let mut min_prefix_len = Vec::with_capacity(keys.len());
+        let mut full_keyword_ref = Vec::with_capacity(keys.len());
+        for (pos, key) in self.keys.values().enumerate() {
+            suggestion_idx.push(key.suggestion_idx as u64);
+            min_prefix_len.push(key.min_len as u64);
+            full_keyword_ref.push(key.full_keyword.to_bits());
+            index
+                .excluded
+                .extend(key.excluded.iter().map(|&len| (pos as u32, len as u32)));
+        }
+        index.excluded.shrink_to_fit();
+        index.suggestion_idx = PackedInts::from_values(&suggestion_idx);
+        index.min_prefix_len = PackedInts::from_values(&min_prefix_len);
+        index.full_keyword_ref = PackedInts::from_values(&full_keyword_ref);
+        index.full_keywords = self.full_keywords.finish();
+
+        index.suggestions = self.suggestions.finish();
+        if let Some(infix) = &mut index.infix {
+            infix.finish();
+        }
+        Ok(self.index)
+    }
+}
+
+impl SortedArrayAmpIndex {
+    /// An empty index, only ever handed to a builder
+    fn empty(infix: Option<InfixIndex>) -> Self {
+        SortedArrayAmpIndex {
+            keys: FrontCodedKeys::new::<&str>(&[]),
+            suggestion_idx: PackedInts::default(),
+            min_prefix_len: PackedInts::default(),
+            full_keyword_ref: PackedInts::default(),
+            excluded: Vec::new(),
+            full_keywords: FullKeywords::default(),
+            suggestions: SuggestionTable::default(),
+            infix,
+        }
+    }
+
+    /// Number of collapsed keywords
+    pub fn key_count(&self) -> usize {
+        self.keys.len
+    }
+
+    /// The collapsed keyword at `pos` in key order
+    pub(crate) fn key_at(&self, pos: usize) -> String {
+        self.keys.key_at(pos)
+    }
+
+    /// `(suggestion_idx, min_prefix_len)` of the key at `pos`
+    pub(crate) fn value_at(&self, pos: usize) -> (usize, usize) {
+        (
+            self.suggestion_idx.get(pos) as usize,
+            self.min_prefix_len.get(pos) as usize,
+        )
+    }
+
+    pub(crate) fn has_infix(&self) -> bool {
+        self.infix.is_some()
+    }
+
+    pub(crate) fn block_id(&self, suggestion_idx: usize) -> i32 {
+        self.suggestions.block_id(suggestion_idx)
+    }
+
+    /// Whether the interval of the key at `pos` excludes its prefix of `len` chars
+    pub(crate) fn excludes(&self, pos: usize, len: usize) -> bool {
+        self.excluded
+            .binary_search(&(pos as u32, len as u32))
+            .is_ok()
+    }
+
+    /// Heap bytes of the keyword structure: the front-coded keys, the value columns and
+    /// the exclusions, excluding the full keyword strings
+    pub fn keyword_bytes(&self) -> usize {
+        self.keys.size_bytes()
+            + self.suggestion_idx.size_bytes()
+            + self.min_prefix_len.size_bytes()
+            + self.full_keyword_ref.size_bytes()
+            + self.excluded.capacity() * std::mem::size_of::<(u32, u32)>()
+    }
+
+    fn full_keyword_ref(&self, pos: usize) -> FullKeywordRef {
+        FullKeywordRef::from_bits(self.full_keyword_ref.get(pos))
+    }
+
+    pub(crate) fn full_keyword(&self, pos: usize) -> FullKeyword {
+        self.full_keywords.get(self.full_keyword_ref(pos))
+    }
+
+    /// The full keyword of the key at `pos`, which is `keyword`
+    pub(crate) fn resolve_full_keyword(&self, pos: usize, keyword: &str) -> String {
+        self.full_keywords
+            .resolve(self.full_keyword_ref(pos), keyword)
+    }
+
+    /// Why `ctx` skips the suggestion at `suggestion_idx`, if it does
+    pub(crate) fn rejection(&self, suggestion_idx: usize, ctx: &QueryContext) -> Option<Verdict> {
+        self.suggestions.rejection(suggestion_idx, ctx)
+    }
+
+    /// Build result from a suggestion and dictionaries
+    pub(crate) fn build_result(
+        &self,
+        suggestion_idx: usize,
+        full_keyword: String,
+        params: &UrlParams,
+        results: &mut Vec<AmpResult>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        results.push(
+            self.suggestions
+                .result(suggestion_idx, full_keyword, params),
+        );
+        Ok(())
+    }
+}
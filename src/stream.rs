@@ -0,0 +1,89 @@
+//! Streaming JSON loading: records are deserialized and handed over one at a time, so the
+//! whole `Vec<OriginalAmp>` never has to be materialized while building an index.
+
+use crate::common::{AmpIndexBuilder, AmpIndexer, BuildOptions, OriginalAmp, StringPool};
+use serde::Deserializer;
+use serde::de::{self, SeqAccess, Visitor};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// Visits the top-level JSON array, passing each record to the callback as it is parsed
+struct RecordVisitor<F> {
+    callback: F,
+}
+
+impl<'de, F> Visitor<'de> for RecordVisitor<F>
+where
+    F: FnMut(OriginalAmp) -> Result<(), Box<dyn Error>>,
+{
+    type Value = usize;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "an array of AMP records")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<usize, A::Error> {
+        let mut count = 0;
+        while let Some(amp) = seq.next_element::<OriginalAmp>()? {
+            (self.callback)(amp).map_err(de::Error::custom)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// Stream AMP records from a reader over a JSON array, returning the number of records
+pub fn stream_amp_records<R, F>(reader: R, callback: F) -> Result<usize, Box<dyn Error>>
+where
+    R: Read,
+    F: FnMut(OriginalAmp) -> Result<(), Box<dyn Error>>,
+{
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let count = deserializer.deserialize_seq(RecordVisitor { callback })?;
+    deserializer.end()?;
+    Ok(count)
+}
+
+/// Stream AMP records from a JSON file, returning the number of records
+pub fn stream_amp_data<P, F>(path: P, callback: F) -> Result<usize, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    F: FnMut(OriginalAmp) -> Result<(), Box<dyn Error>>,
+{
+    let reader = BufReader::new(File::open(path)?);
+    stream_amp_records(reader, callback)
+}
+
+/// Feed every record of a JSON file into an index builder
+pub fn stream_into<P, B>(path: P, builder: &mut B) -> Result<usize, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    B: AmpIndexBuilder,
+{
+    stream_amp_data(path, |amp| builder.add(&amp))
+}
+
+/// Build an index straight from a JSON file, interning dictionary values into `pool`
+pub fn build_from_file_with_pool<T, P>(path: P, pool: &mut StringPool) -> Result<T, Box<dyn Error>>
+where
+    T: AmpIndexer,
+    P: AsRef<Path>,
+{
+    let mut builder = T::builder_with_pool(pool, &BuildOptions::default());
+    stream_into(path, &mut builder)?;
+    builder.finish()
+}
+
+/// Build an index straight from a JSON file
+pub fn build_from_file<T, P>(path: P) -> Result<T, Box<dyn Error>>
+where
+    T: AmpIndexer,
+    P: AsRef<Path>,
+{
+    let mut builder = T::builder(&BuildOptions::default());
+    stream_into(path, &mut builder)?;
+    builder.finish()
+}
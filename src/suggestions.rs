@@ -0,0 +1,386 @@
+//! Columnar storage of the suggestions, shared by all the backends.
+//!
+//! Each field of a suggestion is a column indexed by suggestion: the repeated strings
+//! (titles, advertisers, URL templates, ...) are dictionary encoded and their ids
+//! bit-packed to the width of the largest id, and the URL suffixes are concatenated into a
+//! single buffer cut by an offsets column.
+//!
+//! With `StringCompression::Fsst`, titles and URL suffixes are compressed with symbol
+//! tables trained when the table is built, and decompressed one string at a time by
+//! `result`.
+
+use crate::bits::PackedInts;
+use crate::common::{
+    AmpResult, OriginalAmp, QueryContext, StringCompression, StringPool, TimeWindow,
+};
+use crate::explain::Verdict;
+use crate::fsst::SymbolTable;
+use crate::url_params::{UrlParams, render_url};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Split a URL into a template shared with other URLs (the part before the query string,
+/// or before the last path segment) and its suffix
+pub fn split_url(url: &str) -> (&str, &str) {
+    let split_idx = url.find('?').unwrap_or_else(|| url.rfind('/').unwrap_or(0));
+    url.split_at(split_idx)
+}
+
+/// Distinct strings numbered in insertion order
+#[derive(Debug, Default)]
+struct Dictionary {
+    values: Vec<Arc<str>>,
+    ids: HashMap<Arc<str>, u32>,
+}
+
+impl Dictionary {
+    fn id(&mut self, value: &str, pool: &mut StringPool) -> u64 {
+        if let Some(&id) = self.ids.get(value) {
+            return id as u64;
+        }
+        let id = self.values.len() as u32;
+        let value = pool.intern(value);
+        self.values.push(value.clone());
+        self.ids.insert(value, id);
+        id as u64
+    }
+
+    fn finish(mut self) -> Vec<Arc<str>> {
+        self.values.shrink_to_fit();
+        self.values
+    }
+}
+
+/// Strings looked up by position
+#[derive(Debug)]
+enum Texts {
+    /// Dictionary values, shared through the string pool
+    Pooled(Vec<Arc<str>>),
+    /// One buffer cut by offsets
+    Concatenated { buffer: String, offsets: PackedInts },
+    /// Compressed one by one, then concatenated
+    Compressed {
+        symbols: SymbolTable,
+        buffer: Vec<u8>,
+        offsets: PackedInts,
+    },
+}
+
+impl Default for Texts {
+    fn default() -> Self {
+        Texts::Pooled(Vec::new())
+    }
+}
+
+impl Texts {
+    fn compressed<S: AsRef<str>>(strings: &[S]) -> Self {
+        let symbols = SymbolTable::train(strings);
+        let mut buffer = Vec::new();
+        let mut offsets = vec![0];
+        for string in strings {
+            symbols.compress(string.as_ref(), &mut buffer);
+            offsets.push(buffer.len() as u64);
+        }
+        buffer.shrink_to_fit();
+        Texts::Compressed {
+            symbols,
+            buffer,
+            offsets: PackedInts::from_values(&offsets),
+        }
+    }
+
+    fn get(&self, idx: usize) -> Cow<'_, str> {
+        match self {
+            Texts::Pooled(values) => Cow::Borrowed(&values[idx]),
+            Texts::Concatenated { buffer, offsets } => {
+                Cow::Borrowed(&buffer[offsets.get(idx) as usize..offsets.get(idx + 1) as usize])
+            }
+            Texts::Compressed {
+                symbols,
+                buffer,
+                offsets,
+            } => Cow::Owned(
+                symbols
+                    .decompress(&buffer[offsets.get(idx) as usize..offsets.get(idx + 1) as usize]),
+            ),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Texts::Pooled(values) => values.len(),
+            Texts::Concatenated { offsets, .. } | Texts::Compressed { offsets, .. } => {
+                offsets.len().saturating_sub(1)
+            }
+        }
+    }
+
+    /// Heap bytes held, excluding pooled strings
+    fn size_bytes(&self) -> usize {
+        match self {
+            Texts::Pooled(_) => 0,
+            Texts::Concatenated { buffer, offsets } => buffer.capacity() + offsets.size_bytes(),
+            Texts::Compressed {
+                symbols,
+                buffer,
+                offsets,
+            } => symbols.size_bytes() + buffer.capacity() + offsets.size_bytes(),
+        }
+    }
+}
+
+/// Builds a `SuggestionTable` one suggestion at a time
+#[derive(Debug, Default)]
+pub struct SuggestionTableBuilder {
+    icon_prefix: &'static str,
+    compression: StringCompression,
+    titles: Dictionary,
+    advertisers: Dictionary,
+    iab_categories: Dictionary,
+    icons: Dictionary,
+    url_templates: Dictionary,
+    click_templates: Dictionary,
+    imp_templates: Dictionary,
+    /// One row of ids per suggestion, packed into columns in `finish`
+    rows: Vec<[u64; COLUMNS]>,
+    block_ids: Vec<i32>,
+    suffixes: String,
+    suffix_offsets: Vec<u64>,
+    windows: HashMap<usize, TimeWindow>,
+}
+
+const TITLE: usize = 0;
+const ADVERTISER: usize = 1;
+const IAB_CATEGORY: usize = 2;
+const ICON: usize = 3;
+const URL_TEMPLATE: usize = 4;
+const CLICK_TEMPLATE: usize = 5;
+const IMP_TEMPLATE: usize = 6;
+const COLUMNS: usize = 7;
+
+impl SuggestionTableBuilder {
+    pub fn new() -> Self {
+        SuggestionTableBuilder {
+            suffix_offsets: vec![0],
+            ..Default::default()
+        }
+    }
+
+    /// Serve icons as `prefix` followed by the icon id of the record
+    pub fn icon_prefix(mut self, prefix: &'static str) -> Self {
+        self.icon_prefix = prefix;
+        self
+    }
+
+    /// Compress titles and URL suffixes as `compression` says
+    pub fn compression(mut self, compression: StringCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Number of suggestions pushed so far
+    pub fn len(&self) -> usize {
+        self.block_ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.block_ids.is_empty()
+    }
+
+    /// Add the suggestion of `amp`, interning its strings into `pool`, and return its index
+    pub fn push(&mut self, amp: &OriginalAmp, pool: &mut StringPool) -> usize {
+        let idx = self.block_ids.len();
+
+        let mut row = [0; COLUMNS];
+        row[TITLE] = self.titles.id(&amp.title, pool);
+        row[ADVERTISER] = self.advertisers.id(&amp.advertiser, pool);
+        row[IAB_CATEGORY] = self.iab_categories.id(&amp.iab_category, pool);
+        row[ICON] = if self.icon_prefix.is_empty() {
+            self.icons.id(&amp.icon_id, pool)
+        } else {
+            let icon = format!("{}{}", self.icon_prefix, amp.icon_id);
+            self.icons.id(&icon, pool)
+        };
+
+        let urls = [
+            (URL_TEMPLATE, &mut self.url_templates, &amp.url),
+            (CLICK_TEMPLATE, &mut self.click_templates, &amp.click_url),
+            (IMP_TEMPLATE, &mut self.imp_templates, &amp.impression_url),
+        ];
+        for (column, templates, url) in urls {
+            let (template, suffix) = split_url(url);
+            row[column] = templates.id(template, pool);
+            self.suffixes.push_str(suffix);
+            self.suffix_offsets.push(self.suffixes.len() as u64);
+        }
+
+        self.rows.push(row);
+        self.block_ids.push(amp.block_id);
+        if let Some(window) = amp.window() {
+            self.windows.insert(idx, window);
+        }
+        idx
+    }
+
+    pub fn finish(mut self) -> SuggestionTable {
+        let column = |c: usize| {
+            let values: Vec<u64> = self.rows.iter().map(|row| row[c]).collect();
+            PackedInts::from_values(&values)
+        };
+        let columns = std::array::from_fn(column);
+
+        // Block ids are stored as offsets from the smallest one
+        let block_id_base = self.block_ids.iter().copied().min().unwrap_or(0);
+        let block_id_offsets: Vec<u64> = self
+            .block_ids
+            .iter()
+            .map(|&id| (id as i64 - block_id_base as i64) as u64)
+            .collect();
+
+        let titles = self.titles.finish();
+        let (titles, suffixes) = match self.compression {
+            StringCompression::None => {
+                self.suffixes.shrink_to_fit();
+                let suffixes = Texts::Concatenated {
+                    buffer: self.suffixes,
+                    offsets: PackedInts::from_values(&self.suffix_offsets),
+                };
+                (Texts::Pooled(titles), suffixes)
+            }
+            StringCompression::Fsst => {
+                let suffixes: Vec<&str> = self
+                    .suffix_offsets
+                    .windows(2)
+                    .map(|w| &self.suffixes[w[0] as usize..w[1] as usize])
+                    .collect();
+                (Texts::compressed(&titles), Texts::compressed(&suffixes))
+            }
+        };
+
+        self.windows.shrink_to_fit();
+        SuggestionTable {
+            len: self.block_ids.len(),
+            columns,
+            block_id_base,
+            block_ids: PackedInts::from_values(&block_id_offsets),
+            suffixes,
+            titles,
+            advertisers: self.advertisers.finish(),
+            iab_categories: self.iab_categories.finish(),
+            icons: self.icons.finish(),
+            url_templates: self.url_templates.finish(),
+            click_templates: self.click_templates.finish(),
+            imp_templates: self.imp_templates.finish(),
+            windows: self.windows,
+        }
+    }
+}
+
+/// The suggestions of an index, decoded one field at a time
+#[derive(Debug, Default)]
+pub struct SuggestionTable {
+    len: usize,
+    /// Dictionary ids, indexed by the `TITLE`, `ADVERTISER`, ... constants
+    columns: [PackedInts; COLUMNS],
+    block_id_base: i32,
+    block_ids: PackedInts,
+    /// URL, click URL and impression URL suffixes of every suggestion, in this order
+    suffixes: Texts,
+    titles: Texts,
+    advertisers: Vec<Arc<str>>,
+    iab_categories: Vec<Arc<str>>,
+    icons: Vec<Arc<str>>,
+    url_templates: Vec<Arc<str>>,
+    click_templates: Vec<Arc<str>>,
+    imp_templates: Vec<Arc<str>>,
+    /// Time windows of the suggestions that have one, by suggestion index
+    windows: HashMap<usize, TimeWindow>,
+}
+
+impl SuggestionTable {
+    /// Number of suggestions
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn block_id(&self, idx: usize) -> i32 {
+        (self.block_id_base as i64 + self.block_ids.get(idx) as i64) as i32
+    }
+
+    pub fn advertiser(&self, idx: usize) -> &str {
+        &self.advertisers[self.columns[ADVERTISER].get(idx) as usize]
+    }
+
+    pub fn window(&self, idx: usize) -> Option<&TimeWindow> {
+        self.windows.get(&idx)
+    }
+
+    /// Why `ctx` skips the suggestion at `idx`, if it does
+    pub fn rejection(&self, idx: usize, ctx: &QueryContext) -> Option<Verdict> {
+        if ctx.skips_nothing() {
+            return None;
+        }
+        ctx.rejects(self.block_id(idx), self.advertiser(idx), self.window(idx))
+    }
+
+    /// Decode the suggestion at `idx` as served for `full_keyword`, filling the URL
+    /// placeholders from `params`
+    pub fn result(&self, idx: usize, full_keyword: String, params: &UrlParams) -> AmpResult {
+        let field = |dict: &[Arc<str>], column: usize| {
+            dict[self.columns[column].get(idx) as usize].to_string()
+        };
+        let url = |templates: &[Arc<str>], column: usize, n: usize| {
+            let template = &templates[self.columns[column].get(idx) as usize];
+            render_url(template, &self.suffixes.get(3 * idx + n), params)
+        };
+
+        AmpResult {
+            title: self
+                .titles
+                .get(self.columns[TITLE].get(idx) as usize)
+                .into_owned(),
+            url: url(&self.url_templates, URL_TEMPLATE, 0),
+            click_url: url(&self.click_templates, CLICK_TEMPLATE, 1),
+            impression_url: url(&self.imp_templates, IMP_TEMPLATE, 2),
+            advertiser: field(&self.advertisers, ADVERTISER),
+            block_id: self.block_id(idx),
+            iab_category: field(&self.iab_categories, IAB_CATEGORY),
+            icon: field(&self.icons, ICON),
+            full_keyword,
+        }
+    }
+
+    /// Heap bytes of the columns, the suffixes and the compressed titles, excluding the
+    /// dictionary strings
+    pub fn size_bytes(&self) -> usize {
+        self.columns
+            .iter()
+            .map(PackedInts::size_bytes)
+            .sum::<usize>()
+            + self.block_ids.size_bytes()
+            + self.suffixes.size_bytes()
+            + self.titles.size_bytes()
+            + self.windows.capacity() * std::mem::size_of::<(usize, TimeWindow)>()
+    }
+
+    /// Add the suggestion and dictionary counts to an index's stats
+    pub fn add_stats(&self, stats: &mut HashMap<String, usize>) {
+        stats.insert("suggestions_count".into(), self.len);
+        stats.insert("suggestion_table_bytes".into(), self.size_bytes());
+        stats.insert("advertisers_count".into(), self.advertisers.len());
+        stats.insert("titles_count".into(), self.titles.len());
+        stats.insert("url_templates_count".into(), self.url_templates.len());
+        stats.insert("iab_categories_count".into(), self.iab_categories.len());
+        stats.insert("icons_count".into(), self.icons.len());
+        stats.insert("windows_count".into(), self.windows.len());
+        stats.insert(
+            "suggestion_strings_bytes".into(),
+            self.titles.size_bytes() + self.suffixes.size_bytes(),
+        );
+    }
+}
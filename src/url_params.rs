@@ -0,0 +1,125 @@
+//! Per-request URL parameters.
+//!
+//! URLs of the records may hold placeholders such as `{request_id}`, which are filled when
+//! a result is built, from the `UrlParams` of the query. Both halves produced by
+//! `extract_template` are rendered; a placeholder never straddles them since names hold
+//! neither `?` nor `/`.
+
+use crate::multi::FormFactor;
+use std::fmt::Write;
+
+/// A placeholder of a URL, written `{name}`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UrlParam {
+    /// Opaque id of the request
+    RequestId,
+    /// Region of the user, e.g. an ISO 3166 country or subdivision code
+    Region,
+    /// Form factor of the client: `desktop` or `phone`
+    FormFactor,
+}
+
+impl UrlParam {
+    pub const ALL: [UrlParam; 3] = [UrlParam::RequestId, UrlParam::Region, UrlParam::FormFactor];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            UrlParam::RequestId => "request_id",
+            UrlParam::Region => "region",
+            UrlParam::FormFactor => "form_factor",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|param| param.name() == name)
+    }
+
+    /// The placeholder as written in URLs
+    pub fn placeholder(&self) -> String {
+        format!("{{{}}}", self.name())
+    }
+}
+
+/// Values of the placeholders for one query. Values are raw: they are percent-encoded when
+/// substituted, so they must not be encoded beforehand.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UrlParams {
+    pub request_id: Option<String>,
+    pub region: Option<String>,
+    pub form_factor: Option<FormFactor>,
+}
+
+impl UrlParams {
+    pub fn is_empty(&self) -> bool {
+        self.request_id.is_none() && self.region.is_none() && self.form_factor.is_none()
+    }
+
+    /// Set the value of the placeholder `name`, checking its type
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match UrlParam::from_name(name) {
+            Some(UrlParam::RequestId) => self.request_id = Some(value.to_string()),
+            Some(UrlParam::Region) => self.region = Some(value.to_string()),
+            Some(UrlParam::FormFactor) => self.form_factor = Some(value.parse()?),
+            None => return Err(format!("Unknown URL parameter '{}'", name)),
+        }
+        Ok(())
+    }
+
+    fn write_value(&self, param: UrlParam, out: &mut String) {
+        match param {
+            UrlParam::RequestId => percent_encode_into(self.request_id.as_deref(), out),
+            UrlParam::Region => percent_encode_into(self.region.as_deref(), out),
+            // Always URL-safe
+            UrlParam::FormFactor => {
+                if let Some(form_factor) = self.form_factor {
+                    write!(out, "{}", form_factor).unwrap();
+                }
+            }
+        }
+    }
+}
+
+/// Percent-encode everything but RFC 3986 unreserved characters, so that the value is safe
+/// in a path segment as well as in a query component. A missing value renders empty.
+fn percent_encode_into(value: Option<&str>, out: &mut String) {
+    for b in value.unwrap_or_default().bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(b as char)
+            }
+            _ => write!(out, "%{:02X}", b).unwrap(),
+        }
+    }
+}
+
+/// Render `part` into `out`, substituting placeholders. The rest of the URL is copied as
+/// is: it is already encoded, and braces not naming a placeholder are kept.
+fn render_into(part: &str, params: &UrlParams, out: &mut String) {
+    let mut rest = part;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        match after
+            .find('}')
+            .and_then(|close| Some((UrlParam::from_name(&after[..close])?, close)))
+        {
+            Some((param, close)) => {
+                params.write_value(param, out);
+                rest = &after[close + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+}
+
+/// Rebuild a URL from its template and suffix, filling its placeholders from `params`
+pub fn render_url(template: &str, suffix: &str, params: &UrlParams) -> String {
+    let mut url = String::with_capacity(template.len() + suffix.len());
+    render_into(template, params, &mut url);
+    render_into(suffix, params, &mut url);
+    url
+}
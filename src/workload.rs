@@ -0,0 +1,232 @@
+//! Synthetic query workloads that replay how people type into the URL bar.
+//!
+//! A session picks a full keyword, popular ones more often (Zipf distributed by rank), and
+//! types it one character at a time, issuing a query per keystroke. Queries that match no
+//! keyword are mixed in at a configurable rate. Everything is drawn from a seeded
+//! generator, so the same config over the same data always yields the same queries.
+
+use crate::common::OriginalAmp;
+use crate::infix::word_suffixes;
+use crate::mph::mix;
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+
+/// How to generate a workload
+#[derive(Clone, Debug)]
+pub struct WorkloadConfig {
+    /// Number of queries to generate
+    pub queries: usize,
+    /// Exponent of the Zipf distribution over full keywords ranked by popularity
+    pub zipf_exponent: f64,
+    /// Share of the queries, between 0 and 1, drawn to match no keyword. Keystrokes short of
+    /// a keyword's minimum prefix length miss as well, on top of those.
+    pub miss_share: f64,
+    pub seed: u64,
+}
+
+impl Default for WorkloadConfig {
+    fn default() -> Self {
+        WorkloadConfig {
+            queries: 10_000,
+            zipf_exponent: 1.0,
+            miss_share: 0.2,
+            seed: 42,
+        }
+    }
+}
+
+/// Queries in the order they are issued
+#[derive(Clone, Debug, Default)]
+pub struct Workload {
+    pub queries: Vec<String>,
+    /// How many of the queries were drawn to match no keyword
+    pub misses: usize,
+}
+
+impl Workload {
+    /// Generate a workload over the full keywords of `amps`
+    pub fn generate(amps: &[OriginalAmp], config: &WorkloadConfig) -> Self {
+        let answerable = answerable(amps);
+        let ranked = rank_full_keywords(amps);
+        let zipf = Zipf::new(ranked.len(), config.zipf_exponent);
+        let mut rng = SplitMix64::new(config.seed);
+
+        let mut workload = Workload::default();
+        let mut session: Vec<String> = Vec::new();
+        while workload.queries.len() < config.queries {
+            if ranked.is_empty() || rng.next_f64() < config.miss_share {
+                workload.queries.push(miss(&answerable, &mut rng));
+                workload.misses += 1;
+                continue;
+            }
+            if session.is_empty() {
+                // Keystrokes are popped from the end, so store them longest first
+                let full_keyword = ranked[zipf.sample(&mut rng)];
+                session = keystrokes(full_keyword);
+                session.reverse();
+            }
+            workload.queries.extend(session.pop());
+        }
+        workload
+    }
+
+    pub fn len(&self) -> usize {
+        self.queries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+}
+
+/// Distinct full keywords, most popular first. Popularity is how many keywords a full
+/// keyword stands for across all suggestions, ties broken alphabetically.
+fn rank_full_keywords(amps: &[OriginalAmp]) -> Vec<&str> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for amp in amps {
+        for (full_keyword, count) in amp.full_keywords.iter().filter(|(fk, _)| !fk.is_empty()) {
+            *counts.entry(full_keyword.as_str()).or_default() += count;
+        }
+    }
+    let mut ranked: Vec<(&str, usize)> = counts.into_iter().collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    ranked
+        .into_iter()
+        .map(|(full_keyword, _)| full_keyword)
+        .collect()
+}
+
+/// Every query typed on the way to `s`, one per char
+fn keystrokes(s: &str) -> Vec<String> {
+    s.char_indices()
+        .skip(1)
+        .map(|(i, _)| i)
+        .chain(std::iter::once(s.len()))
+        .map(|end| s[..end].to_string())
+        .collect()
+}
+
+/// Everything a prefix query could match: the keywords, the full keywords and their
+/// word-boundary suffixes
+fn answerable(amps: &[OriginalAmp]) -> BTreeSet<&str> {
+    let mut answerable = BTreeSet::new();
+    for amp in amps {
+        answerable.extend(amp.keywords.iter().map(String::as_str));
+        for (full_keyword, _) in &amp.full_keywords {
+            answerable.insert(full_keyword.as_str());
+            answerable.extend(word_suffixes(full_keyword));
+        }
+    }
+    answerable
+}
+
+/// A random lowercase word that is no prefix of anything `answerable`
+fn miss(answerable: &BTreeSet<&str>, rng: &mut SplitMix64) -> String {
+    loop {
+        let len = 4 + rng.next_below(7);
+        let word: String = (0..len)
+            .map(|_| (b'a' + rng.next_below(26) as u8) as char)
+            .collect();
+        // Whatever starts with the word sorts right after it
+        let extended = answerable
+            .range(word.as_str()..)
+            .next()
+            .is_some_and(|next| next.starts_with(word.as_str()));
+        if !extended {
+            return word;
+        }
+    }
+}
+
+/// Samples ranks from a Zipf distribution by binary search over its cumulative weights
+struct Zipf {
+    cumulative: Vec<f64>,
+}
+
+impl Zipf {
+    fn new(n: usize, exponent: f64) -> Self {
+        let mut total = 0.0;
+        let cumulative = (1..=n)
+            .map(|rank| {
+                total += 1.0 / (rank as f64).powf(exponent);
+                total
+            })
+            .collect();
+        Zipf { cumulative }
+    }
+
+    /// A 0-based rank
+    fn sample(&self, rng: &mut SplitMix64) -> usize {
+        let total = self.cumulative.last().copied().unwrap_or(0.0);
+        let target = rng.next_f64() * total;
+        self.cumulative
+            .partition_point(|&c| c <= target)
+            .min(self.cumulative.len().saturating_sub(1))
+    }
+}
+
+/// SplitMix64, small and good enough for sampling workloads
+struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        mix(self.state)
+    }
+
+    /// Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn next_below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Latency distribution of a run of queries
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LatencySummary {
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    /// Queries per second over the whole run
+    pub throughput: f64,
+}
+
+impl LatencySummary {
+    /// Time `run` on each query, one at a time
+    pub fn measure(queries: &[String], mut run: impl FnMut(&str)) -> Self {
+        let latencies = queries
+            .iter()
+            .map(|query| {
+                let start = Instant::now();
+                run(query);
+                start.elapsed()
+            })
+            .collect();
+        Self::from_latencies(latencies)
+    }
+
+    /// Summarize the latency of each query of a run
+    pub fn from_latencies(mut latencies: Vec<Duration>) -> Self {
+        if latencies.is_empty() {
+            return Self::default();
+        }
+        latencies.sort();
+        let total: Duration = latencies.iter().sum();
+        let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+        LatencySummary {
+            p50: percentile(50),
+            p99: percentile(99),
+            max: latencies[latencies.len() - 1],
+            throughput: latencies.len() as f64 / total.as_secs_f64().max(f64::MIN_POSITIVE),
+        }
+    }
+}
@@ -1,7 +1,10 @@
+import json
+
 import rethink_about_amp
 
 manager = rethink_about_amp.AmpIndexManager()
-manager.build_from_file("us-desktop", "data/amp-us-desktop.json")
+print(f"Available backends: {manager.backends()}")
+manager.build_from_file("us-desktop", "data/amp-us-desktop.json", backend="blart", instrument=True)
 results = manager.query("us-desktop", "am")
 
 print(f"Found {len(results)} results")
@@ -13,3 +16,15 @@ for result in results[:3]:
     print(f"  Full keyword: {result.full_keyword}")
     print(f"  Block ID: {result.block_id}")
     print(f"  IAB Category: {result.iab_category}")
+
+dismissed = manager.query("us-desktop", "am", blocked_block_ids=[results[0].block_id])
+print(f"After dismissing {results[0].block_id}: {[r.block_id for r in dismissed]}")
+
+amazon = manager.lookup_block("us-desktop", results[0].block_id)
+print(f"Keywords for {amazon.advertiser}: {len(manager.keywords_for('us-desktop', amazon.block_id))}")
+print(f"Keys under 'amazon p': {manager.iter_prefix('us-desktop', 'amazon p')}")
+
+print(json.loads(manager.explain("us-desktop", "amazon p"))["full_keyword"])
+
+metrics = manager.metrics("us-desktop")
+print(f"Queries: {metrics['queries']}, hits: {metrics['hits']}")
@@ -1,31 +1,40 @@
-use rethink_about_amp::{AmpIndexer, BTreeAmpIndex, BlartAmpIndex, HybridAmpIndex, load_amp_data};
+use rethink_about_amp::{
+    AmpIndexer, AmpQuery, BTreeAmpIndex, BlartAmpIndex, HybridAmpIndex, LoudsAmpIndex,
+    PrefixTableAmpIndex, QueryContext, QueryPath, SortedArrayAmpIndex, load_amp_data,
+};
 use std::path::Path;
 
-fn prepare_btree_index() -> BTreeAmpIndex {
-    let data_path = Path::new("data/amp-us-desktop.json");
-    let amps = load_amp_data(data_path).expect("Failed to load AMP data");
+const DESKTOP_DATA: &str = "data/amp-us-desktop.json";
+const PHONE_DATA: &str = "data/amp-us-phone.json";
+
+fn prepare_index<T: AmpIndexer>(data_path: &str) -> T {
+    let amps = load_amp_data(Path::new(data_path)).expect("Failed to load AMP data");
 
-    let mut index = BTreeAmpIndex::new();
-    index.build(&amps).expect("Failed to build BTree index");
-    index
+    T::build(&amps).expect("Failed to build index")
 }
 
-fn prepare_hybrid_index() -> HybridAmpIndex {
-    let data_path = Path::new("data/amp-us-desktop.json");
-    let amps = load_amp_data(data_path).expect("Failed to load AMP data");
+fn prepare_btree_index() -> BTreeAmpIndex {
+    prepare_index(DESKTOP_DATA)
+}
 
-    let mut index = HybridAmpIndex::new();
-    index.build(&amps).expect("Failed to build FST index");
-    index
+fn prepare_hybrid_index() -> HybridAmpIndex {
+    prepare_index(DESKTOP_DATA)
 }
 
 fn prepare_blart_index() -> BlartAmpIndex {
-    let data_path = Path::new("data/amp-us-desktop.json");
-    let amps = load_amp_data(data_path).expect("Failed to load AMP data");
+    prepare_index(DESKTOP_DATA)
+}
+
+fn prepare_louds_index() -> LoudsAmpIndex {
+    prepare_index(DESKTOP_DATA)
+}
+
+fn prepare_sorted_array_index() -> SortedArrayAmpIndex {
+    prepare_index(DESKTOP_DATA)
+}
 
-    let mut index = BlartAmpIndex::new();
-    index.build(&amps).expect("Failed to build FST index");
-    index
+fn prepare_prefix_table_index() -> PrefixTableAmpIndex {
+    prepare_index(DESKTOP_DATA)
 }
 
 fn test_amazon_prefix_queries_for<T: AmpIndexer>(index: &T, indexer_name: &str) {
@@ -124,18 +133,27 @@ fn test_stats_for<T: AmpIndexer>(index: &T, indexer_name: &str) {
 }
 
 fn test_scan_all_keywords(idx: &impl AmpIndexer) {
-    let data_path = Path::new("data/amp-us-desktop.json");
+    test_scan_all_keywords_in(idx, DESKTOP_DATA);
+}
+
+fn test_scan_all_keywords_in(idx: &impl AmpIndexer, data_path: &str) {
+    let data_path = Path::new(data_path);
     let amps = load_amp_data(data_path).expect("Failed to load AMP data");
 
     for (n, amp) in amps.iter().enumerate() {
         println!("=============Suggestion #: {n}");
         // dbg!(amp);
-        for kw in &amp.keywords {
+        let full_keywords = amp
+            .full_keywords
+            .iter()
+            .flat_map(|(fk, count)| std::iter::repeat_n(fk, *count));
+        for (kw, full_keyword) in amp.keywords.iter().zip(full_keywords) {
             // dbg!(kw);
             let res = idx.query(kw).expect("query failed");
             assert_eq!(res.len(), 1, "missing key");
-            // TODO: check other fields other than `block_id`.
+            // TODO: check other fields other than `block_id` and `full_keyword`.
             assert_eq!(res[0].block_id, amp.block_id, "incorrect suggestion");
+            assert_eq!(&res[0].full_keyword, full_keyword, "incorrect full keyword");
         }
     }
 }
@@ -205,3 +223,292 @@ fn test_btree_full_scan() {
     let index = prepare_btree_index();
     test_scan_all_keywords(&index);
 }
+
+#[test]
+fn test_hybrid_full_scan() {
+    let index = prepare_hybrid_index();
+    test_scan_all_keywords(&index);
+}
+
+#[test]
+fn test_btree_phone_query_urls() {
+    let index: BTreeAmpIndex = prepare_index(PHONE_DATA);
+    test_query_urls_for(&index, "BTree (phone)");
+}
+
+#[test]
+fn test_hybrid_phone_query_urls() {
+    let index: HybridAmpIndex = prepare_index(PHONE_DATA);
+    test_query_urls_for(&index, "Hybrid (phone)");
+}
+
+#[test]
+fn test_blart_phone_query_urls() {
+    let index: BlartAmpIndex = prepare_index(PHONE_DATA);
+    test_query_urls_for(&index, "Blart (phone)");
+}
+
+#[test]
+fn test_btree_phone_full_scan() {
+    let index: BTreeAmpIndex = prepare_index(PHONE_DATA);
+    test_scan_all_keywords_in(&index, PHONE_DATA);
+}
+
+#[test]
+fn test_hybrid_phone_full_scan() {
+    let index: HybridAmpIndex = prepare_index(PHONE_DATA);
+    test_scan_all_keywords_in(&index, PHONE_DATA);
+}
+
+#[test]
+fn test_blart_phone_full_scan() {
+    let index: BlartAmpIndex = prepare_index(PHONE_DATA);
+    test_scan_all_keywords_in(&index, PHONE_DATA);
+}
+
+#[test]
+fn test_louds_amazon_prefix_queries() {
+    let index = prepare_louds_index();
+    test_amazon_prefix_queries_for(&index, "LOUDS");
+}
+
+#[test]
+fn test_louds_query_urls() {
+    let index = prepare_louds_index();
+    test_query_urls_for(&index, "LOUDS");
+}
+
+#[test]
+fn test_louds_stats() {
+    let index = prepare_louds_index();
+    test_stats_for(&index, "LOUDS");
+}
+
+#[test]
+fn test_louds_full_scan() {
+    let index = prepare_louds_index();
+    test_scan_all_keywords(&index);
+}
+
+#[test]
+fn test_louds_phone_query_urls() {
+    let index: LoudsAmpIndex = prepare_index(PHONE_DATA);
+    test_query_urls_for(&index, "LOUDS (phone)");
+}
+
+#[test]
+fn test_louds_phone_full_scan() {
+    let index: LoudsAmpIndex = prepare_index(PHONE_DATA);
+    test_scan_all_keywords_in(&index, PHONE_DATA);
+}
+
+#[test]
+fn test_louds_matches_blart_keys() {
+    let blart = prepare_blart_index();
+    let louds = prepare_louds_index();
+    for prefix in ["", "a", "amazon", "k cup", "zz"] {
+        let expected: Vec<_> = blart.iter_prefix(prefix).collect();
+        let actual: Vec<_> = louds.iter_prefix(prefix).collect();
+        assert_eq!(actual, expected, "prefix {:?}", prefix);
+    }
+
+    // Keys, values and exclusions together take a fraction of the same keys in a tree
+    let tree_bytes = blart.stats()["keyword_structure_bytes"];
+    let stats = louds.stats();
+    assert!(
+        stats["keyword_structure_bytes"] * 4 < tree_bytes,
+        "{} vs {}",
+        stats["keyword_structure_bytes"],
+        tree_bytes
+    );
+}
+
+#[test]
+fn test_sorted_array_amazon_prefix_queries() {
+    let index = prepare_sorted_array_index();
+    test_amazon_prefix_queries_for(&index, "SortedArray");
+}
+
+#[test]
+fn test_sorted_array_query_urls() {
+    let index = prepare_sorted_array_index();
+    test_query_urls_for(&index, "SortedArray");
+}
+
+#[test]
+fn test_sorted_array_stats() {
+    let index = prepare_sorted_array_index();
+    test_stats_for(&index, "SortedArray");
+}
+
+#[test]
+fn test_sorted_array_full_scan() {
+    let index = prepare_sorted_array_index();
+    test_scan_all_keywords(&index);
+}
+
+#[test]
+fn test_sorted_array_phone_query_urls() {
+    let index: SortedArrayAmpIndex = prepare_index(PHONE_DATA);
+    test_query_urls_for(&index, "SortedArray (phone)");
+}
+
+#[test]
+fn test_sorted_array_phone_full_scan() {
+    let index: SortedArrayAmpIndex = prepare_index(PHONE_DATA);
+    test_scan_all_keywords_in(&index, PHONE_DATA);
+}
+
+#[test]
+fn test_sorted_array_matches_btree() {
+    let btree = prepare_btree_index();
+    let sorted_array = prepare_sorted_array_index();
+    let keys: Vec<_> = btree.iter_prefix("").collect();
+    assert_eq!(sorted_array.iter_prefix("").collect::<Vec<_>>(), keys);
+
+    // Every prefix of every key, plus queries between and past the keys
+    let mut queries: Vec<String> = vec!["".into(), "\u{10ffff}".into(), "zzzz".into()];
+    for (key, _, _) in &keys {
+        let mut prefix = String::new();
+        for c in key.chars() {
+            prefix.push(c);
+            queries.push(prefix.clone());
+        }
+        queries.push(format!("{}!", key));
+    }
+    for query in &queries {
+        let expected = btree.query(query).unwrap();
+        let actual = sorted_array.query(query).unwrap();
+        assert_eq!(
+            actual
+                .iter()
+                .map(|r| (r.block_id, &r.full_keyword))
+                .collect::<Vec<_>>(),
+            expected
+                .iter()
+                .map(|r| (r.block_id, &r.full_keyword))
+                .collect::<Vec<_>>(),
+            "query {:?}",
+            query
+        );
+    }
+
+    // Keys are front-coded in blocks of 16
+    let stats = sorted_array.stats();
+    assert_eq!(stats["key_blocks"], keys.len().div_ceil(16));
+}
+
+#[test]
+fn test_prefix_table_amazon_prefix_queries() {
+    let index = prepare_prefix_table_index();
+    test_amazon_prefix_queries_for(&index, "PrefixTable");
+}
+
+#[test]
+fn test_prefix_table_query_urls() {
+    let index = prepare_prefix_table_index();
+    test_query_urls_for(&index, "PrefixTable");
+}
+
+#[test]
+fn test_prefix_table_stats() {
+    let index = prepare_prefix_table_index();
+    test_stats_for(&index, "PrefixTable");
+}
+
+#[test]
+fn test_prefix_table_full_scan() {
+    let index = prepare_prefix_table_index();
+    test_scan_all_keywords(&index);
+}
+
+#[test]
+fn test_prefix_table_phone_query_urls() {
+    let index: PrefixTableAmpIndex = prepare_index(PHONE_DATA);
+    test_query_urls_for(&index, "PrefixTable (phone)");
+}
+
+#[test]
+fn test_prefix_table_phone_full_scan() {
+    let index: PrefixTableAmpIndex = prepare_index(PHONE_DATA);
+    test_scan_all_keywords_in(&index, PHONE_DATA);
+}
+
+#[test]
+fn test_prefix_table_matches_sorted_array() {
+    let sorted_array = prepare_sorted_array_index();
+    let prefix_table = prepare_prefix_table_index();
+    let keys: Vec<_> = sorted_array.iter_prefix("").collect();
+
+    // Every prefix of every key, answered or not, plus misses
+    let mut queries: Vec<String> = vec!["".into(), "zzzz".into()];
+    for (key, _, _) in &keys {
+        let mut prefix = String::new();
+        for c in key.chars() {
+            prefix.push(c);
+            queries.push(prefix.clone());
+        }
+        queries.push(format!("{}!", key));
+    }
+    let ctx = QueryContext::default();
+    let mut hits = 0;
+    for query in &queries {
+        let (expected, _) = sorted_array.query_with_path(query, &ctx).unwrap();
+        let (actual, path) = prefix_table.query_with_path(query, &ctx).unwrap();
+        assert_eq!(path, QueryPath::AnswerTable, "query {:?}", query);
+        assert_eq!(
+            actual
+                .iter()
+                .map(|r| (r.block_id, &r.full_keyword, &r.url))
+                .collect::<Vec<_>>(),
+            expected
+                .iter()
+                .map(|r| (r.block_id, &r.full_keyword, &r.url))
+                .collect::<Vec<_>>(),
+            "query {:?}",
+            query
+        );
+        hits += actual.len();
+    }
+    assert!(hits > keys.len());
+
+    // A filtered out answer falls back to the scan of the sorted keys
+    let amazon = sorted_array.query("amazon").unwrap()[0].block_id;
+    let ctx = QueryContext::new().block_id(amazon);
+    let (expected, _) = sorted_array.query_with_path("am", &ctx).unwrap();
+    let (actual, path) = prefix_table.query_with_path("am", &ctx).unwrap();
+    assert_eq!(path, QueryPath::Tree);
+    assert_eq!(
+        actual.iter().map(|r| r.block_id).collect::<Vec<_>>(),
+        expected.iter().map(|r| r.block_id).collect::<Vec<_>>()
+    );
+
+    let stats = prefix_table.stats();
+    assert!(stats["answer_table_entries"] > keys.len());
+    assert!(stats["keyword_structure_bytes"] > sorted_array.stats()["keyword_structure_bytes"]);
+}
+
+#[test]
+fn test_prefix_table_skips_excluded_prefixes() {
+    let amps = load_amp_data(Path::new(DESKTOP_DATA)).expect("Failed to load AMP data");
+    // One interval `amazon, 2` excluding "amaz" and "amazo"
+    let mut amp = amps[0].clone();
+    amp.keywords = vec!["am".into(), "ama".into(), "amazon".into()];
+    amp.full_keywords = vec![("amazon".into(), 3)];
+    let index = PrefixTableAmpIndex::build(&[amp]).unwrap();
+
+    let ctx = QueryContext::default();
+    for (query, expected) in [
+        ("a", 0),
+        ("am", 1),
+        ("ama", 1),
+        ("amaz", 0),
+        ("amazo", 0),
+        ("amazon", 1),
+    ] {
+        let (results, path) = index.query_with_path(query, &ctx).unwrap();
+        assert_eq!(path, QueryPath::AnswerTable, "query {:?}", query);
+        assert_eq!(results.len(), expected, "query {:?}", query);
+    }
+    assert_eq!(index.stats()["keyword_exclusions"], 2);
+}
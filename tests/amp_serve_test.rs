@@ -0,0 +1,290 @@
+//! Runs the `amp-serve` binary on localhost and talks to it over HTTP.
+
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const DESKTOP_DATA: &str = "data/amp-us-desktop.json";
+
+struct AmpServe {
+    child: Child,
+    addr: String,
+    // Kept open so that the server can keep logging
+    _stdout: BufReader<ChildStdout>,
+}
+
+impl AmpServe {
+    fn start(data: &str) -> Self {
+        Self::start_with(data, &[])
+    }
+
+    fn start_with(data: &str, extra_args: &[&str]) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_amp-serve"))
+            .args(["--data", data, "--addr", "127.0.0.1:0", "--threads", "2"])
+            .args(extra_args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to start amp-serve");
+
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        stdout.read_line(&mut line).expect("Failed to read output");
+        let addr = line
+            .trim()
+            .strip_prefix("Listening on http://")
+            .unwrap_or_else(|| panic!("Unexpected output: {}", line))
+            .to_string();
+
+        AmpServe {
+            child,
+            addr,
+            _stdout: stdout,
+        }
+    }
+
+    /// Send a GET request, returning the status code and the body
+    fn get_text(&self, path: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(&self.addr).expect("Failed to connect");
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            path, self.addr
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").expect("Malformed response");
+        let status = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .expect("Malformed status line");
+        (status, body.to_string())
+    }
+
+    /// Send a GET request, returning the status code and the JSON body
+    fn get(&self, path: &str) -> (u16, Value) {
+        let (status, body) = self.get_text(path);
+        (
+            status,
+            serde_json::from_str(&body).expect("Body is not JSON"),
+        )
+    }
+
+    fn suggest(&self, q: &str) -> Vec<Value> {
+        let (status, body) = self.get(&format!("/api/v1/suggest?q={}", q));
+        assert_eq!(status, 200, "{}", body);
+        body["suggestions"].as_array().unwrap().clone()
+    }
+}
+
+impl Drop for AmpServe {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A copy of `src` in a fresh temporary file
+fn temp_copy(src: &str, name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("amp-serve-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::copy(src, &path).unwrap();
+    path
+}
+
+#[test]
+fn test_suggest_response_shape() {
+    let server = AmpServe::start(DESKTOP_DATA);
+
+    let suggestions = server.suggest("amazon");
+    assert_eq!(suggestions.len(), 1);
+    let suggestion = suggestions[0].as_object().unwrap();
+    for field in [
+        "title",
+        "url",
+        "click_url",
+        "impression_url",
+        "advertiser",
+        "block_id",
+        "iab_category",
+        "icon",
+        "full_keyword",
+        "is_sponsored",
+        "provider",
+    ] {
+        assert!(suggestion.contains_key(field), "Missing field {}", field);
+    }
+    assert_eq!(suggestion["advertiser"], "Amazon");
+    assert_eq!(suggestion["is_sponsored"], true);
+    assert_eq!(suggestion["provider"], "adm");
+    assert!(suggestion["block_id"].is_i64());
+
+    // The query is URL-decoded
+    assert_eq!(server.suggest("%61mazon"), suggestions);
+    assert!(server.suggest("no+such+keyword").is_empty());
+}
+
+#[test]
+fn test_errors_and_health() {
+    let server = AmpServe::start(DESKTOP_DATA);
+
+    assert_eq!(server.get("/api/v1/suggest").0, 400);
+    assert_eq!(server.get("/api/v1/explain").0, 400);
+    // Malformed percent-encoding, including a sign where a hex digit is expected
+    for path in [
+        "/api/v1/suggest?q=%+1",
+        "/api/v1/suggest?q=am%2",
+        "/api/v1/suggest?q=%zzamazon",
+        "/api/v1/suggest?q=amazon&request_id=%-1",
+        "/api/v1/explain?q=%+1",
+    ] {
+        assert_eq!(server.get(path).0, 400, "{}", path);
+    }
+
+    let (status, explanation) = server.get("/api/v1/explain?q=amazon+p");
+    assert_eq!(status, 200);
+    assert_eq!(explanation["query"], "amazon p");
+    assert_eq!(
+        explanation["full_keyword"]["full_keyword"],
+        "amazon prime login"
+    );
+    assert_eq!(server.get("/nope").0, 404);
+    assert_eq!(server.get("/__heartbeat__").0, 200);
+    assert_eq!(server.get("/__lbheartbeat__").0, 200);
+
+    let (status, stats) = server.get("/stats");
+    assert_eq!(status, 200);
+    assert_eq!(stats["backend"], "blart");
+    assert_eq!(stats["reloads"], 0);
+    assert!(stats["index"]["suggestions_count"].as_u64().unwrap() > 0);
+
+    server.suggest("amazon");
+    let (status, metrics) = server.get_text("/metrics");
+    assert_eq!(status, 200);
+    assert!(
+        metrics.contains("amp_query_results_total{result=\"hit\"} 1\n"),
+        "{}",
+        metrics
+    );
+}
+
+#[test]
+fn test_reload_on_sighup() {
+    let data = temp_copy(DESKTOP_DATA, "reload.json");
+    let server = AmpServe::start(data.to_str().unwrap());
+    assert_eq!(server.suggest("amazon").len(), 1);
+
+    // Drop the Amazon suggestion from the payload
+    let mut amps: Vec<Value> = serde_json::from_slice(&std::fs::read(&data).unwrap()).unwrap();
+    amps.retain(|amp| amp["advertiser"] != "Amazon");
+    std::fs::write(&data, serde_json::to_vec(&amps).unwrap()).unwrap();
+
+    let status = Command::new("kill")
+        .args(["-HUP", &server.child.id().to_string()])
+        .status()
+        .expect("Failed to run kill");
+    assert!(status.success());
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while server.get("/stats").1["reloads"] != 1 {
+        assert!(Instant::now() < deadline, "Index was not reloaded");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert!(server.suggest("amazon").is_empty());
+    let _ = std::fs::remove_file(&data);
+}
+
+#[test]
+fn test_time_windows() {
+    let data = temp_copy(DESKTOP_DATA, "windows.json");
+    let mut amps: Vec<Value> = serde_json::from_slice(&std::fs::read(&data).unwrap()).unwrap();
+    let amazon = amps
+        .iter_mut()
+        .find(|amp| amp["advertiser"] == "Amazon")
+        .unwrap();
+    amazon["start"] = 2000.into();
+    amazon["end"] = 3000.into();
+    let block_id = amazon["id"].clone();
+    std::fs::write(&data, serde_json::to_vec(&amps).unwrap()).unwrap();
+
+    let before = AmpServe::start_with(data.to_str().unwrap(), &["--now", "1000"]);
+    assert!(before.suggest("amazon").is_empty());
+    let (status, schedule) = before.get("/api/v1/schedule");
+    assert_eq!(status, 200);
+    assert_eq!(schedule["now"], 1000);
+    let upcoming = schedule["upcoming"].as_array().unwrap();
+    assert_eq!(upcoming.len(), 2, "{}", schedule);
+    assert_eq!(upcoming[0]["at"], 2000);
+    assert_eq!(upcoming[0]["kind"], "start");
+    assert_eq!(upcoming[0]["block_id"], block_id);
+    let (_, explanation) = before.get("/api/v1/explain?q=amazon");
+    let candidates = explanation["candidates"].as_array().unwrap();
+    assert!(
+        candidates.iter().any(|c| c["verdict"] == "inactive"),
+        "{}",
+        explanation
+    );
+
+    let during = AmpServe::start_with(data.to_str().unwrap(), &["--now", "2000"]);
+    assert_eq!(during.suggest("amazon")[0]["block_id"], block_id);
+    assert_eq!(
+        during.get("/api/v1/schedule").1["upcoming"][0]["kind"],
+        "end"
+    );
+
+    let after = AmpServe::start_with(data.to_str().unwrap(), &["--now", "3000"]);
+    assert!(after.suggest("amazon").is_empty());
+    assert!(
+        after.get("/api/v1/schedule").1["upcoming"]
+            .as_array()
+            .unwrap()
+            .is_empty()
+    );
+    let _ = std::fs::remove_file(&data);
+}
+
+#[test]
+fn test_url_params() {
+    let data = temp_copy(DESKTOP_DATA, "url_params.json");
+    let mut amps: Vec<Value> = serde_json::from_slice(&std::fs::read(&data).unwrap()).unwrap();
+    let amazon = amps
+        .iter_mut()
+        .find(|amp| amp["advertiser"] == "Amazon")
+        .unwrap();
+    let click_url = format!(
+        "{}&rid={{request_id}}",
+        amazon["click_url"].as_str().unwrap()
+    );
+    amazon["click_url"] = click_url.into();
+    amazon["impression_url"] = "https://impression.example.com/?ff={form_factor}".into();
+    std::fs::write(&data, serde_json::to_vec(&amps).unwrap()).unwrap();
+    let server = AmpServe::start(data.to_str().unwrap());
+
+    let (status, body) =
+        server.get("/api/v1/suggest?q=amazon&request_id=a%2Fb+c&form_factor=phone");
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["request_id"], "a/b c");
+    let suggestion = &body["suggestions"][0];
+    assert!(
+        suggestion["click_url"]
+            .as_str()
+            .unwrap()
+            .ends_with("&rid=a%2Fb%20c"),
+        "{}",
+        suggestion
+    );
+    assert_eq!(
+        suggestion["impression_url"],
+        "https://impression.example.com/?ff=phone"
+    );
+
+    let (status, _) = server.get("/api/v1/suggest?q=amazon&form_factor=tablet");
+    assert_eq!(status, 400);
+    let _ = std::fs::remove_file(&data);
+}
@@ -0,0 +1,53 @@
+use rethink_about_amp::analysis::analyze;
+use rethink_about_amp::load_amp_data;
+
+const DESKTOP_DATA: &str = "data/amp-us-desktop.json";
+
+#[test]
+fn test_redundancy_report_covers_every_keyword() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let report = analyze(&amps);
+
+    let keywords: usize = amps.iter().map(|amp| amp.keywords.len()).sum();
+    assert_eq!(report.suggestions, amps.len());
+    assert_eq!(report.chains.keywords, keywords);
+    assert!(report.chains.chains <= keywords);
+    assert!(report.chains.implicit_full_keywords <= report.chains.chains);
+    assert!(report.chains.intervals + report.chains.exclusions < keywords);
+
+    let fields = report
+        .fields
+        .iter()
+        .map(|field| field.field)
+        .collect::<Vec<_>>();
+    for field in ["keywords", "title", "url", "advertiser"] {
+        assert!(fields.contains(&field), "missing field {}", field);
+    }
+}
+
+#[test]
+fn test_redundancy_report_estimates_and_rankings() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let report = analyze(&amps);
+
+    for field in &report.fields {
+        assert!(field.distinct <= field.values, "{}", field.field);
+        assert!(field.best().bytes <= field.raw_bytes, "{}", field.field);
+    }
+    for templates in &report.templates {
+        assert!((0.0..=1.0).contains(&templates.hit_rate()));
+    }
+
+    assert!(!report.top_advertisers.is_empty());
+    assert!(
+        report
+            .top_advertisers
+            .windows(2)
+            .all(|pair| pair[0].keywords >= pair[1].keywords)
+    );
+    assert!(
+        report
+            .to_string()
+            .contains(&report.top_advertisers[0].advertiser)
+    );
+}
@@ -0,0 +1,61 @@
+use rethink_about_amp::bits::{BitVec, PackedInts};
+
+/// Bits with a known pattern, spanning several words
+fn bits(len: usize) -> Vec<bool> {
+    (0..len).map(|i| i % 3 == 0 || i % 7 == 0).collect()
+}
+
+#[test]
+fn test_rank_select() {
+    for len in [0, 1, 63, 64, 65, 200] {
+        let expected = bits(len);
+        let mut bv = BitVec::new();
+        for &bit in &expected {
+            bv.push(bit);
+        }
+        bv.finish();
+        assert_eq!(bv.len(), len);
+
+        let mut ones = Vec::new();
+        let mut zeros = Vec::new();
+        for (i, &bit) in expected.iter().enumerate() {
+            assert_eq!(bv.get(i), bit);
+            assert_eq!(bv.rank1(i), ones.len(), "rank1({}) of {}", i, len);
+            if bit { ones.push(i) } else { zeros.push(i) }
+        }
+        assert_eq!(bv.rank1(len), ones.len());
+        assert_eq!(bv.count_ones(), ones.len());
+
+        for (k, &pos) in ones.iter().enumerate() {
+            assert_eq!(bv.select1(k), Some(pos), "select1({}) of {}", k, len);
+        }
+        for (k, &pos) in zeros.iter().enumerate() {
+            assert_eq!(bv.select0(k), Some(pos), "select0({}) of {}", k, len);
+        }
+        // Padding bits of the last word are not zeros
+        assert_eq!(bv.select1(ones.len()), None);
+        assert_eq!(bv.select0(zeros.len()), None);
+    }
+}
+
+#[test]
+fn test_packed_ints() {
+    let values: Vec<u64> = (0..100).map(|i| (i * 37) % 1000).collect();
+    let packed = PackedInts::from_values(&values);
+    assert_eq!(packed.width(), 10);
+    assert_eq!(packed.len(), values.len());
+    for (i, &value) in values.iter().enumerate() {
+        assert_eq!(packed.get(i), value);
+    }
+    // 100 values of 10 bits
+    assert_eq!(packed.size_bytes(), 16 * 8);
+
+    let zeros = PackedInts::from_values(&[0, 0, 0]);
+    assert_eq!(zeros.width(), 0);
+    assert_eq!(zeros.get(2), 0);
+
+    let wide = PackedInts::from_values(&[u64::MAX, 1, u64::MAX]);
+    assert_eq!(wide.get(0), u64::MAX);
+    assert_eq!(wide.get(1), 1);
+    assert_eq!(wide.get(2), u64::MAX);
+}
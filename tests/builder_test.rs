@@ -0,0 +1,84 @@
+use rethink_about_amp::{
+    AmpIndexBuilder, AmpIndexer, AmpQuery, BTreeAmpIndex, BlartAmpIndex, BuildOptions,
+    HybridAmpIndex, load_amp_data,
+};
+use std::sync::Arc;
+use std::thread;
+
+const DESKTOP_DATA: &str = "data/amp-us-desktop.json";
+
+fn test_shared_across_threads_for<T: AmpIndexer + 'static>(indexer_name: &str) {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let index = Arc::new(T::build(&amps).expect("Failed to build index"));
+
+    // Frozen indexes are `Send + Sync`, so they are queried concurrently without a lock
+    let handles: Vec<_> = amps
+        .chunks(amps.len().div_ceil(4))
+        .map(|chunk| {
+            let index = Arc::clone(&index);
+            let chunk = chunk.to_vec();
+            thread::spawn(move || {
+                for amp in &chunk {
+                    let results = index.query(&amp.keywords[0]).expect("Query failed");
+                    assert_eq!(results.len(), 1);
+                    assert_eq!(results[0].block_id, amp.block_id);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle
+            .join()
+            .unwrap_or_else(|_| panic!("{}: query thread panicked", indexer_name));
+    }
+}
+
+#[test]
+fn test_btree_shared_across_threads() {
+    test_shared_across_threads_for::<BTreeAmpIndex>("BTree");
+}
+
+#[test]
+fn test_hybrid_shared_across_threads() {
+    test_shared_across_threads_for::<HybridAmpIndex>("Hybrid");
+}
+
+#[test]
+fn test_blart_shared_across_threads() {
+    test_shared_across_threads_for::<BlartAmpIndex>("Blart");
+}
+
+#[test]
+fn test_hybrid_short_cache_option() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+
+    for short_cache_max_chars in [0, 3, 6] {
+        let options = BuildOptions {
+            short_cache_max_chars,
+            ..BuildOptions::default()
+        };
+        let mut builder = HybridAmpIndex::builder(&options);
+        builder.add_all(&amps).expect("Failed to add records");
+        let index = builder.finish().expect("Failed to finish index");
+
+        let stats = index.stats();
+        if short_cache_max_chars == 0 {
+            assert_eq!(stats["cache_exact_matches"], 0);
+        } else {
+            assert!(stats["cache_exact_matches"] > 0);
+        }
+
+        for amp in amps.iter().step_by(5) {
+            for kw in &amp.keywords {
+                let results = index.query(kw).expect("Query failed");
+                assert_eq!(
+                    results.first().map(|r| r.block_id),
+                    Some(amp.block_id),
+                    "short_cache_max_chars={}: query '{}'",
+                    short_cache_max_chars,
+                    kw
+                );
+            }
+        }
+    }
+}
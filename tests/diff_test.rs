@@ -0,0 +1,123 @@
+use rethink_about_amp::diff::{AnswerChange, DiffOptions, diff_payloads};
+use rethink_about_amp::{OriginalAmp, load_amp_data};
+
+fn desktop() -> Vec<OriginalAmp> {
+    load_amp_data("data/amp-us-desktop.json").expect("Failed to load AMP data")
+}
+
+#[test]
+fn test_identical_payloads() {
+    let amps = desktop();
+    let diff = diff_payloads(&amps, &amps, &DiffOptions::default()).expect("Diff failed");
+    assert!(diff.is_empty(), "{}", diff);
+    assert!(diff.prefixes_checked > 0);
+}
+
+#[test]
+fn test_payload_changes() {
+    let old = desktop();
+    let mut new = old.clone();
+
+    // Remove the first suggestion (Amazon, block 59)
+    let removed = new.remove(0);
+    assert_eq!(removed.block_id, 59);
+
+    // Retitle another one
+    new[0].title = "Amazon.com - New Title".to_string();
+    let retitled = new[0].block_id;
+
+    // Add a suggestion whose keyword collides with an existing collapsed keyword
+    let mut added = new[1].clone();
+    added.block_id = 999_999;
+    added.advertiser = "Newcomer".to_string();
+    added.keywords = vec!["k cup holder".to_string(), "zzqx".to_string()];
+    added.full_keywords = vec![("k cup holder".to_string(), 1), ("zzqx".to_string(), 1)];
+    new.push(added);
+
+    let options = DiffOptions {
+        prefixes: Some(vec!["zzqx".to_string(), "mini ".to_string()]),
+        ..DiffOptions::default()
+    };
+    let diff = diff_payloads(&old, &new, &options).expect("Diff failed");
+
+    assert_eq!(diff.removed.len(), 1);
+    assert_eq!(diff.removed[0].block_id, 59);
+    assert_eq!(diff.added.len(), 1);
+    assert_eq!(diff.added[0].advertiser, "Newcomer");
+
+    assert_eq!(diff.field_changes.len(), 1);
+    assert_eq!(diff.field_changes[0].block_id, retitled);
+    assert_eq!(diff.field_changes[0].field, "title");
+
+    let newcomer = &diff.keywords["Newcomer"];
+    assert_eq!(newcomer.gained, vec!["k cup holder", "zzqx"]);
+    assert!(newcomer.lost.is_empty());
+
+    assert!(
+        diff.new_collisions
+            .iter()
+            .any(|c| c.keyword == "k cup holder" && c.block_ids.contains(&999_999)),
+        "{:?}",
+        diff.new_collisions
+    );
+
+    assert_eq!(diff.prefixes_checked, 2);
+    assert_eq!(
+        diff.answer_changes,
+        vec![AnswerChange {
+            prefix: "zzqx".to_string(),
+            old: None,
+            new: Some(999_999),
+        }]
+    );
+
+    // Both output formats mention the changes
+    let text = diff.to_string();
+    assert!(text.contains("+ [999999] Newcomer"), "{}", text);
+    let json: serde_json::Value = serde_json::from_str(&diff.to_json().unwrap()).unwrap();
+    assert_eq!(json["removed"][0]["block_id"], 59);
+    assert_eq!(json["answer_changes"][0]["prefix"], "zzqx");
+}
+
+#[test]
+fn test_sampled_prefixes_include_changed_keywords() {
+    let old = desktop();
+    let mut new = old.clone();
+    new[0].keywords.push("zzqx".to_string());
+    new[0].full_keywords.push(("zzqx".to_string(), 1));
+
+    let options = DiffOptions {
+        sample_size: 10,
+        ..DiffOptions::default()
+    };
+    let diff = diff_payloads(&old, &new, &options).expect("Diff failed");
+    assert!(diff.prefixes_checked >= 10);
+    assert!(diff.answer_changes.iter().any(|c| c.prefix == "zzqx"));
+}
+
+#[test]
+fn test_collisions_use_the_index_keys() {
+    let old = desktop();
+    let mut new = old.clone();
+
+    // "zzqx" and "zzqxy" have their own full keywords, so they stay two keys and the
+    // newcomer's "zzqx" collides with the first
+    let mut first = new[0].clone();
+    first.block_id = 999_998;
+    first.keywords = vec!["zzqx".to_string(), "zzqxy".to_string()];
+    first.full_keywords = vec![("zzqx".to_string(), 1), ("zzqxy".to_string(), 1)];
+    let mut second = new[1].clone();
+    second.block_id = 999_999;
+    second.keywords = vec!["zzqx".to_string()];
+    second.full_keywords = vec![("zzqx".to_string(), 1)];
+    new.extend([first, second]);
+
+    let diff = diff_payloads(&old, &new, &DiffOptions::default()).expect("Diff failed");
+    let collision = diff
+        .new_collisions
+        .iter()
+        .find(|c| c.keyword == "zzqx")
+        .unwrap_or_else(|| panic!("{:?}", diff.new_collisions));
+    assert_eq!(collision.block_ids, vec![999_998, 999_999]);
+    assert!(!diff.new_collisions.iter().any(|c| c.keyword == "zzqxy"));
+}
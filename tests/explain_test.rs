@@ -0,0 +1,168 @@
+use rethink_about_amp::explain::{FullKeywordSource, Verdict};
+use rethink_about_amp::{
+    AmpIndexer, AmpQuery, BTreeAmpIndex, BackendRegistry, BlartAmpIndex, BuildOptions,
+    HybridAmpIndex, QueryPath, load_amp_data,
+};
+
+const DESKTOP_DATA: &str = "data/amp-us-desktop.json";
+
+fn test_explain_matches_query_for<T: AmpIndexer>(indexer_name: &str) {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let index = T::build(&amps).expect("Failed to build index");
+
+    let mut queries = vec![
+        "am".to_string(),
+        "zzzz".to_string(),
+        "ma".to_string(),
+        "am\0".to_string(),
+    ];
+    for amp in amps.iter().step_by(17) {
+        queries.extend(amp.keywords.iter().step_by(3).cloned());
+    }
+
+    for query in &queries {
+        let results = index.query(query).expect("Query failed");
+        let explanation = index.explain(query).expect("Explain failed");
+        assert_eq!(explanation.query_len, query.chars().count());
+        for candidate in &explanation.candidates {
+            assert!(candidate.keyword.starts_with(query.as_str()));
+        }
+
+        match (results.first(), explanation.winner()) {
+            (Some(result), Some(winner)) => {
+                assert_eq!(
+                    winner.block_id, result.block_id,
+                    "{}: {}",
+                    indexer_name, query
+                );
+                let full_keyword = explanation.full_keyword.as_ref().unwrap();
+                assert_eq!(full_keyword.full_keyword, result.full_keyword);
+                // Keys rejected for min_prefix_len really are too long for the query
+                for rejected in explanation.too_short() {
+                    assert!(rejected.min_prefix_len > explanation.query_len);
+                }
+            }
+            (None, None) => {
+                assert!(explanation.full_keyword.is_none());
+                assert!(
+                    explanation
+                        .candidates
+                        .iter()
+                        .all(|c| matches!(c.verdict, Verdict::TooShort | Verdict::Excluded))
+                );
+            }
+            (result, winner) => panic!(
+                "{}: {:?} answered {:?} but explained {:?}",
+                indexer_name,
+                query,
+                result.map(|r| r.block_id),
+                winner
+            ),
+        }
+    }
+}
+
+#[test]
+fn test_btree_explain() {
+    test_explain_matches_query_for::<BTreeAmpIndex>("BTree");
+}
+
+#[test]
+fn test_blart_explain() {
+    test_explain_matches_query_for::<BlartAmpIndex>("Blart");
+}
+
+#[test]
+fn test_hybrid_explain() {
+    test_explain_matches_query_for::<HybridAmpIndex>("Hybrid");
+}
+
+#[test]
+fn test_explain_rejections() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let index = BTreeAmpIndex::build(&amps).expect("Failed to build index");
+
+    // "amalie arena tickets" sorts before the winner but needs more chars
+    let explanation = index.explain("am").unwrap();
+    assert_eq!(explanation.path, QueryPath::Tree);
+    let rejected = &explanation.candidates[0];
+    assert_eq!(rejected.keyword, "amalie arena tickets");
+    assert_eq!(rejected.verdict, Verdict::TooShort);
+    let winner = explanation.winner().unwrap();
+    assert_eq!(winner.keyword, "amazin");
+    // The scan stops at the winner
+    assert_eq!(explanation.candidates.last(), Some(winner));
+    let full_keyword = explanation.full_keyword.as_ref().unwrap();
+    assert_eq!(full_keyword.full_keyword, "amazon");
+    assert_eq!(full_keyword.source, FullKeywordSource::Stored);
+
+    let text = explanation.to_string();
+    assert!(text.contains("rejected, needs 14 chars"), "{}", text);
+    let json = explanation.to_json().unwrap();
+    assert!(json.contains("\"verdict\": \"too_short\""), "{}", json);
+
+    let miss = index.explain("zzzz").unwrap();
+    assert!(miss.candidates.is_empty());
+    assert!(miss.winner().is_none());
+    assert!(miss.to_string().contains("No suggestion"));
+}
+
+#[test]
+fn test_explain_excluded_prefix() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let mut amp = amps[0].clone();
+    amp.keywords = ["am", "ama", "amazon"].map(String::from).to_vec();
+    amp.full_keywords = vec![("amazon".to_string(), 3)];
+    let amps = [amp];
+
+    // One interval, "amazon" from 2 chars, that skips "amaz" and "amazo"
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        let index = registry
+            .build(name, &amps, &BuildOptions::default())
+            .unwrap();
+        assert_eq!(index.query("ama").unwrap().len(), 1, "{}", name);
+        assert!(index.query("amaz").unwrap().is_empty(), "{}", name);
+        let explanation = index.explain("amaz").unwrap();
+        assert!(explanation.winner().is_none(), "{}", name);
+        assert_eq!(explanation.candidates[0].keyword, "amazon", "{}", name);
+        assert_eq!(
+            explanation.candidates[0].verdict,
+            Verdict::Excluded,
+            "{}",
+            name
+        );
+        let text = explanation.to_string();
+        assert!(
+            text.contains("excluded from the key's interval"),
+            "{}: {}",
+            name,
+            text
+        );
+        assert_eq!(
+            index.keywords_for(amps[0].block_id),
+            ["am", "ama", "amazon"]
+        );
+    }
+}
+
+#[test]
+fn test_hybrid_explain_paths() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let index = HybridAmpIndex::build(&amps).expect("Failed to build index");
+
+    let explanation = index.explain("ma").unwrap();
+    assert_eq!(explanation.path, QueryPath::ShortCache);
+    assert_eq!(explanation.winner().unwrap().path, QueryPath::ShortCache);
+    let full_keyword = explanation.full_keyword.as_ref().unwrap();
+    let expected = if full_keyword.full_keyword == explanation.winner().unwrap().keyword {
+        FullKeywordSource::SameAsKeyword
+    } else {
+        FullKeywordSource::Stored
+    };
+    assert_eq!(full_keyword.source, expected);
+
+    let explanation = index.explain("amazon p").unwrap();
+    assert_eq!(explanation.path, QueryPath::Tree);
+    assert_eq!(explanation.winner().unwrap().keyword, "amazon prime login");
+}
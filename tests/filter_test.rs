@@ -0,0 +1,172 @@
+use rethink_about_amp::explain::Verdict;
+use rethink_about_amp::{AmpQuery, BackendRegistry, BuildOptions, OriginalAmp, QueryContext};
+
+const DESKTOP_DATA: &str = "data/amp-us-desktop.json";
+
+fn amp(block_id: i32, advertiser: &str, keywords: &[&str]) -> OriginalAmp {
+    OriginalAmp {
+        keywords: keywords.iter().map(|k| k.to_string()).collect(),
+        title: format!("Title {}", block_id),
+        url: format!("https://example.com/{}", block_id),
+        score: Some(0.3),
+        full_keywords: vec![(keywords.last().unwrap().to_string(), keywords.len())],
+        advertiser: advertiser.to_string(),
+        block_id,
+        iab_category: "22 - Shopping".to_string(),
+        click_url: format!("https://click.example.com/ctp?id={}", block_id),
+        impression_url: format!("https://impression.example.com/?id={}", block_id),
+        icon_id: "icon".to_string(),
+        start: None,
+        end: None,
+    }
+}
+
+/// Three suggestions all answering "fo", through the collapsed keys "foo", "food" and
+/// "foods", so that key order and key length agree on the preferred one
+fn overlapping_amps() -> Vec<OriginalAmp> {
+    vec![
+        amp(1, "Acme", &["fo", "foo"]),
+        amp(2, "Globex", &["fo", "foo", "food"]),
+        amp(3, "Acme", &["fo", "foo", "food", "foods"]),
+    ]
+}
+
+fn answer(index: &impl AmpQuery, query: &str, filter: &QueryContext) -> Option<i32> {
+    let results = index.query_with_context(query, filter).unwrap();
+    assert!(results.len() <= 1);
+    results.first().map(|r| r.block_id)
+}
+
+#[test]
+fn test_filter_skips_to_next_candidate() {
+    let amps = overlapping_amps();
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        for short_cache_max_chars in [0, 3] {
+            let options = BuildOptions {
+                short_cache_max_chars,
+                ..BuildOptions::default()
+            };
+            let index = registry.build(name, &amps, &options).unwrap();
+            let context = format!("{} (cache {})", name, short_cache_max_chars);
+
+            assert_eq!(
+                answer(&index, "fo", &QueryContext::new()),
+                Some(1),
+                "{}",
+                context
+            );
+            let cases = [
+                (QueryContext::new().block_id(1), Some(2)),
+                (QueryContext::new().block_id(1).block_id(2), Some(3)),
+                (QueryContext::new().block_advertiser("Acme"), Some(2)),
+                (
+                    QueryContext::new().block_id(2).block_advertiser("Acme"),
+                    None,
+                ),
+                // Unknown ids and advertisers don't matter
+                (
+                    QueryContext::new().block_id(42).block_advertiser("acme"),
+                    Some(1),
+                ),
+            ];
+            for (filter, expected) in &cases {
+                assert_eq!(
+                    answer(&index, "fo", filter),
+                    *expected,
+                    "{}: {:?}",
+                    context,
+                    filter
+                );
+            }
+
+            // Only the suggestion owning the keyword is skipped
+            let filter = QueryContext::new().block_id(2);
+            assert_eq!(answer(&index, "food", &filter), Some(3), "{}", context);
+            assert_eq!(answer(&index, "foods", &filter), Some(3), "{}", context);
+            assert_eq!(
+                answer(&index, "foods", &filter.block_id(3)),
+                None,
+                "{}",
+                context
+            );
+        }
+    }
+}
+
+#[test]
+fn test_explain_reports_blocked_keys() {
+    let amps = overlapping_amps();
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        let index = registry
+            .build(name, &amps, &BuildOptions::default())
+            .unwrap();
+        let filter = QueryContext::new().block_id(1);
+        let explanation = index.explain_with_context("fo", &filter).unwrap();
+
+        let winner = explanation.winner().expect("No winner");
+        assert_eq!(winner.block_id, 2, "{}", name);
+        assert_eq!(winner.keyword, "food", "{}", name);
+        let blocked: Vec<_> = explanation
+            .candidates
+            .iter()
+            .filter(|c| c.verdict == Verdict::Blocked)
+            .map(|c| c.keyword.as_str())
+            .collect();
+        assert_eq!(blocked, vec!["foo"], "{}", name);
+        assert!(explanation.to_string().contains("blocked by the filter"));
+    }
+}
+
+#[test]
+fn test_filtered_queries_never_return_blocked_suggestions() {
+    let amps = rethink_about_amp::load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let registry = BackendRegistry::with_defaults();
+    let queries = [
+        "a", "am", "amaz", "amazon", "b", "be", "wal", "target", "ho",
+    ];
+    for name in registry.names() {
+        let index = registry
+            .build(name, &amps, &BuildOptions::default())
+            .unwrap();
+        for query in queries {
+            // Block every answer in turn until nothing is left
+            let mut filter = QueryContext::new();
+            while let Some(result) = index.query_with_context(query, &filter).unwrap().pop() {
+                assert!(
+                    !filter.blocked_block_ids.contains(&result.block_id),
+                    "{}: {:?} returned blocked block {}",
+                    name,
+                    query,
+                    result.block_id
+                );
+                filter = filter.block_id(result.block_id);
+            }
+        }
+
+        // An empty filter changes nothing
+        for query in queries {
+            let plain = index.query(query).unwrap();
+            let filtered = index
+                .query_with_context(query, &QueryContext::new())
+                .unwrap();
+            assert_eq!(
+                plain.first().map(|r| r.block_id),
+                filtered.first().map(|r| r.block_id)
+            );
+        }
+
+        // Blocking an advertiser hides all of its suggestions
+        let filter = QueryContext::new().block_advertiser("Amazon");
+        for query in ["am", "amaz", "amazon"] {
+            let results = index.query_with_context(query, &filter).unwrap();
+            assert!(
+                results.iter().all(|r| r.advertiser != "Amazon"),
+                "{}: {:?}",
+                name,
+                query
+            );
+        }
+    }
+}
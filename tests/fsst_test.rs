@@ -0,0 +1,58 @@
+use rethink_about_amp::fsst::SymbolTable;
+use rethink_about_amp::load_amp_data;
+
+const DESKTOP_DATA: &str = "data/amp-us-desktop.json";
+
+fn round_trip(table: &SymbolTable, text: &str) -> usize {
+    let mut compressed = Vec::new();
+    table.compress(text, &mut compressed);
+    assert_eq!(table.decompress(&compressed), text);
+    compressed.len()
+}
+
+#[test]
+fn test_round_trips_unseen_strings() {
+    let table = SymbolTable::train(&["https://example.com/?tag=a", "https://example.org/b"]);
+    assert!(!table.is_empty());
+    assert!(table.len() <= 255);
+
+    for text in [
+        "",
+        "https://example.com/?tag=a",
+        "https://example.net/?tag=zzz",
+        "café crème ☕",
+        "\u{7f}\u{ff}\u{10ffff}",
+    ] {
+        round_trip(&table, text);
+    }
+
+    // Bytes no symbol covers are escaped
+    assert_eq!(round_trip(&table, "QQ"), 4);
+}
+
+#[test]
+fn test_untrained_table_escapes_everything() {
+    let table = SymbolTable::train::<&str>(&[]);
+    assert!(table.is_empty());
+    assert_eq!(round_trip(&table, "amazon"), 12);
+}
+
+#[test]
+fn test_compresses_suggestion_strings() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let urls: Vec<&str> = amps
+        .iter()
+        .flat_map(|amp| [&amp.url, &amp.click_url, &amp.impression_url])
+        .map(String::as_str)
+        .collect();
+    let table = SymbolTable::train(&urls);
+
+    let plain: usize = urls.iter().map(|url| url.len()).sum();
+    let compressed: usize = urls.iter().map(|url| round_trip(&table, url)).sum();
+    assert!(
+        compressed * 2 < plain,
+        "{} bytes compressed to {}",
+        plain,
+        compressed
+    );
+}
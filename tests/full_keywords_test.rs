@@ -0,0 +1,78 @@
+use rethink_about_amp::common::FullKeyword;
+use rethink_about_amp::full_keywords::{FullKeywordRef, FullKeywordsBuilder};
+use rethink_about_amp::{BackendRegistry, BuildOptions, StringPool, load_amp_data};
+
+const DESKTOP_DATA: &str = "data/amp-us-desktop.json";
+
+#[test]
+fn test_full_keywords_are_interned_once() {
+    let mut pool = StringPool::new();
+    let mut builder = FullKeywordsBuilder::new();
+    let amazon = FullKeyword::Different("amazon".to_string());
+
+    let same = builder.push(&FullKeyword::Same, &mut pool);
+    let first = builder.push(&amazon, &mut pool);
+    let second = builder.push(&amazon, &mut pool);
+    let other = builder.push(&FullKeyword::Different("ebay".to_string()), &mut pool);
+    assert_eq!(same, FullKeywordRef::SAME);
+    assert_eq!(first, second);
+    assert_ne!(first, other);
+
+    let full_keywords = builder.finish();
+    assert_eq!(full_keywords.len(), 2);
+    assert_eq!(full_keywords.resolve(same, "amazon fresh"), "amazon fresh");
+    assert_eq!(full_keywords.resolve(first, "amazin"), "amazon");
+    assert_eq!(
+        FullKeywordRef::from_bits(other.to_bits()),
+        other,
+        "references round trip through a packed column"
+    );
+}
+
+#[test]
+fn test_full_keyword_stats_across_backends() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let registry = BackendRegistry::with_defaults();
+
+    for name in registry.names() {
+        let index = registry
+            .build(name, &amps, &BuildOptions::default())
+            .unwrap();
+        let stats = index.stats();
+        let implicit = stats["full_keywords_implicit"];
+        let referenced = stats["full_keywords_referenced"];
+        assert!(implicit > referenced, "{}: {:?}", name, stats);
+        assert!(stats["full_keywords_count"] <= referenced, "{}", name);
+        assert_eq!(
+            implicit + referenced,
+            index.iter_prefix("").count(),
+            "{}: every collapsed key has one full keyword",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_full_keywords_of_replaced_keys_are_not_stored() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let mut first = amps[0].clone();
+    first.keywords = vec!["foo".to_string()];
+    first.full_keywords = vec![("foo bar".to_string(), 1)];
+    let mut second = amps[1].clone();
+    second.keywords = vec!["foo".to_string()];
+    second.full_keywords = vec![("foo baz".to_string(), 1)];
+
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        let index = registry
+            .build(
+                name,
+                &[first.clone(), second.clone()],
+                &BuildOptions::default(),
+            )
+            .unwrap();
+        let stats = index.stats();
+        assert_eq!(stats["full_keywords_count"], 1, "{}: {:?}", name, stats);
+        assert_eq!(stats["full_keywords_referenced"], 1, "{}", name);
+    }
+}
@@ -0,0 +1,211 @@
+use rethink_about_amp::explain::Verdict;
+use rethink_about_amp::infix::word_suffixes;
+use rethink_about_amp::{
+    AmpQuery, BackendRegistry, BuildOptions, IndexConfig, OriginalAmp, QueryContext, QueryPath,
+    load_amp_data,
+};
+
+const DESKTOP_DATA: &str = "data/amp-us-desktop.json";
+
+fn amp(block_id: i32, advertiser: &str, keywords: &[&str]) -> OriginalAmp {
+    OriginalAmp {
+        keywords: keywords.iter().map(|k| k.to_string()).collect(),
+        title: format!("Title {}", block_id),
+        url: format!("https://example.com/{}", block_id),
+        score: Some(0.3),
+        full_keywords: vec![(keywords.last().unwrap().to_string(), keywords.len())],
+        advertiser: advertiser.to_string(),
+        block_id,
+        iab_category: "22 - Shopping".to_string(),
+        click_url: format!("https://click.example.com/ctp?id={}", block_id),
+        impression_url: format!("https://impression.example.com/?id={}", block_id),
+        icon_id: "icon".to_string(),
+        start: None,
+        end: None,
+    }
+}
+
+fn amps() -> Vec<OriginalAmp> {
+    vec![
+        amp(1, "Amazon", &["amazon prime video"]),
+        amp(2, "Amazon", &["amazon fresh"]),
+        amp(3, "Freshly", &["fres", "fresh", "freshly"]),
+        amp(4, "Fandango", &["fandango prime video deals"]),
+    ]
+}
+
+fn infix_options(min_chars: usize) -> BuildOptions {
+    BuildOptions {
+        infix_min_chars: Some(min_chars),
+        ..BuildOptions::default()
+    }
+}
+
+#[test]
+fn test_word_suffixes() {
+    let suffixes: Vec<_> = word_suffixes("amazon prime video").collect();
+    assert_eq!(suffixes, vec!["prime video", "video"]);
+    assert_eq!(word_suffixes("amazon").count(), 0);
+    // Runs of spaces and trailing spaces start no suffix
+    let suffixes: Vec<_> = word_suffixes("a  b ").collect();
+    assert_eq!(suffixes, vec!["b "]);
+}
+
+#[test]
+fn test_infix_matches_inner_words() {
+    let amps = amps();
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        let index = registry.build(name, &amps, &infix_options(3)).unwrap();
+        let ctx = QueryContext::default();
+
+        let (results, path) = index.query_with_path("prime vid", &ctx).unwrap();
+        assert_eq!(path, QueryPath::Infix, "{}", name);
+        assert_eq!(results[0].block_id, 1, "{}", name);
+        assert_eq!(results[0].full_keyword, "amazon prime video", "{}", name);
+        assert_eq!(index.query("video").unwrap()[0].block_id, 1, "{}", name);
+
+        // A prefix hit ranks above any infix match
+        let (results, path) = index.query_with_path("fresh", &ctx).unwrap();
+        assert_ne!(path, QueryPath::Infix, "{}", name);
+        assert_eq!(results[0].block_id, 3, "{}", name);
+
+        // Unless the prefix hit is filtered out
+        let ctx = QueryContext::new().block_id(3);
+        let (results, path) = index.query_with_path("fresh", &ctx).unwrap();
+        assert_eq!(path, QueryPath::Infix, "{}", name);
+        assert_eq!(results[0].block_id, 2, "{}", name);
+        assert_eq!(results[0].full_keyword, "amazon fresh", "{}", name);
+
+        let ctx = QueryContext::new().block_id(1);
+        let results = index.query_with_context("prime video", &ctx).unwrap();
+        assert_eq!(results[0].block_id, 4, "{}", name);
+        let ctx = ctx.block_advertiser("Fandango");
+        assert!(
+            index
+                .query_with_context("prime video", &ctx)
+                .unwrap()
+                .is_empty()
+        );
+
+        // Queries match at word boundaries only
+        assert!(index.query("rime video").unwrap().is_empty(), "{}", name);
+
+        assert!(index.stats()["infix_entries"] > 0, "{}", name);
+    }
+}
+
+#[test]
+fn test_infix_covers_keywords_past_the_runs() {
+    // "hulu live tv" is past the end of the runs, so it is its own full keyword
+    let mut hulu = amp(5, "Hulu", &["hulu", "hulu live tv"]);
+    hulu.full_keywords = vec![("hulu".to_string(), 1)];
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        let index = registry
+            .build(name, std::slice::from_ref(&hulu), &infix_options(3))
+            .unwrap();
+        let (results, path) = index
+            .query_with_path("live tv", &QueryContext::default())
+            .unwrap();
+        assert_eq!(path, QueryPath::Infix, "{}", name);
+        assert_eq!(results[0].block_id, 5, "{}", name);
+        assert_eq!(results[0].full_keyword, "hulu live tv", "{}", name);
+    }
+}
+
+#[test]
+fn test_infix_min_chars() {
+    let amps = amps();
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        let index = registry.build(name, &amps, &infix_options(4)).unwrap();
+        assert!(index.query("vid").unwrap().is_empty(), "{}", name);
+        assert_eq!(index.query("vide").unwrap()[0].block_id, 1, "{}", name);
+
+        // Disabled by default
+        let index = registry
+            .build(name, &amps, &BuildOptions::default())
+            .unwrap();
+        assert!(index.query("prime video").unwrap().is_empty(), "{}", name);
+        assert_eq!(index.stats()["infix_entries"], 0, "{}", name);
+    }
+}
+
+#[test]
+fn test_explain_infix() {
+    let amps = amps();
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        let index = registry.build(name, &amps, &infix_options(3)).unwrap();
+        let ctx = QueryContext::new().block_id(1);
+        let explanation = index.explain_with_context("prime", &ctx).unwrap();
+
+        assert_eq!(explanation.path, QueryPath::Infix, "{}", name);
+        let scanned: Vec<_> = explanation
+            .candidates
+            .iter()
+            .map(|c| (c.keyword.as_str(), c.block_id, c.path, c.verdict))
+            .collect();
+        assert_eq!(
+            scanned,
+            vec![
+                ("prime video", 1, QueryPath::Infix, Verdict::Blocked),
+                ("prime video deals", 4, QueryPath::Infix, Verdict::Selected),
+            ],
+            "{}",
+            name
+        );
+        let full_keyword = explanation.full_keyword.unwrap();
+        assert_eq!(full_keyword.full_keyword, "fandango prime video deals");
+    }
+}
+
+#[test]
+fn test_infix_config() {
+    let config: IndexConfig =
+        serde_json::from_str(r#"{"backend": "btree", "infix_min_chars": 3}"#).unwrap();
+    assert_eq!(config.options.infix_min_chars, Some(3));
+}
+
+#[test]
+fn test_infix_on_real_data() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        let plain = registry
+            .build(name, &amps, &BuildOptions::default())
+            .unwrap();
+        let index = registry.build(name, &amps, &infix_options(4)).unwrap();
+
+        // Prefix answers are unchanged, misses may be answered by an inner word
+        let answer = |index: &dyn AmpQuery, query: &str| {
+            let results = index.query(query).unwrap();
+            results
+                .first()
+                .map(|r| (r.block_id, r.full_keyword.clone()))
+        };
+        for query in ["am", "amazon", "nike", "wal"] {
+            let expected = answer(&plain, query);
+            let actual = answer(&index, query);
+            match expected {
+                Some(_) => assert_eq!(expected, actual, "{} {}", name, query),
+                None => assert!(
+                    actual.is_none_or(|(_, fk)| fk.contains(&format!(" {}", query))),
+                    "{} {}",
+                    name,
+                    query
+                ),
+            }
+        }
+
+        let results = index.query("prime video").unwrap();
+        assert!(
+            results
+                .iter()
+                .all(|r| r.full_keyword.contains("prime video")),
+            "{}",
+            name
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use rethink_about_amp::common::{FullKeyword, collapse_intervals, expand_keyword};
+use rethink_about_amp::{
+    AmpIndexer, AmpQuery, BTreeAmpIndex, BackendRegistry, BlartAmpIndex, BuildOptions,
+    HybridAmpIndex, load_amp_data,
+};
+
+const DESKTOP_DATA: &str = "data/amp-us-desktop.json";
+
+#[test]
+fn test_expand_keyword() {
+    assert_eq!(expand_keyword("foobar", 4), vec!["foob", "fooba", "foobar"]);
+    assert_eq!(expand_keyword("café", 3), vec!["caf", "café"]);
+    assert_eq!(expand_keyword("a", 1), vec!["a"]);
+}
+
+#[test]
+fn test_collapse_intervals_with_gaps_and_out_of_order() {
+    let keywords: Vec<String> = ["amazon", "am", "ama", "bo", "boo", "b"]
+        .iter()
+        .map(|kw| kw.to_string())
+        .collect();
+    let full_keywords = vec![("amazon".to_string(), 3), ("boots".to_string(), 3)];
+    let intervals = collapse_intervals(&keywords, &full_keywords);
+    let summary: Vec<_> = intervals
+        .intervals
+        .iter()
+        .map(|i| {
+            (
+                i.key.as_str(),
+                i.min_len,
+                i.excluded.clone(),
+                &i.full_keyword,
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("amazon", 2, vec![4, 5], &FullKeyword::Same),
+            (
+                "boo",
+                1,
+                vec![],
+                &FullKeyword::Different("boots".to_string())
+            ),
+        ]
+    );
+    assert!(intervals.contains("ama") && intervals.contains("b"));
+    assert!(!intervals.contains("amaz") && !intervals.contains("a"));
+    assert_eq!(intervals.entries(), 4);
+}
+
+#[test]
+fn test_collapse_intervals_per_full_keyword() {
+    // "am" stands for another full keyword than "amazon", and "bo" has none
+    let keywords: Vec<String> = ["am", "ama", "amazon", "bo"]
+        .iter()
+        .map(|kw| kw.to_string())
+        .collect();
+    let full_keywords = vec![("american".to_string(), 1), ("amazon".to_string(), 2)];
+    let intervals = collapse_intervals(&keywords, &full_keywords);
+    let summary: Vec<_> = intervals
+        .intervals
+        .iter()
+        .map(|i| {
+            (
+                i.key.as_str(),
+                i.min_len,
+                i.excluded.clone(),
+                &i.full_keyword,
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (
+                "am",
+                2,
+                vec![],
+                &FullKeyword::Different("american".to_string())
+            ),
+            ("amazon", 3, vec![4, 5], &FullKeyword::Same),
+            ("bo", 2, vec![], &FullKeyword::Same),
+        ]
+    );
+}
+
+fn test_keywords_for_for<T: AmpIndexer>(indexer_name: &str) {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let index = T::build(&amps).expect("Failed to build index");
+
+    for amp in amps.iter().step_by(11) {
+        let mut expected = amp.keywords.clone();
+        expected.sort();
+        expected.dedup();
+        assert_eq!(
+            index.keywords_for(amp.block_id),
+            expected,
+            "{}: block {}",
+            indexer_name,
+            amp.block_id
+        );
+    }
+    assert!(index.keywords_for(-1).is_empty());
+}
+
+#[test]
+fn test_btree_keywords_for() {
+    test_keywords_for_for::<BTreeAmpIndex>("BTree");
+}
+
+#[test]
+fn test_blart_keywords_for() {
+    test_keywords_for_for::<BlartAmpIndex>("Blart");
+}
+
+#[test]
+fn test_hybrid_keywords_for() {
+    test_keywords_for_for::<HybridAmpIndex>("Hybrid");
+}
+
+#[test]
+fn test_iter_prefix_agrees_across_backends() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let registry = BackendRegistry::with_defaults();
+    let indexes: Vec<_> = registry
+        .names()
+        .into_iter()
+        .map(|name| {
+            let index = registry
+                .build(name, &amps, &BuildOptions::default())
+                .unwrap();
+            (name, index)
+        })
+        .collect();
+
+    let amazon = amps.iter().find(|a| a.advertiser == "Amazon").unwrap();
+    for prefix in ["amazon", "am", "a", "zzzz", ""] {
+        let (reference, expected) = &indexes[0];
+        let expected: Vec<_> = expected.iter_prefix(prefix).collect();
+        for (name, index) in &indexes {
+            let entries: Vec<_> = index.iter_prefix(prefix).collect();
+            assert!(entries.windows(2).all(|w| w[0].0 < w[1].0), "Not sorted");
+            assert!(entries.iter().all(|(kw, _, _)| kw.starts_with(prefix)));
+            assert_eq!(entries, expected, "{} vs {}: {:?}", name, reference, prefix);
+        }
+    }
+
+    // "amazo" is the first partial for the full keyword "amazon fresh"
+    let entry = ("amazon fresh".to_string(), 5, amazon.block_id);
+    let entries: Vec<_> = indexes[0].1.iter_prefix("amazon").collect();
+    assert!(entries.contains(&entry), "{:?}", entries);
+    assert_eq!(indexes[0].1.iter_prefix("zzzz").count(), 0);
+}
+
+#[test]
+fn test_lookup_block() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        let index = registry
+            .build(name, &amps, &BuildOptions::default())
+            .unwrap();
+        for amp in amps.iter().step_by(13) {
+            let result = index
+                .lookup_block(amp.block_id)
+                .unwrap_or_else(|| panic!("{}: block {} not found", name, amp.block_id));
+            assert_eq!(result.block_id, amp.block_id);
+            assert_eq!(result.title, amp.title);
+            assert_eq!(result.advertiser, amp.advertiser);
+            assert_eq!(result.url, amp.url);
+            assert_eq!(result.click_url, amp.click_url);
+        }
+        assert!(index.lookup_block(-1).is_none(), "{}", name);
+    }
+}
+
+#[test]
+fn test_blart_prefix_with_nul() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let index = BlartAmpIndex::build(&amps).expect("Failed to build index");
+    assert_eq!(index.iter_prefix("am\0").count(), 0);
+}
+
+#[test]
+fn test_query_with_nul_is_empty_for_all_backends() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        let index = registry
+            .build(name, &amps, &BuildOptions::default())
+            .expect("Failed to build index");
+        let results = index.query("am\0").expect("Query failed");
+        assert!(results.is_empty(), "{}", name);
+    }
+}
@@ -0,0 +1,116 @@
+use rethink_about_amp::memory_report::{COMPONENT_STATS, Measurement, MemoryReport, split_stats};
+use rethink_about_amp::{
+    AmpQuery, BackendRegistry, BuildOptions, StringCompression, load_amp_data,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+fn measurement(backend: &str, allocated_bytes: usize, peak_bytes: usize) -> Measurement {
+    Measurement {
+        dataset: "data/amp-us-desktop.json".to_string(),
+        backend: backend.to_string(),
+        string_compression: StringCompression::None,
+        allocated_bytes,
+        peak_bytes,
+        build_ms: 12.5,
+        ns_per_query: 800.0,
+        key_count: 11512,
+        components: BTreeMap::from([("suggestion_table_bytes".to_string(), 379762)]),
+        counts: BTreeMap::from([("suggestions_count".to_string(), 6152)]),
+    }
+}
+
+#[test]
+fn test_report_round_trips_through_json() {
+    let report = MemoryReport {
+        measurements: vec![
+            measurement("btree", 1000, 2000),
+            measurement("louds", 500, 900),
+        ],
+    };
+    let json = report.to_json().unwrap();
+    assert!(
+        json.contains("\"string_compression\": \"none\""),
+        "{}",
+        json
+    );
+    assert_eq!(MemoryReport::from_json(&json).unwrap(), report);
+}
+
+#[test]
+fn test_report_as_csv() {
+    let mut quoted = measurement("btree", 1000, 2000);
+    quoted.dataset = "data/a,b.json".to_string();
+    quoted.string_compression = StringCompression::Fsst;
+    let report = MemoryReport {
+        measurements: vec![measurement("btree", 1000, 2000), quoted],
+    };
+    let csv = report.to_csv();
+    let lines: Vec<&str> = csv.lines().collect();
+
+    assert_eq!(lines[0], "dataset,backend,string_compression,metric,value");
+    assert!(lines.contains(&"data/amp-us-desktop.json,btree,none,allocated_bytes,1000"));
+    assert!(lines.contains(&"data/amp-us-desktop.json,btree,none,build_ms,12.500"));
+    assert!(lines.contains(&"data/amp-us-desktop.json,btree,none,suggestion_table_bytes,379762"));
+    assert!(lines.contains(&"data/amp-us-desktop.json,btree,none,suggestions_count,6152"));
+    assert!(lines.contains(&"\"data/a,b.json\",btree,fsst,peak_bytes,2000"));
+    // 5 metrics, 1 component and 1 count per measurement
+    assert_eq!(lines.len(), 1 + 2 * 7);
+}
+
+#[test]
+fn test_every_backend_reports_its_component_bytes() {
+    let amps = load_amp_data("data/amp-us-desktop.json").expect("Failed to load AMP data");
+    let registry = BackendRegistry::with_defaults();
+    let options = BuildOptions {
+        infix_min_chars: Some(3),
+        ..BuildOptions::default()
+    };
+    for name in registry.names() {
+        let index = registry.build(name, &amps, &options).unwrap();
+        let (components, counts) = split_stats(index.stats());
+        let names: BTreeSet<&str> = components.keys().map(String::as_str).collect();
+        assert_eq!(names, BTreeSet::from(COMPONENT_STATS), "{}", name);
+        for (component, bytes) in &components {
+            assert!(*bytes > 0, "{} {}", name, component);
+        }
+        assert_eq!(
+            counts.get("suggestions_count"),
+            Some(&amps.len()),
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_regressions_over_threshold() {
+    let baseline = MemoryReport {
+        measurements: vec![
+            measurement("btree", 1000, 2000),
+            measurement("louds", 500, 900),
+        ],
+    };
+    let report = MemoryReport {
+        measurements: vec![
+            measurement("btree", 1040, 2000),
+            measurement("louds", 600, 900),
+            measurement("hybrid", 9000, 9000),
+        ],
+    };
+
+    assert!(report.regressions(&baseline, 25.0).is_empty());
+
+    let regressions = report.regressions(&baseline, 5.0);
+    assert_eq!(regressions.len(), 1, "{:?}", regressions);
+    assert_eq!(regressions[0].backend, "louds");
+    assert_eq!(regressions[0].metric, "allocated_bytes");
+    assert!((regressions[0].growth_pct() - 20.0).abs() < 1e-9);
+    assert!(
+        regressions[0]
+            .to_string()
+            .contains("grew 20.0% (500 -> 600)")
+    );
+
+    // Shrinking never regresses, and missing baselines are skipped
+    assert!(baseline.regressions(&report, 0.0).is_empty());
+}
@@ -0,0 +1,145 @@
+use rethink_about_amp::metrics::{LATENCY_BUCKETS_US, OTHER_ADVERTISER};
+use rethink_about_amp::{
+    AmpIndexer, AmpQuery, BTreeAmpIndex, BackendRegistry, BuildOptions, HybridAmpIndex,
+    InstrumentedIndex, QueryContext, QueryPath, load_amp_data,
+};
+use std::sync::Arc;
+
+const DESKTOP_DATA: &str = "data/amp-us-desktop.json";
+
+#[test]
+fn test_counts_hits_and_misses() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let index = InstrumentedIndex::<BTreeAmpIndex>::build(&amps).expect("Failed to build index");
+
+    assert_eq!(index.query("amazon").unwrap().len(), 1);
+    assert_eq!(index.query("amazon").unwrap().len(), 1);
+    assert!(index.query("no such keyword").unwrap().is_empty());
+
+    let snapshot = index.snapshot();
+    assert_eq!(snapshot.queries, 3);
+    assert_eq!(snapshot.hits, 2);
+    assert_eq!(snapshot.misses, 1);
+    assert_eq!(snapshot.errors, 0);
+    assert!((snapshot.hit_rate() - 2.0 / 3.0).abs() < 1e-9);
+    assert_eq!(snapshot.advertiser_hits["Amazon"], 2);
+    assert!(!snapshot.advertiser_hits.contains_key(OTHER_ADVERTISER));
+    assert_eq!(snapshot.paths["tree"], 3);
+    assert_eq!(snapshot.paths["short_cache"], 0);
+
+    // Latencies are bucketed by query length in characters, the longest ones together
+    let by_length = |label: &str| {
+        snapshot
+            .latency
+            .iter()
+            .find(|l| l.query_length == label)
+            .unwrap()
+            .count
+    };
+    assert_eq!(by_length("6"), 2);
+    assert_eq!(by_length("10+"), 1);
+    let counted: u64 = snapshot.latency.iter().map(|l| l.count).sum();
+    assert_eq!(counted, 3);
+    for latency in &snapshot.latency {
+        assert_eq!(latency.buckets.len(), LATENCY_BUCKETS_US.len() + 1);
+        assert_eq!(latency.buckets.iter().sum::<u64>(), latency.count);
+    }
+
+    // Stats are those of the wrapped index
+    assert_eq!(index.stats(), index.inner().stats());
+}
+
+#[test]
+fn test_unknown_advertisers() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let index = InstrumentedIndex::new(BTreeAmpIndex::build(&amps).unwrap(), ["Nobody"]);
+
+    index.query("amazon").unwrap();
+    let snapshot = index.snapshot();
+    assert_eq!(snapshot.advertiser_hits["Nobody"], 0);
+    assert_eq!(snapshot.advertiser_hits[OTHER_ADVERTISER], 1);
+}
+
+#[test]
+fn test_hybrid_query_paths() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let index = InstrumentedIndex::<HybridAmpIndex>::build(&amps).expect("Failed to build index");
+
+    let (results, path) = index
+        .inner()
+        .query_with_path("amaz", &QueryContext::default())
+        .unwrap();
+    assert!(!results.is_empty());
+    assert_eq!(path, QueryPath::Tree);
+    let (results, path) = index
+        .inner()
+        .query_with_path("ma", &QueryContext::default())
+        .unwrap();
+    assert!(!results.is_empty());
+    assert_eq!(path, QueryPath::ShortCache);
+
+    index.query("ma").unwrap();
+    index.query("amaz").unwrap();
+    index.query("amazon").unwrap();
+    let snapshot = index.snapshot();
+    assert_eq!(snapshot.paths["short_cache"], 1);
+    assert_eq!(snapshot.paths["tree"], 2);
+}
+
+#[test]
+fn test_concurrent_queries() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let index = BackendRegistry::with_defaults()
+        .build("blart", &amps, &BuildOptions::default())
+        .unwrap();
+    let index = Arc::new(InstrumentedIndex::for_amps(index, &amps));
+    let metrics = index.metrics();
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let index = Arc::clone(&index);
+            std::thread::spawn(move || {
+                for _ in 0..250 {
+                    index.query("amazon").unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    drop(index);
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.queries, 1000);
+    assert_eq!(snapshot.hits, 1000);
+    assert_eq!(snapshot.advertiser_hits["Amazon"], 1000);
+}
+
+#[test]
+fn test_prometheus_export() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let index = InstrumentedIndex::<BTreeAmpIndex>::build(&amps).expect("Failed to build index");
+    index.query("amazon").unwrap();
+    index.query("zzzzzz").unwrap();
+
+    let text = index.snapshot().to_prometheus("amp");
+    assert!(
+        text.contains("# TYPE amp_queries_total counter"),
+        "{}",
+        text
+    );
+    assert!(text.contains("amp_queries_total 2\n"));
+    assert!(text.contains("amp_query_results_total{result=\"hit\"} 1\n"));
+    assert!(text.contains("amp_query_results_total{result=\"miss\"} 1\n"));
+    assert!(text.contains("amp_advertiser_hits_total{advertiser=\"Amazon\"} 1\n"));
+    assert!(text.contains("# TYPE amp_query_duration_seconds histogram"));
+    assert!(text.contains("amp_query_duration_seconds_bucket{query_length=\"6\",le=\"+Inf\"} 2\n"));
+    assert!(text.contains("amp_query_duration_seconds_count{query_length=\"6\"} 2\n"));
+
+    // Every sample line is `name{labels} value`
+    for line in text.lines().filter(|l| !l.starts_with('#')) {
+        let (_, value) = line.rsplit_once(' ').unwrap();
+        assert!(value.parse::<f64>().is_ok(), "{}", line);
+    }
+}
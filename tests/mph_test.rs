@@ -0,0 +1,55 @@
+use rethink_about_amp::mph::{Mph, hash_bytes};
+use std::collections::HashSet;
+
+#[test]
+fn test_mph_is_a_bijection() {
+    for n in [0, 1, 2, 5, 100, 10_000] {
+        let hashes: Vec<u64> = (0..n)
+            .map(|i| hash_bytes(format!("key {}", i).as_bytes()))
+            .collect();
+        let mph = Mph::new(&hashes).unwrap();
+        assert_eq!(mph.len(), n);
+        let slots: HashSet<usize> = hashes.iter().map(|&h| mph.slot(h).unwrap()).collect();
+        assert_eq!(slots.len(), n);
+        assert!(slots.iter().all(|&s| s < n));
+    }
+}
+
+#[test]
+fn test_mph_small_sets() {
+    // Small sets often put most keys in one bucket
+    for n in 1..100 {
+        for set in 0..20 {
+            let hashes: Vec<u64> = (0..n)
+                .map(|i| hash_bytes(format!("{} {}", set, i).as_bytes()))
+                .collect();
+            let mph = Mph::new(&hashes).unwrap();
+            let slots: HashSet<usize> = hashes.iter().map(|&h| mph.slot(h).unwrap()).collect();
+            assert_eq!(slots.len(), n);
+        }
+    }
+}
+
+#[test]
+fn test_mph_empty_and_duplicates() {
+    let mph = Mph::new(&[]).unwrap();
+    assert!(mph.is_empty());
+    assert_eq!(mph.slot(hash_bytes(b"anything")), None);
+
+    let hash = hash_bytes(b"same");
+    assert!(Mph::new(&[hash, 1, hash]).is_err());
+}
+
+#[test]
+fn test_mph_size() {
+    let hashes: Vec<u64> = (0..100_000u32)
+        .map(|i| hash_bytes(&i.to_le_bytes()))
+        .collect();
+    let mph = Mph::new(&hashes).unwrap();
+    // A few bits per key
+    assert!(
+        mph.size_bytes() * 8 < hashes.len() * 8,
+        "{}",
+        mph.size_bytes()
+    );
+}
@@ -0,0 +1,98 @@
+use rethink_about_amp::{
+    AmpIndexer, BTreeAmpIndex, BlartAmpIndex, FormFactor, HybridAmpIndex, IndexKey, MultiIndex,
+    load_amp_data,
+};
+
+fn desktop() -> IndexKey {
+    IndexKey::new("us", FormFactor::Desktop)
+}
+
+fn phone() -> IndexKey {
+    IndexKey::new("US", FormFactor::Phone)
+}
+
+#[test]
+fn test_index_key_from_file_name() {
+    assert_eq!(
+        IndexKey::from_file_name("amp-us-desktop.json"),
+        Some(desktop())
+    );
+    assert_eq!(IndexKey::from_file_name("amp-us-phone.json"), Some(phone()));
+    assert_eq!(
+        IndexKey::from_file_name("amp-us-mobile.json"),
+        Some(phone())
+    );
+    assert_eq!(IndexKey::from_file_name("amp-us-tablet.json"), None);
+    assert_eq!(IndexKey::from_file_name("advertisers.json"), None);
+    assert_eq!(IndexKey::from_file_name("amp-us-desktop.csv"), None);
+}
+
+fn test_multi_index_for<T: AmpIndexer>(indexer_name: &str) {
+    let multi: MultiIndex<T> = MultiIndex::load_dir("data").expect("Failed to load data dir");
+    assert_eq!(multi.keys(), vec![&desktop(), &phone()], "{}", indexer_name);
+
+    // Both datasets answer the same keyword, but with form factor specific click URLs
+    let desktop_results = multi.query(&desktop(), "amazon").expect("Query failed");
+    let phone_results = multi.query(&phone(), "amazon").expect("Query failed");
+    assert_eq!(desktop_results.len(), 1, "{}", indexer_name);
+    assert_eq!(phone_results.len(), 1, "{}", indexer_name);
+    assert_eq!(desktop_results[0].block_id, phone_results[0].block_id);
+    assert_eq!(desktop_results[0].title, phone_results[0].title);
+    assert!(
+        desktop_results[0].click_url.contains("mozillacla"),
+        "{}: unexpected desktop click URL {}",
+        indexer_name,
+        desktop_results[0].click_url
+    );
+    assert!(
+        phone_results[0].click_url.contains("firefoxmobilecla"),
+        "{}: unexpected phone click URL {}",
+        indexer_name,
+        phone_results[0].click_url
+    );
+
+    let missing = IndexKey::new("de", FormFactor::Desktop);
+    assert!(multi.query(&missing, "amazon").is_err(), "{}", indexer_name);
+}
+
+#[test]
+fn test_btree_multi_index() {
+    test_multi_index_for::<BTreeAmpIndex>("BTree");
+}
+
+#[test]
+fn test_hybrid_multi_index() {
+    test_multi_index_for::<HybridAmpIndex>("Hybrid");
+}
+
+#[test]
+fn test_blart_multi_index() {
+    test_multi_index_for::<BlartAmpIndex>("Blart");
+}
+
+#[test]
+fn test_multi_index_shares_dictionaries() {
+    let desktop_amps = load_amp_data("data/amp-us-desktop.json").expect("Failed to load");
+    let phone_amps = load_amp_data("data/amp-us-phone.json").expect("Failed to load");
+
+    let mut desktop_only: MultiIndex<BlartAmpIndex> = MultiIndex::new();
+    desktop_only.insert(desktop(), &desktop_amps).unwrap();
+    let desktop_strings = desktop_only.stats()["shared_strings_count"];
+
+    let mut multi: MultiIndex<BlartAmpIndex> = MultiIndex::new();
+    multi.insert(desktop(), &desktop_amps).unwrap();
+    multi.insert(phone(), &phone_amps).unwrap();
+    let stats = multi.stats();
+
+    // Titles, advertisers, icons and categories overlap, so adding the phone dataset
+    // adds far fewer strings than a second standalone index would hold.
+    let added = stats["shared_strings_count"] - desktop_strings;
+    assert!(
+        added < desktop_strings / 2,
+        "phone dataset added {} strings on top of {}",
+        added,
+        desktop_strings
+    );
+    assert_eq!(stats["index_count"], 2);
+    assert!(stats["us/phone.suggestions_count"] > 0);
+}
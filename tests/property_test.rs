@@ -0,0 +1,404 @@
+//! Property tests for keyword collapsing and the indexers, on generated keyword sets mixing
+//! multibyte characters, spaces and shared prefixes.
+
+use proptest::prelude::*;
+use rethink_about_amp::common::{collapse_intervals, collapse_keywords, collapse_keywords_ex};
+use rethink_about_amp::{
+    AmpIndexBuilder, AmpIndexer, AmpQuery, BTreeAmpIndex, BackendRegistry, BlartAmpIndex,
+    BuildOptions, HybridAmpIndex, LoudsAmpIndex, OriginalAmp, PrefixTableAmpIndex,
+    SortedArrayAmpIndex,
+};
+use std::collections::{HashMap, HashSet};
+
+/// A small alphabet so that generated keywords often share prefixes
+fn keyword() -> impl Strategy<Value = String> {
+    prop::collection::vec(prop::sample::select(vec!['a', 'b', 'é', '日', ' ']), 1..7)
+        .prop_map(|chars| chars.into_iter().collect())
+}
+
+/// Prefixes of `s` of at least `min` chars, shortest first
+fn partials(s: &str, min: usize) -> Vec<String> {
+    let len = s.chars().count();
+    (min..=len).map(|n| s.chars().take(n).collect()).collect()
+}
+
+/// A sequence of keywords and the full keyword each one stands for
+fn keywords_with_full() -> impl Strategy<Value = Vec<(String, String)>> {
+    prop::collection::vec((keyword(), any::<usize>()), 0..8).prop_map(|full_keywords| {
+        full_keywords
+            .into_iter()
+            .flat_map(|(fk, seed)| {
+                let min = 1 + seed % fk.chars().count();
+                partials(&fk, min)
+                    .into_iter()
+                    .map(move |kw| (kw, fk.clone()))
+            })
+            .collect()
+    })
+}
+
+/// Run-length encode the full keyword of each keyword, as in the AMP payloads
+fn encode_runs(full_keywords: &[String]) -> Vec<(String, usize)> {
+    let mut runs: Vec<(String, usize)> = Vec::new();
+    for fk in full_keywords {
+        match runs.last_mut() {
+            Some((last, count)) if last == fk => *count += 1,
+            _ => runs.push((fk.clone(), 1)),
+        }
+    }
+    runs
+}
+
+/// Generated suggestions, along with the expected answer `(block_id, full_keyword)` for
+/// each of their keywords. As in the real payloads, a keyword belongs to one suggestion.
+/// Some suggestions skip partials or list their keywords out of order, and some have full
+/// keywords for only their first keywords, the rest being their own full keyword.
+fn suggestions() -> impl Strategy<Value = (Vec<OriginalAmp>, HashMap<String, (i32, String)>)> {
+    prop::collection::vec(
+        (
+            prop::collection::vec((keyword(), any::<usize>()), 1..4),
+            any::<usize>(),
+            any::<usize>(),
+        ),
+        1..8,
+    )
+    .prop_map(|suggestions| {
+        let mut expected = HashMap::new();
+        let mut amps = Vec::new();
+        for (i, (full_keywords, order, uncovered)) in suggestions.into_iter().enumerate() {
+            let block_id = i as i32 + 1;
+            let mut pairs = Vec::new();
+            for (fk, seed) in full_keywords {
+                let len = fk.chars().count();
+                let min = 1 + seed % len;
+                // Every other seed skips some of the partials short of the full keyword
+                let gaps = if seed / len % 2 == 0 {
+                    seed / len / 2
+                } else {
+                    0
+                };
+                for (n, kw) in partials(&fk, min).into_iter().enumerate() {
+                    let skipped = gaps >> n & 1 == 1 && kw.chars().count() < len;
+                    if !skipped && !expected.contains_key(&kw) {
+                        expected.insert(kw.clone(), (block_id, fk.clone()));
+                        pairs.push((kw, fk.clone()));
+                    }
+                }
+            }
+            let half = pairs.len() / 2;
+            match order % 3 {
+                1 => pairs.reverse(),
+                2 => pairs.rotate_left(half),
+                _ => {}
+            }
+            let (keywords, mut fks): (Vec<String>, Vec<String>) = pairs.into_iter().unzip();
+            // A quarter of the suggestions lose the full keywords of their last keywords
+            let uncovered = if uncovered % 4 == 0 {
+                uncovered / 4 % (keywords.len() + 1)
+            } else {
+                0
+            };
+            let covered = keywords.len() - uncovered;
+            for kw in &keywords[covered..] {
+                expected.insert(kw.clone(), (block_id, kw.clone()));
+            }
+            fks.truncate(covered);
+            amps.push(amp(block_id, keywords, encode_runs(&fks)));
+        }
+        (amps, expected)
+    })
+}
+
+/// Generated suggestions whose keywords may collide: with two letters and a few chars,
+/// suggestions often share keywords and collapsed keys
+fn colliding_suggestions() -> impl Strategy<Value = Vec<OriginalAmp>> {
+    let full_keyword = prop::collection::vec(prop::sample::select(vec!['a', 'b']), 1..4)
+        .prop_map(|chars| chars.into_iter().collect::<String>());
+    prop::collection::vec(
+        prop::collection::vec((full_keyword, any::<usize>()), 1..3),
+        1..6,
+    )
+    .prop_map(|suggestions| {
+        suggestions
+            .into_iter()
+            .enumerate()
+            .map(|(i, full_keywords)| {
+                let mut pairs: Vec<(String, String)> = Vec::new();
+                for (fk, seed) in full_keywords {
+                    let min = 1 + seed % fk.chars().count();
+                    for kw in partials(&fk, min) {
+                        if !pairs.iter().any(|(k, _)| *k == kw) {
+                            pairs.push((kw, fk.clone()));
+                        }
+                    }
+                }
+                let (keywords, fks): (Vec<String>, Vec<String>) = pairs.into_iter().unzip();
+                amp(i as i32 + 1, keywords, encode_runs(&fks))
+            })
+            .collect()
+    })
+}
+
+fn amp(block_id: i32, keywords: Vec<String>, full_keywords: Vec<(String, usize)>) -> OriginalAmp {
+    OriginalAmp {
+        keywords,
+        title: format!("Title {}", block_id),
+        url: format!("https://example.com/{}?ref=amp", block_id),
+        score: Some(0.3),
+        full_keywords,
+        advertiser: format!("Advertiser {}", block_id % 3),
+        block_id,
+        iab_category: "22 - Shopping".to_string(),
+        click_url: format!("https://click.example.com/ctp?id={}", block_id),
+        impression_url: format!("https://impression.example.com/?id={}", block_id),
+        icon_id: format!("icon-{}", block_id % 2),
+        start: None,
+        end: None,
+    }
+}
+
+/// Check that `index` answers exactly the expected keywords, with the right suggestion
+fn check_answers(
+    name: &str,
+    index: &impl AmpQuery,
+    expected: &HashMap<String, (i32, String)>,
+    queries: &[String],
+) -> Result<(), TestCaseError> {
+    for query in queries {
+        let results = index
+            .query(query)
+            .map_err(|e| TestCaseError::fail(e.to_string()))?;
+        match expected.get(query) {
+            Some((block_id, full_keyword)) => {
+                prop_assert_eq!(results.len(), 1, "{}: {:?} not answered", name, query);
+                prop_assert_eq!(results[0].block_id, *block_id, "{}: {:?}", name, query);
+                prop_assert_eq!(
+                    &results[0].full_keyword,
+                    full_keyword,
+                    "{}: {:?}",
+                    name,
+                    query
+                );
+            }
+            None => prop_assert!(
+                results.is_empty(),
+                "{}: {:?} unexpectedly answered by {}",
+                name,
+                query,
+                results[0].block_id
+            ),
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn test_collapsed_keywords_answer_exactly_the_originals(
+        keywords in prop::collection::vec(keyword(), 0..40),
+    ) {
+        let collapsed = collapse_keywords(&keywords);
+        prop_assert!(collapsed.len() <= keywords.len());
+
+        // Every original keyword is still answered
+        for kw in &keywords {
+            let len = kw.chars().count();
+            prop_assert!(
+                collapsed
+                    .iter()
+                    .any(|(c, min)| c.starts_with(kw.as_str()) && len >= *min),
+                "{:?} is lost",
+                kw
+            );
+        }
+
+        // Nothing extra is answered
+        let originals: HashSet<&str> = keywords.iter().map(String::as_str).collect();
+        for (c, min) in &collapsed {
+            for partial in partials(c, *min) {
+                prop_assert!(originals.contains(partial.as_str()), "{:?} is extra", partial);
+            }
+        }
+    }
+
+    #[test]
+    fn test_collapsed_intervals_answer_exactly_the_originals(
+        keywords in prop::collection::vec(keyword(), 0..40),
+    ) {
+        // All for the same full keyword, so that any keyword may share an interval
+        let intervals = collapse_intervals(&keywords, &[("x".to_string(), keywords.len())]);
+        prop_assert!(intervals.intervals.len() <= collapse_keywords(&keywords).len());
+
+        let originals: HashSet<&str> = keywords.iter().map(String::as_str).collect();
+        for kw in &keywords {
+            prop_assert!(intervals.contains(kw), "{:?} is lost", kw);
+        }
+        for interval in &intervals.intervals {
+            for partial in partials(&interval.key, interval.min_len) {
+                prop_assert_eq!(
+                    intervals.contains(&partial),
+                    originals.contains(partial.as_str()),
+                    "{:?}",
+                    partial
+                );
+            }
+        }
+        let expected: std::collections::BTreeSet<String> =
+            originals.iter().map(|kw| kw.to_string()).collect();
+        prop_assert_eq!(intervals.keywords(), expected);
+    }
+
+    #[test]
+    fn test_collapsed_full_keywords_match_the_originals(keywords in keywords_with_full()) {
+        let (kws, fks): (Vec<String>, Vec<String>) = keywords.iter().cloned().unzip();
+        let collapsed = collapse_keywords_ex(&kws, &encode_runs(&fks));
+
+        let originals: HashSet<(&str, &str)> =
+            keywords.iter().map(|(k, f)| (k.as_str(), f.as_str())).collect();
+        let mut covered = 0;
+        for (c, min, fk) in &collapsed {
+            let full_keyword = fk.full_keyword(c);
+            for partial in partials(c, *min) {
+                prop_assert!(
+                    originals.contains(&(partial.as_str(), full_keyword.as_str())),
+                    "{:?} gets full keyword {:?}",
+                    partial,
+                    full_keyword
+                );
+                covered += 1;
+            }
+        }
+        // Runs don't overlap, so each keyword is covered exactly once
+        prop_assert_eq!(covered, keywords.len());
+    }
+
+    #[test]
+    fn test_collapsed_intervals_match_the_full_keywords(keywords in keywords_with_full()) {
+        let (kws, fks): (Vec<String>, Vec<String>) = keywords.iter().cloned().unzip();
+        let runs = encode_runs(&fks);
+        let intervals = collapse_intervals(&kws, &runs);
+        prop_assert!(intervals.intervals.len() <= collapse_keywords_ex(&kws, &runs).len());
+
+        let originals: HashSet<(&str, &str)> =
+            keywords.iter().map(|(k, f)| (k.as_str(), f.as_str())).collect();
+        let mut answered = HashSet::new();
+        for interval in &intervals.intervals {
+            let full_keyword = interval.full_keyword.full_keyword(&interval.key);
+            for keyword in interval.keywords() {
+                prop_assert!(
+                    originals.contains(&(keyword, full_keyword.as_str())),
+                    "{:?} gets full keyword {:?}",
+                    keyword,
+                    full_keyword
+                );
+                answered.insert((keyword.to_string(), full_keyword.clone()));
+            }
+        }
+        prop_assert_eq!(answered.len(), originals.len());
+    }
+
+    #[test]
+    fn test_all_backends_answer_exactly_the_keywords(
+        (amps, expected) in suggestions(),
+        extra in prop::collection::vec(keyword(), 0..20),
+    ) {
+        // Every keyword, every prefix of a full keyword (including the ones too short to
+        // be answered) and some random queries
+        let mut queries: Vec<String> = expected.keys().cloned().collect();
+        for (_, full_keyword) in expected.values() {
+            queries.extend(partials(full_keyword, 1));
+        }
+        queries.extend(extra);
+
+        let btree = BTreeAmpIndex::build(&amps).unwrap();
+        let blart = BlartAmpIndex::build(&amps).unwrap();
+        let hybrid = HybridAmpIndex::build(&amps).unwrap();
+        let louds = LoudsAmpIndex::build(&amps).unwrap();
+        let sorted_array = SortedArrayAmpIndex::build(&amps).unwrap();
+        let prefix_table = PrefixTableAmpIndex::build(&amps).unwrap();
+        let options = BuildOptions {
+            short_cache_max_chars: 1,
+            ..BuildOptions::default()
+        };
+        let mut builder = HybridAmpIndex::builder(&options);
+        builder.add_all(&amps).unwrap();
+        let hybrid_small_cache = builder.finish().unwrap();
+
+        check_answers("BTree", &btree, &expected, &queries)?;
+        check_answers("Blart", &blart, &expected, &queries)?;
+        check_answers("Hybrid", &hybrid, &expected, &queries)?;
+        check_answers("LOUDS", &louds, &expected, &queries)?;
+        check_answers("SortedArray", &sorted_array, &expected, &queries)?;
+        check_answers("PrefixTable", &prefix_table, &expected, &queries)?;
+        check_answers("Hybrid (1 char cache)", &hybrid_small_cache, &expected, &queries)?;
+
+        // Every keyword is listed for its suggestion, and only those
+        for amp in &amps {
+            let mut keywords: Vec<String> = expected
+                .iter()
+                .filter(|(_, (block_id, _))| *block_id == amp.block_id)
+                .map(|(kw, _)| kw.clone())
+                .collect();
+            keywords.sort();
+            prop_assert_eq!(&btree.keywords_for(amp.block_id), &keywords);
+            prop_assert_eq!(&blart.keywords_for(amp.block_id), &keywords);
+            prop_assert_eq!(&hybrid.keywords_for(amp.block_id), &keywords);
+            prop_assert_eq!(&louds.keywords_for(amp.block_id), &keywords);
+            prop_assert_eq!(&sorted_array.keywords_for(amp.block_id), &keywords);
+            prop_assert_eq!(&prefix_table.keywords_for(amp.block_id), &keywords);
+        }
+    }
+
+    #[test]
+    fn test_all_backends_agree_on_colliding_suggestions(amps in colliding_suggestions()) {
+        let registry = BackendRegistry::with_defaults();
+        let indexes: Vec<_> = registry
+            .names()
+            .into_iter()
+            .map(|name| (name, registry.build(name, &amps, &BuildOptions::default()).unwrap()))
+            .collect();
+
+        // Every backend keeps a shared key for the same suggestion, with the same interval
+        let (reference_name, reference) = &indexes[0];
+        let keys: Vec<_> = reference.iter_prefix("").collect();
+        for (name, index) in &indexes[1..] {
+            let other: Vec<_> = index.iter_prefix("").collect();
+            prop_assert_eq!(&other, &keys, "{} and {}", name, reference_name);
+            for amp in &amps {
+                prop_assert_eq!(
+                    index.keywords_for(amp.block_id),
+                    reference.keywords_for(amp.block_id),
+                    "{} and {} on {}",
+                    name,
+                    reference_name,
+                    amp.block_id
+                );
+            }
+        }
+
+        // Whichever suggestion answers a keyword has it, with the same full keyword
+        let queries: HashSet<&String> = amps.iter().flat_map(|amp| &amp.keywords).collect();
+        for (name, index) in &indexes {
+            for query in &queries {
+                // A suggestion that lost a shared key may leave some of its keywords out
+                let Some(result) = index.query(query).unwrap().pop() else {
+                    continue;
+                };
+                let amp = amps.iter().find(|amp| amp.block_id == result.block_id).unwrap();
+                let full_keywords = amp
+                    .full_keywords
+                    .iter()
+                    .flat_map(|(fk, count)| std::iter::repeat_n(fk, *count));
+                let full_keyword = amp.keywords.iter().zip(full_keywords).find(|(kw, _)| kw == query);
+                prop_assert_eq!(
+                    full_keyword.map(|(_, fk)| fk),
+                    Some(&result.full_keyword),
+                    "{}: {:?} answered by {}",
+                    name,
+                    query,
+                    amp.block_id
+                );
+            }
+        }
+    }
+}
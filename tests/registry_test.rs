@@ -0,0 +1,205 @@
+use rethink_about_amp::{
+    AmpQuery, BTreeAmpIndex, BackendRegistry, BuildOptions, DynAmpIndex, IndexConfig, OriginalAmp,
+    StringCompression, load_amp_data,
+};
+
+const DESKTOP_DATA: &str = "data/amp-us-desktop.json";
+
+#[test]
+fn test_default_backends() {
+    let registry = BackendRegistry::with_defaults();
+    assert_eq!(
+        registry.names(),
+        vec![
+            "blart",
+            "btree",
+            "hybrid",
+            "louds",
+            "prefix_table",
+            "sorted_array"
+        ]
+    );
+}
+
+#[test]
+fn test_all_backends_agree() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let registry = BackendRegistry::default();
+
+    let indexes: Vec<(&str, DynAmpIndex)> = registry
+        .names()
+        .into_iter()
+        .map(|name| {
+            let index = registry
+                .build(name, &amps, &BuildOptions::default())
+                .expect("Failed to build index");
+            (name, index)
+        })
+        .collect();
+
+    for query in ["am", "amazon", "k c", "k cup", "mini ", "mini s", "fo"] {
+        let expected = indexes[0].1.query(query).expect("Query failed");
+        for (name, index) in &indexes[1..] {
+            let results = index.query(query).expect("Query failed");
+            assert_eq!(
+                results.iter().map(|r| r.block_id).collect::<Vec<_>>(),
+                expected.iter().map(|r| r.block_id).collect::<Vec<_>>(),
+                "{} disagrees with {} on '{}'",
+                name,
+                indexes[0].0,
+                query
+            );
+        }
+    }
+}
+
+#[test]
+fn test_unknown_backend() {
+    let registry = BackendRegistry::with_defaults();
+    let err = registry
+        .build("fst", &[], &BuildOptions::default())
+        .err()
+        .expect("Unknown backend was built");
+    assert!(err.to_string().contains("Unknown backend 'fst'"), "{}", err);
+}
+
+#[test]
+fn test_build_from_config() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let registry = BackendRegistry::with_defaults();
+
+    let config: IndexConfig =
+        serde_json::from_str(r#"{"backend": "hybrid", "short_cache_max_chars": 0}"#).unwrap();
+    assert_eq!(config.backend, "hybrid");
+    assert_eq!(config.options.short_cache_max_chars, 0);
+    let index = registry.build_from_config(&config, &amps).unwrap();
+    assert_eq!(index.stats()["cache_exact_matches"], 0);
+    assert_eq!(index.query("amazon").unwrap().len(), 1);
+
+    let config: IndexConfig =
+        serde_json::from_str(r#"{"backend": "louds", "string_compression": "fsst"}"#).unwrap();
+    assert_eq!(config.options.string_compression, StringCompression::Fsst);
+    let compressed = registry.build_from_config(&config, &amps).unwrap();
+    let plain = registry
+        .build("louds", &amps, &BuildOptions::default())
+        .unwrap();
+    for query in ["am", "amazon", "k cup", "fo"] {
+        let expected = plain.query(query).unwrap();
+        let results = compressed.query(query).unwrap();
+        assert_eq!(results.len(), expected.len());
+        for (result, expected) in results.iter().zip(&expected) {
+            assert_eq!(result.title, expected.title);
+            assert_eq!(result.url, expected.url);
+        }
+    }
+    assert!(
+        compressed.stats()["suggestion_strings_bytes"] < plain.stats()["suggestion_strings_bytes"]
+    );
+
+    // Everything is optional
+    let config: IndexConfig = serde_json::from_str("{}").unwrap();
+    assert_eq!(config.backend, "blart");
+    assert_eq!(config.options.short_cache_max_chars, 3);
+    assert_eq!(config.options.string_compression, StringCompression::None);
+}
+
+#[test]
+fn test_custom_backend() {
+    let mut registry = BackendRegistry::new();
+    assert!(registry.names().is_empty());
+
+    registry.register_indexer::<BTreeAmpIndex>("reference");
+    assert!(registry.contains("reference"));
+
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let index = registry
+        .build("reference", &amps, &BuildOptions::default())
+        .unwrap();
+    assert_eq!(index.query("amazon").unwrap()[0].advertiser, "Amazon");
+}
+
+#[test]
+fn test_full_keyword_runs_stay_apart() {
+    // "a" and "ab" share a full keyword, "abc" has its own: collapsing "a".."abc" into
+    // one key would answer "a" with "abc"
+    let amps: Vec<OriginalAmp> = serde_json::from_str(
+        r#"[{
+            "keywords": ["a", "ab", "abc", "x", "xy"],
+            "full_keywords": [["ab", 2], ["abc", 1], ["xyz", 2]],
+            "title": "Title", "url": "https://example.com", "score": 0.3,
+            "advertiser": "Acme", "id": 1, "iab_category": "22 - Shopping",
+            "click_url": "https://click.example.com", "impression_url": "https://imp.example.com",
+            "icon": "icon"
+        }]"#,
+    )
+    .unwrap();
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        let index = registry
+            .build(name, &amps, &BuildOptions::default())
+            .unwrap();
+        for (query, full_keyword) in [
+            ("a", "ab"),
+            ("ab", "ab"),
+            ("abc", "abc"),
+            ("x", "xyz"),
+            ("xy", "xyz"),
+        ] {
+            let results = index.query(query).unwrap();
+            assert_eq!(results.len(), 1, "{} '{}'", name, query);
+            assert_eq!(
+                results[0].full_keyword, full_keyword,
+                "{} '{}'",
+                name, query
+            );
+        }
+    }
+}
+
+#[test]
+fn test_first_suggestion_keeps_a_shared_key() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    // Both collapse to the key "abc": the first added keeps it, with its own interval
+    let mut longer = amps[0].clone();
+    longer.keywords = vec!["ab".to_string(), "abc".to_string()];
+    longer.full_keywords = vec![("abc".to_string(), 2)];
+    let mut shorter = amps[1].clone();
+    shorter.keywords = vec!["abc".to_string()];
+    shorter.full_keywords = vec![("abc".to_string(), 1)];
+
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        let index = registry
+            .build(
+                name,
+                &[longer.clone(), shorter.clone()],
+                &BuildOptions::default(),
+            )
+            .unwrap();
+        for query in ["ab", "abc"] {
+            let results = index.query(query).unwrap();
+            assert_eq!(results.len(), 1, "{} '{}'", name, query);
+            assert_eq!(results[0].block_id, longer.block_id, "{} '{}'", name, query);
+        }
+        assert_eq!(
+            index.keywords_for(longer.block_id),
+            ["ab", "abc"],
+            "{}",
+            name
+        );
+        assert!(index.keywords_for(shorter.block_id).is_empty(), "{}", name);
+
+        let index = registry
+            .build(
+                name,
+                &[shorter.clone(), longer.clone()],
+                &BuildOptions::default(),
+            )
+            .unwrap();
+        assert!(index.query("ab").unwrap().is_empty(), "{}", name);
+        let results = index.query("abc").unwrap();
+        assert_eq!(results.len(), 1, "{}", name);
+        assert_eq!(results[0].block_id, shorter.block_id, "{}", name);
+        assert!(index.keywords_for(longer.block_id).is_empty(), "{}", name);
+    }
+}
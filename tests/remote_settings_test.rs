@@ -0,0 +1,126 @@
+use rethink_about_amp::remote_settings::{
+    Attachment, Record, amp_records, build_index, build_multi_index, load_amps,
+};
+use rethink_about_amp::{
+    AmpIndexer, BTreeAmpIndex, BlartAmpIndex, FileRecordSource, FormFactor, HybridAmpIndex,
+    IndexKey, RecordSource,
+};
+use std::error::Error;
+
+const FIXTURE_DIR: &str = "tests/fixtures/remote-settings";
+
+fn desktop() -> IndexKey {
+    IndexKey::new("us", FormFactor::Desktop)
+}
+
+fn phone() -> IndexKey {
+    IndexKey::new("us", FormFactor::Phone)
+}
+
+/// Wraps the file source and flips a byte of every attachment it serves
+struct TamperedSource(FileRecordSource);
+
+impl RecordSource for TamperedSource {
+    fn records(&self) -> Result<Vec<Record>, Box<dyn Error>> {
+        self.0.records()
+    }
+
+    fn fetch_attachment(&self, attachment: &Attachment) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut content = self.0.fetch_attachment(attachment)?;
+        content[1] ^= 0x20;
+        Ok(content)
+    }
+}
+
+#[test]
+fn test_amp_records_selection() {
+    let source = FileRecordSource::new(FIXTURE_DIR);
+
+    // Sorted by id, and the non-AMP records and the AMP record without attachment are skipped
+    let ids: Vec<String> = amp_records(&source, &desktop())
+        .expect("Failed to list records")
+        .into_iter()
+        .map(|r| r.id)
+        .collect();
+    assert_eq!(ids, vec!["data-01-us-desktop", "data-02-us-desktop"]);
+
+    let records = amp_records(&source, &phone()).expect("Failed to list records");
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].id, "data-01-us-phone");
+
+    let none = amp_records(&source, &IndexKey::new("ca", FormFactor::Desktop)).unwrap();
+    assert!(none.is_empty());
+}
+
+#[test]
+fn test_load_amps_concatenates_attachments() {
+    let source = FileRecordSource::new(FIXTURE_DIR);
+    let amps = load_amps(&source, &desktop()).expect("Failed to load records");
+    assert_eq!(amps.len(), 20);
+    assert_eq!(amps[0].block_id, 59);
+}
+
+#[test]
+fn test_tampered_attachment_is_rejected() {
+    let source = TamperedSource(FileRecordSource::new(FIXTURE_DIR));
+    let err = load_amps(&source, &desktop()).expect_err("Tampered attachment was accepted");
+    assert!(err.to_string().contains("hash mismatch"), "{}", err);
+}
+
+#[test]
+fn test_attachment_outside_root_is_rejected() {
+    let source = FileRecordSource::new(FIXTURE_DIR);
+    for location in [
+        "../records.json",
+        "/etc/passwd",
+        "main-workspace/../../records.json",
+    ] {
+        let attachment = Attachment {
+            hash: String::new(),
+            size: 0,
+            location: location.to_string(),
+            filename: None,
+        };
+        let err = source
+            .fetch_attachment(&attachment)
+            .expect_err("Attachment outside the root was read");
+        assert!(
+            err.to_string().contains("Invalid attachment location"),
+            "{}",
+            err
+        );
+    }
+}
+
+fn test_build_index_for<T: AmpIndexer>(indexer_name: &str) {
+    let source = FileRecordSource::new(FIXTURE_DIR);
+    let index: T = build_index(&source, &desktop()).expect("Failed to build index");
+    let results = index.query("amazon").expect("Query failed");
+    assert_eq!(results.len(), 1, "{}", indexer_name);
+    assert_eq!(results[0].advertiser, "Amazon", "{}", indexer_name);
+
+    let multi = build_multi_index::<T>(&source).expect("Failed to build multi index");
+    assert_eq!(multi.keys(), vec![&desktop(), &phone()], "{}", indexer_name);
+    let results = multi.query(&phone(), "amazon").expect("Query failed");
+    assert!(
+        results[0].click_url.contains("firefoxmobilecla"),
+        "{}: unexpected phone click URL {}",
+        indexer_name,
+        results[0].click_url
+    );
+}
+
+#[test]
+fn test_btree_build_from_records() {
+    test_build_index_for::<BTreeAmpIndex>("BTree");
+}
+
+#[test]
+fn test_hybrid_build_from_records() {
+    test_build_index_for::<HybridAmpIndex>("Hybrid");
+}
+
+#[test]
+fn test_blart_build_from_records() {
+    test_build_index_for::<BlartAmpIndex>("Blart");
+}
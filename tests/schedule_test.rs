@@ -0,0 +1,164 @@
+use rethink_about_amp::explain::Verdict;
+use rethink_about_amp::schedule::{ManualClock, TransitionKind};
+use rethink_about_amp::{
+    AmpQuery, BackendRegistry, BuildOptions, Clock, OriginalAmp, QueryContext, Schedule, TimeWindow,
+};
+
+fn amp(block_id: i32, keywords: &[&str], start: Option<i64>, end: Option<i64>) -> OriginalAmp {
+    OriginalAmp {
+        keywords: keywords.iter().map(|k| k.to_string()).collect(),
+        title: format!("Title {}", block_id),
+        url: format!("https://example.com/{}", block_id),
+        score: Some(0.3),
+        full_keywords: vec![(keywords.last().unwrap().to_string(), keywords.len())],
+        advertiser: format!("Advertiser {}", block_id),
+        block_id,
+        iab_category: "22 - Shopping".to_string(),
+        click_url: format!("https://click.example.com/ctp?id={}", block_id),
+        impression_url: format!("https://impression.example.com/?id={}", block_id),
+        icon_id: "icon".to_string(),
+        start,
+        end,
+    }
+}
+
+/// Three suggestions answering "fo", preferred in block id order: the first runs from 100
+/// to 200, the second from 150 on, the third always
+fn scheduled_amps() -> Vec<OriginalAmp> {
+    vec![
+        amp(1, &["fo", "foo"], Some(100), Some(200)),
+        amp(2, &["fo", "foo", "food"], Some(150), None),
+        amp(3, &["fo", "foo", "food", "foods"], None, None),
+    ]
+}
+
+#[test]
+fn test_time_window_bounds() {
+    let window = TimeWindow {
+        start: Some(100),
+        end: Some(200),
+    };
+    assert!(!window.contains(99));
+    assert!(window.contains(100));
+    assert!(window.contains(199));
+    assert!(!window.contains(200));
+
+    let open = TimeWindow::default();
+    assert!(open.contains(i64::MIN) && open.contains(i64::MAX));
+}
+
+#[test]
+fn test_window_deserialization() {
+    let json = r#"[
+        {"keywords": ["fo"], "title": "t", "url": "u", "score": 0.3, "advertiser": "a",
+         "id": 1, "iab_category": "c", "click_url": "c", "impression_url": "i", "icon": "1",
+         "start": 100, "end": 200},
+        {"keywords": ["fo"], "title": "t", "url": "u", "score": 0.3, "advertiser": "a",
+         "id": 2, "iab_category": "c", "click_url": "c", "impression_url": "i", "icon": "1"}
+    ]"#;
+    let amps: Vec<OriginalAmp> = serde_json::from_str(json).unwrap();
+    assert_eq!(
+        amps[0].window(),
+        Some(TimeWindow {
+            start: Some(100),
+            end: Some(200)
+        })
+    );
+    assert_eq!(amps[1].window(), None);
+}
+
+#[test]
+fn test_queries_follow_the_clock() {
+    let amps = scheduled_amps();
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        for short_cache_max_chars in [0, 3] {
+            let options = BuildOptions {
+                short_cache_max_chars,
+                ..BuildOptions::default()
+            };
+            let index = registry.build(name, &amps, &options).unwrap();
+            let clock = ManualClock::new(0);
+            let answer = |query: &str| {
+                let filter = QueryContext::new().at(clock.now());
+                let results = index.query_with_context(query, &filter).unwrap();
+                results.first().map(|r| r.block_id)
+            };
+            let context = format!("{} (cache {})", name, short_cache_max_chars);
+
+            // Windows are ignored without a time
+            assert_eq!(index.query("fo").unwrap()[0].block_id, 1, "{}", context);
+
+            assert_eq!(answer("fo"), Some(3), "{}", context);
+            assert_eq!(answer("food"), Some(3), "{}", context);
+            clock.set(100);
+            assert_eq!(answer("fo"), Some(1), "{}", context);
+            assert_eq!(answer("food"), Some(3), "{}", context);
+            clock.advance(50);
+            assert_eq!(answer("fo"), Some(1), "{}", context);
+            assert_eq!(answer("food"), Some(2), "{}", context);
+            clock.set(200);
+            assert_eq!(answer("fo"), Some(2), "{}", context);
+
+            // Windows and blocks combine
+            let filter = QueryContext::new().at(clock.now()).block_id(2);
+            let results = index.query_with_context("fo", &filter).unwrap();
+            assert_eq!(results[0].block_id, 3, "{}", context);
+        }
+    }
+}
+
+#[test]
+fn test_explain_reports_inactive_keys() {
+    let amps = scheduled_amps();
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        let index = registry
+            .build(name, &amps, &BuildOptions::default())
+            .unwrap();
+        let filter = QueryContext::new().at(120).block_id(1);
+        let explanation = index.explain_with_context("fo", &filter).unwrap();
+
+        assert_eq!(explanation.winner().unwrap().block_id, 3, "{}", name);
+        let skipped: Vec<_> = explanation
+            .candidates
+            .iter()
+            .filter(|c| matches!(c.verdict, Verdict::Blocked | Verdict::Inactive))
+            .map(|c| (c.block_id, c.verdict))
+            .collect();
+        assert_eq!(
+            skipped,
+            vec![(1, Verdict::Blocked), (2, Verdict::Inactive)],
+            "{}",
+            name
+        );
+        assert!(explanation.to_string().contains("outside its time window"));
+    }
+}
+
+#[test]
+fn test_schedule_lists_upcoming_transitions() {
+    let schedule = Schedule::new(&scheduled_amps());
+    assert_eq!(schedule.len(), 3);
+
+    let upcoming: Vec<_> = schedule
+        .upcoming(0)
+        .iter()
+        .map(|t| (t.at, t.kind, t.block_id))
+        .collect();
+    assert_eq!(
+        upcoming,
+        vec![
+            (100, TransitionKind::Start, 1),
+            (150, TransitionKind::Start, 2),
+            (200, TransitionKind::End, 1),
+        ]
+    );
+
+    // A transition at `now` has already happened
+    assert_eq!(schedule.upcoming(100).len(), 2);
+    assert_eq!(schedule.next_change(100), Some(150));
+    assert_eq!(schedule.next_change(199), Some(200));
+    assert_eq!(schedule.next_change(200), None);
+    assert!(schedule.upcoming(200).is_empty());
+}
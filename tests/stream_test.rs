@@ -0,0 +1,86 @@
+use rethink_about_amp::stream::{stream_amp_records, stream_into};
+use rethink_about_amp::{
+    AmpIndexBuilder, AmpIndexer, BTreeAmpIndex, BlartAmpIndex, BuildOptions, HybridAmpIndex,
+    StringPool, build_from_file, load_amp_data, stream_amp_data,
+};
+
+const DESKTOP_DATA: &str = "data/amp-us-desktop.json";
+
+#[test]
+fn test_stream_matches_load() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let mut block_ids = Vec::new();
+    let count = stream_amp_data(DESKTOP_DATA, |amp| {
+        block_ids.push(amp.block_id);
+        Ok(())
+    })
+    .expect("Failed to stream AMP data");
+
+    assert_eq!(count, amps.len());
+    assert_eq!(
+        block_ids,
+        amps.iter().map(|a| a.block_id).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_stream_errors() {
+    // Not an array
+    assert!(stream_amp_records(r#"{"keywords": []}"#.as_bytes(), |_| Ok(())).is_err());
+    // Truncated array
+    assert!(stream_amp_records("[".as_bytes(), |_| Ok(())).is_err());
+    // Trailing garbage
+    assert!(stream_amp_records("[] []".as_bytes(), |_| Ok(())).is_err());
+    // Errors from the callback stop the stream
+    let amps = std::fs::read(DESKTOP_DATA).unwrap();
+    let mut seen = 0;
+    let err = stream_amp_records(amps.as_slice(), |_| {
+        seen += 1;
+        Err("stop".into())
+    })
+    .expect_err("Callback error was swallowed");
+    assert_eq!(seen, 1);
+    assert!(err.to_string().contains("stop"), "{}", err);
+}
+
+fn test_streaming_build_for<T: AmpIndexer>(indexer_name: &str) {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let built = T::build(&amps).expect("Failed to build index");
+
+    let streamed: T = build_from_file(DESKTOP_DATA).expect("Failed to stream index");
+
+    let mut pool = StringPool::new();
+    let mut builder = T::builder_with_pool(&mut pool, &BuildOptions::default());
+    stream_into(DESKTOP_DATA, &mut builder).expect("Failed to stream records");
+    let incremental = builder.finish().expect("Failed to finish index");
+
+    assert_eq!(built.stats(), streamed.stats(), "{}", indexer_name);
+    assert_eq!(built.stats(), incremental.stats(), "{}", indexer_name);
+
+    for amp in amps.iter().step_by(7) {
+        for kw in amp.keywords.iter().step_by(5) {
+            let expected = built.query(kw).expect("Query failed");
+            for index in [&streamed, &incremental] {
+                let results = index.query(kw).expect("Query failed");
+                assert_eq!(results.len(), expected.len(), "{}: {}", indexer_name, kw);
+                assert_eq!(results[0].block_id, expected[0].block_id);
+                assert_eq!(results[0].full_keyword, expected[0].full_keyword);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_btree_streaming_build() {
+    test_streaming_build_for::<BTreeAmpIndex>("BTree");
+}
+
+#[test]
+fn test_hybrid_streaming_build() {
+    test_streaming_build_for::<HybridAmpIndex>("Hybrid");
+}
+
+#[test]
+fn test_blart_streaming_build() {
+    test_streaming_build_for::<BlartAmpIndex>("Blart");
+}
@@ -0,0 +1,128 @@
+use rethink_about_amp::suggestions::{SuggestionTableBuilder, split_url};
+use rethink_about_amp::{
+    OriginalAmp, QueryContext, StringCompression, StringPool, UrlParams, load_amp_data,
+};
+
+const DESKTOP_DATA: &str = "data/amp-us-desktop.json";
+
+#[test]
+fn test_split_url() {
+    assert_eq!(
+        split_url("https://example.com/a/b?id=1"),
+        ("https://example.com/a/b", "?id=1")
+    );
+    assert_eq!(
+        split_url("https://example.com/a/b"),
+        ("https://example.com/a", "/b")
+    );
+    assert_eq!(split_url("no-slash"), ("", "no-slash"));
+}
+
+#[test]
+fn test_table_round_trips_records() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let mut pool = StringPool::new();
+    let mut builder = SuggestionTableBuilder::new();
+    for amp in &amps {
+        builder.push(amp, &mut pool);
+    }
+    let table = builder.finish();
+    assert_eq!(table.len(), amps.len());
+
+    for (idx, amp) in amps.iter().enumerate() {
+        let result = table.result(idx, "kw".to_string(), &UrlParams::default());
+        assert_eq!(result.title, amp.title);
+        assert_eq!(result.url, amp.url);
+        assert_eq!(result.click_url, amp.click_url);
+        assert_eq!(result.impression_url, amp.impression_url);
+        assert_eq!(result.advertiser, amp.advertiser);
+        assert_eq!(result.block_id, amp.block_id);
+        assert_eq!(result.iab_category, amp.iab_category);
+        assert_eq!(result.icon, amp.icon_id);
+        assert_eq!(table.advertiser(idx), amp.advertiser);
+    }
+
+    // The suffixes themselves, plus a few bytes of ids per row instead of the ~100 of
+    // a struct of ids and owned suffixes
+    let suffix_bytes: usize = amps
+        .iter()
+        .flat_map(|amp| [&amp.url, &amp.click_url, &amp.impression_url])
+        .map(|url| split_url(url).1.len())
+        .sum();
+    let overhead = table.size_bytes() - suffix_bytes;
+    assert!(
+        overhead < amps.len() * 16,
+        "{} bytes besides the suffixes for {} rows",
+        overhead,
+        amps.len()
+    );
+}
+
+#[test]
+fn test_compressed_table_round_trips_records() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let build = |compression| {
+        let mut pool = StringPool::new();
+        let mut builder = SuggestionTableBuilder::new().compression(compression);
+        for amp in &amps {
+            builder.push(amp, &mut pool);
+        }
+        builder.finish()
+    };
+    let plain = build(StringCompression::None);
+    let compressed = build(StringCompression::Fsst);
+
+    let params = UrlParams::default();
+    for idx in 0..amps.len() {
+        let expected = plain.result(idx, "kw".to_string(), &params);
+        let result = compressed.result(idx, "kw".to_string(), &params);
+        assert_eq!(result.title, expected.title);
+        assert_eq!(result.url, expected.url);
+        assert_eq!(result.click_url, expected.click_url);
+        assert_eq!(result.impression_url, expected.impression_url);
+        assert_eq!(result.advertiser, expected.advertiser);
+    }
+    // Even with its titles, no longer shared through the pool
+    assert!(
+        compressed.size_bytes() < plain.size_bytes() * 3 / 4,
+        "{} bytes compressed vs. {} plain",
+        compressed.size_bytes(),
+        plain.size_bytes()
+    );
+}
+
+#[test]
+fn test_table_rejections() {
+    let amp = |block_id: i32, advertiser: &str| OriginalAmp {
+        keywords: vec!["kw".to_string()],
+        title: "Title".to_string(),
+        url: "https://example.com/".to_string(),
+        score: None,
+        full_keywords: vec![("kw".to_string(), 1)],
+        advertiser: advertiser.to_string(),
+        block_id,
+        iab_category: "22 - Shopping".to_string(),
+        click_url: "https://click.example.com/".to_string(),
+        impression_url: "https://impression.example.com/".to_string(),
+        icon_id: "1".to_string(),
+        start: Some(100),
+        end: None,
+    };
+    let mut pool = StringPool::new();
+    let mut builder = SuggestionTableBuilder::new().icon_prefix("icon://");
+    builder.push(&amp(-5, "Acme"), &mut pool);
+    builder.push(&amp(7, "Globex"), &mut pool);
+    let table = builder.finish();
+
+    // Negative block ids survive the packing
+    assert_eq!(table.block_id(0), -5);
+    assert_eq!(table.block_id(1), 7);
+    let result = table.result(1, "kw".to_string(), &UrlParams::default());
+    assert_eq!(result.icon, "icon://1");
+
+    assert_eq!(table.rejection(0, &QueryContext::default()), None);
+    let ctx = QueryContext::new().block_advertiser("Globex");
+    assert_eq!(table.rejection(0, &ctx), None);
+    assert!(table.rejection(1, &ctx).is_some());
+    assert!(table.rejection(0, &QueryContext::new().at(50)).is_some());
+}
@@ -0,0 +1,142 @@
+use rethink_about_amp::url_params::render_url;
+use rethink_about_amp::{
+    AmpQuery, BackendRegistry, BuildOptions, FormFactor, OriginalAmp, QueryContext, UrlParam,
+    UrlParams,
+};
+
+fn params(request_id: &str, region: &str, form_factor: FormFactor) -> UrlParams {
+    UrlParams {
+        request_id: Some(request_id.to_string()),
+        region: Some(region.to_string()),
+        form_factor: Some(form_factor),
+    }
+}
+
+#[test]
+fn test_render_url() {
+    let params = params("abc-123", "US-CA", FormFactor::Phone);
+    assert_eq!(
+        render_url(
+            "https://click.example.com/ctp",
+            "?id=1&rid={request_id}&r={region}&ff={form_factor}",
+            &params
+        ),
+        "https://click.example.com/ctp?id=1&rid=abc-123&r=US-CA&ff=phone"
+    );
+    // Placeholders in the template part too
+    assert_eq!(
+        render_url("https://example.com/{region}", "/x", &params),
+        "https://example.com/US-CA/x"
+    );
+    // No placeholder
+    assert_eq!(
+        render_url("https://example.com", "?a=1", &params),
+        "https://example.com?a=1"
+    );
+}
+
+#[test]
+fn test_render_url_encoding() {
+    let params = UrlParams {
+        request_id: Some("a b&c=d/é%20".to_string()),
+        ..UrlParams::default()
+    };
+    // Values are encoded once, the literal parts are left as they are
+    assert_eq!(
+        render_url(
+            "https://example.com/a%20b",
+            "?q=x%26y&rid={request_id}",
+            &params
+        ),
+        "https://example.com/a%20b?q=x%26y&rid=a%20b%26c%3Dd%2F%C3%A9%2520"
+    );
+}
+
+#[test]
+fn test_render_url_edge_cases() {
+    let params = UrlParams::default();
+    // Missing values render empty
+    assert_eq!(
+        render_url("", "?rid={request_id}&ff={form_factor}", &params),
+        "?rid=&ff="
+    );
+    // Braces not naming a placeholder are kept
+    assert_eq!(
+        render_url("", "?a={other}&b={&c=}", &params),
+        "?a={other}&b={&c=}"
+    );
+    assert_eq!(render_url("", "?a={request_id", &params), "?a={request_id");
+    assert_eq!(render_url("", "?a={{region}}", &params), "?a={}");
+}
+
+#[test]
+fn test_url_params_set() {
+    let mut params = UrlParams::default();
+    params.set("request_id", "r1").unwrap();
+    params.set("form_factor", "mobile").unwrap();
+    assert_eq!(params.request_id.as_deref(), Some("r1"));
+    assert_eq!(params.form_factor, Some(FormFactor::Phone));
+    assert!(params.set("form_factor", "tablet").is_err());
+    assert!(params.set("user_id", "1").is_err());
+
+    for param in UrlParam::ALL {
+        assert_eq!(UrlParam::from_name(param.name()), Some(param));
+    }
+    assert_eq!(UrlParam::RequestId.placeholder(), "{request_id}");
+}
+
+fn amp(block_id: i32) -> OriginalAmp {
+    OriginalAmp {
+        keywords: vec!["fo".to_string(), "foo".to_string()],
+        title: format!("Title {}", block_id),
+        url: format!("https://example.com/{}", block_id),
+        score: Some(0.3),
+        full_keywords: vec![("foo".to_string(), 2)],
+        advertiser: "Acme".to_string(),
+        block_id,
+        iab_category: "22 - Shopping".to_string(),
+        click_url: format!(
+            "https://click.example.com/ctp?id={}&rid={{request_id}}&ff={{form_factor}}",
+            block_id
+        ),
+        impression_url: format!(
+            "https://impression.example.com/?id={}&region={{region}}",
+            block_id
+        ),
+        icon_id: "icon".to_string(),
+        start: None,
+        end: None,
+    }
+}
+
+#[test]
+fn test_backends_fill_placeholders() {
+    let amps = vec![amp(1)];
+    let registry = BackendRegistry::with_defaults();
+    for name in registry.names() {
+        let index = registry
+            .build(name, &amps, &BuildOptions::default())
+            .unwrap();
+
+        let ctx = QueryContext::new().with_url_params(params("r 1", "CA", FormFactor::Desktop));
+        let results = index.query_with_context("foo", &ctx).unwrap();
+        assert_eq!(
+            results[0].click_url, "https://click.example.com/ctp?id=1&rid=r%201&ff=desktop",
+            "{}",
+            name
+        );
+        assert_eq!(
+            results[0].impression_url, "https://impression.example.com/?id=1&region=CA",
+            "{}",
+            name
+        );
+        assert_eq!(results[0].url, "https://example.com/1", "{}", name);
+
+        let results = index.query("foo").unwrap();
+        assert_eq!(
+            results[0].click_url, "https://click.example.com/ctp?id=1&rid=&ff=",
+            "{}",
+            name
+        );
+    }
+}
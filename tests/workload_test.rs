@@ -0,0 +1,92 @@
+use rethink_about_amp::workload::{LatencySummary, Workload, WorkloadConfig};
+use rethink_about_amp::{AmpIndexer, AmpQuery, BTreeAmpIndex, load_amp_data};
+use std::time::Duration;
+
+const DESKTOP_DATA: &str = "data/amp-us-desktop.json";
+
+#[test]
+fn test_workload_is_reproducible() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let config = WorkloadConfig {
+        queries: 2_000,
+        ..WorkloadConfig::default()
+    };
+
+    let workload = Workload::generate(&amps, &config);
+    assert_eq!(workload.len(), 2_000);
+    assert_eq!(workload.queries, Workload::generate(&amps, &config).queries);
+
+    let reseeded = Workload::generate(&amps, &WorkloadConfig { seed: 7, ..config });
+    assert_ne!(workload.queries, reseeded.queries);
+}
+
+#[test]
+fn test_workload_types_full_keywords_and_misses() {
+    let amps = load_amp_data(DESKTOP_DATA).expect("Failed to load AMP data");
+    let index = BTreeAmpIndex::build(&amps).expect("Failed to build index");
+    let config = WorkloadConfig {
+        queries: 5_000,
+        miss_share: 0.3,
+        ..WorkloadConfig::default()
+    };
+    let workload = Workload::generate(&amps, &config);
+
+    // About 30% misses. Only they and the keystrokes short of a keyword go unanswered.
+    let share = workload.misses as f64 / workload.len() as f64;
+    assert!((0.25..0.35).contains(&share), "{}", share);
+    let full_keywords: Vec<&str> = amps
+        .iter()
+        .flat_map(|amp| amp.full_keywords.iter().map(|(fk, _)| fk.as_str()))
+        .collect();
+    let (mut unanswered, mut short) = (0, 0);
+    for query in &workload.queries {
+        if index.query(query).unwrap().is_empty() {
+            unanswered += 1;
+            short += usize::from(
+                full_keywords
+                    .iter()
+                    .any(|fk| fk.starts_with(query.as_str())),
+            );
+        }
+    }
+    assert!(short > 0);
+    assert_eq!(unanswered, workload.misses + short);
+
+    // Sessions type one more char per keystroke, starting from one char
+    let first = workload
+        .queries
+        .iter()
+        .find(|query| index.query(query).is_ok_and(|results| !results.is_empty()))
+        .unwrap();
+    assert!(first.chars().count() <= 3, "{:?}", first);
+
+    // Zipf: the most popular full keyword is typed in full many times
+    let without = Workload::generate(
+        &amps,
+        &WorkloadConfig {
+            miss_share: 0.0,
+            ..config
+        },
+    );
+    assert_eq!(without.misses, 0);
+    let mut counts = std::collections::HashMap::new();
+    for query in &without.queries {
+        *counts.entry(query.as_str()).or_insert(0) += 1;
+    }
+    assert!(counts.values().any(|&count| count > 20));
+}
+
+#[test]
+fn test_latency_summary() {
+    let latencies: Vec<Duration> = (1..=100).map(Duration::from_micros).collect();
+    let summary = LatencySummary::from_latencies(latencies);
+    assert_eq!(summary.p50, Duration::from_micros(50));
+    assert_eq!(summary.p99, Duration::from_micros(99));
+    assert_eq!(summary.max, Duration::from_micros(100));
+    assert!((summary.throughput - 100.0 / 0.00505).abs() < 1.0);
+
+    assert_eq!(
+        LatencySummary::from_latencies(Vec::new()).p99,
+        Duration::ZERO
+    );
+}